@@ -58,6 +58,9 @@ fn generate_file(keycodes: KeyCodes) -> syn::File {
                 pub const #idents: KeyCode = KeyCode(#codes);
             )*
 
+            // Named key codes in the order of the header, including aliases
+            pub const NAMED: &'static [(&'static str, KeyCode)] = &[#((#names, KeyCode(#codes)),)*];
+
             pub fn code(&self) -> u16 {
                 self.0
             }
@@ -0,0 +1,818 @@
+use std::env;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use log::debug;
+use thiserror::Error;
+
+use super::{Command, SharedStatus};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No D-Bus address found in {0:?}")]
+    Address(String),
+    #[error("D-Bus authentication failed")]
+    Auth,
+    #[error("Malformed D-Bus message")]
+    Malformed,
+    #[error("D-Bus call {0} failed: {1}")]
+    Call(&'static str, String),
+    #[error("Name {0} is already owned by another process")]
+    NameTaken(&'static str),
+    #[error("D-Bus connection failed")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session" => Ok(Bus::Session),
+            "system" => Ok(Bus::System),
+            _ => Err(format!("Unknown bus {:?}, expected session or system", s)),
+        }
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bus::Session => f.write_str("session"),
+            Bus::System => f.write_str("system"),
+        }
+    }
+}
+
+impl Bus {
+    fn address(&self) -> String {
+        let (var, default) = match self {
+            Bus::Session => ("DBUS_SESSION_BUS_ADDRESS", ""),
+            Bus::System => ("DBUS_SYSTEM_BUS_ADDRESS", "unix:path=/var/run/dbus/system_bus_socket"),
+        };
+        env::var(var).unwrap_or(String::from(default))
+    }
+
+    // Returns the first unix socket of the address, e.g. unix:path=/run/bus
+    fn socket_addr(&self) -> Result<SocketAddr, Error> {
+        let address = self.address();
+        for transport in address.split(';') {
+            let Some(params) = transport.strip_prefix("unix:") else {
+                continue;
+            };
+            for (key, value) in params.split(',').filter_map(|p| p.split_once('=')) {
+                match key {
+                    "path" => return Ok(SocketAddr::from_pathname(value)?),
+                    "abstract" => return Ok(SocketAddr::from_abstract_name(value)?),
+                    _ => continue,
+                }
+            }
+        }
+        Err(Error::Address(address))
+    }
+}
+
+// Maximum message size allowed by the specification
+const MAX_MESSAGE_SIZE: usize = 128 * 1024 * 1024;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+// Subset of D-Bus values used by the service
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Path(String),
+    Signature(String),
+    U32(u32),
+    U64(u64),
+    Dict(Vec<(String, Value)>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    fn signature(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "s",
+            Value::Path(_) => "o",
+            Value::Signature(_) => "g",
+            Value::U32(_) => "u",
+            Value::U64(_) => "t",
+            Value::Dict(_) => "a{sv}",
+            Value::Variant(_) => "v",
+        }
+    }
+}
+
+// Serializes values in little endian. Offsets are relative to the start of
+// the buffer, which has to be 8 byte aligned within the message
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, n: usize) {
+        while !self.buf.len().is_multiple_of(n) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend(v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.buf.push(s.len() as u8);
+        self.buf.extend(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    // Writes the array length and the elements written by the closure
+    fn array(&mut self, element_align: usize, f: impl FnOnce(&mut Self)) {
+        self.u32(0);
+        let len_pos = self.buf.len() - 4;
+        self.align(element_align);
+        let start = self.buf.len();
+        f(self);
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Str(s) | Value::Path(s) => self.string(s),
+            Value::Signature(s) => self.signature(s),
+            Value::U32(v) => self.u32(*v),
+            Value::U64(v) => {
+                self.align(8);
+                self.buf.extend(v.to_le_bytes());
+            }
+            Value::Dict(entries) => self.array(8, |w| {
+                for (key, value) in entries {
+                    w.align(8);
+                    w.string(key);
+                    w.variant(value);
+                }
+            }),
+            Value::Variant(value) => self.variant(value),
+        }
+    }
+
+    fn variant(&mut self, value: &Value) {
+        self.signature(value.signature());
+        self.value(value);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], big_endian: bool) -> Self {
+        Reader {
+            buf,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.next_multiple_of(n);
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.buf.get(self.pos..self.pos + n).ok_or(Error::Malformed)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.align(4);
+        let bytes = self.bytes(4)?.try_into().map_err(|_| Error::Malformed)?;
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()? as usize;
+        let s = self.bytes(len + 1)?;
+        Ok(String::from_utf8_lossy(&s[..len]).into_owned())
+    }
+
+    fn signature(&mut self) -> Result<String, Error> {
+        let len = self.u8()? as usize;
+        let s = self.bytes(len + 1)?;
+        Ok(String::from_utf8_lossy(&s[..len]).into_owned())
+    }
+
+    // Reads a value of a basic type which is used in message headers
+    fn value(&mut self, signature: &str) -> Result<Value, Error> {
+        match signature {
+            "s" => Ok(Value::Str(self.string()?)),
+            "o" => Ok(Value::Path(self.string()?)),
+            "g" => Ok(Value::Signature(self.signature()?)),
+            "u" => Ok(Value::U32(self.u32()?)),
+            _ => Err(Error::Malformed),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Message {
+    fn method_call(destination: &str, path: &str, interface: &str, member: &str) -> Self {
+        Message {
+            kind: METHOD_CALL,
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            destination: Some(destination.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn reply_to(call: &Message, kind: u8) -> Self {
+        Message {
+            kind,
+            reply_serial: Some(call.serial),
+            destination: call.sender.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn with_body(mut self, args: &[Value]) -> Self {
+        let mut w = Writer::default();
+        for arg in args {
+            w.value(arg);
+        }
+        self.signature = args.iter().map(Value::signature).collect();
+        self.body = w.buf;
+        self
+    }
+
+    // Returns the string arguments of the body
+    fn strings(&self) -> Result<Vec<String>, Error> {
+        let mut r = Reader::new(&self.body, self.big_endian);
+        self.signature
+            .chars()
+            .map(|c| match c {
+                's' | 'o' => r.string(),
+                _ => Err(Error::Malformed),
+            })
+            .collect()
+    }
+
+    fn encode(&self, serial: u32) -> Vec<u8> {
+        let mut fields = Vec::new();
+        let strings = [
+            (FIELD_PATH, self.path.clone().map(Value::Path)),
+            (FIELD_INTERFACE, self.interface.clone().map(Value::Str)),
+            (FIELD_MEMBER, self.member.clone().map(Value::Str)),
+            (FIELD_ERROR_NAME, self.error_name.clone().map(Value::Str)),
+            (FIELD_REPLY_SERIAL, self.reply_serial.map(Value::U32)),
+            (FIELD_DESTINATION, self.destination.clone().map(Value::Str)),
+        ];
+        for (code, value) in strings {
+            fields.extend(value.map(|v| (code, v)));
+        }
+        if !self.signature.is_empty() {
+            fields.push((FIELD_SIGNATURE, Value::Signature(self.signature.clone())));
+        }
+
+        let mut w = Writer::default();
+        w.buf.extend([b'l', self.kind, self.flags, 1]);
+        w.u32(self.body.len() as u32);
+        w.u32(serial);
+        w.array(8, |w| {
+            for (code, value) in &fields {
+                w.align(8);
+                w.buf.push(*code);
+                w.variant(value);
+            }
+        });
+        w.align(8);
+        w.buf.extend(&self.body);
+        w.buf
+    }
+
+    fn read(stream: &mut UnixStream) -> Result<Self, Error> {
+        let mut fixed = [0u8; 16];
+        stream.read_exact(&mut fixed)?;
+        let big_endian = match fixed[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => return Err(Error::Malformed),
+        };
+        let mut r = Reader::new(&fixed, big_endian);
+        r.pos = 4;
+        let body_len = r.u32()? as usize;
+        let serial = r.u32()?;
+        let fields_len = r.u32()? as usize;
+        if fields_len.saturating_add(body_len) > MAX_MESSAGE_SIZE {
+            return Err(Error::Malformed);
+        }
+
+        // the header is padded to 8 bytes
+        let mut rest = vec![0u8; fields_len.next_multiple_of(8) + body_len];
+        stream.read_exact(&mut rest)?;
+
+        let mut msg = Message {
+            kind: fixed[1],
+            flags: fixed[2],
+            serial,
+            big_endian,
+            ..Default::default()
+        };
+
+        // field offsets are relative to the start of the message
+        let mut header = fixed.to_vec();
+        header.extend(&rest[..fields_len]);
+        let mut r = Reader::new(&header, big_endian);
+        r.pos = 16;
+        while r.pos < header.len() {
+            r.align(8);
+            let code = r.u8()?;
+            let signature = r.signature()?;
+            let value = r.value(&signature)?;
+            match (code, value) {
+                (FIELD_PATH, Value::Path(v)) => msg.path = Some(v),
+                (FIELD_INTERFACE, Value::Str(v)) => msg.interface = Some(v),
+                (FIELD_MEMBER, Value::Str(v)) => msg.member = Some(v),
+                (FIELD_ERROR_NAME, Value::Str(v)) => msg.error_name = Some(v),
+                (FIELD_REPLY_SERIAL, Value::U32(v)) => msg.reply_serial = Some(v),
+                (FIELD_DESTINATION, Value::Str(v)) => msg.destination = Some(v),
+                (FIELD_SENDER, Value::Str(v)) => msg.sender = Some(v),
+                (FIELD_SIGNATURE, Value::Signature(v)) => msg.signature = v,
+                _ => {}
+            }
+        }
+
+        msg.body = rest[fields_len.next_multiple_of(8)..].to_vec();
+        Ok(msg)
+    }
+}
+
+const NAME: &str = "org.oikos.Dashboard";
+const PATH: &str = "/org/oikos/Dashboard";
+const INTERFACE: &str = "org.oikos.Dashboard";
+
+// Reply arguments and the requested command, or the error name and message
+type Reply = Result<(Vec<Value>, Option<Command>), (&'static str, String)>;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.oikos.Dashboard">
+    <method name="Refresh"/>
+    <method name="SetTemplate">
+      <arg name="path" type="s" direction="in"/>
+    </method>
+    <property name="Template" type="s" access="read"/>
+    <property name="LastRender" type="t" access="read"/>
+    <property name="LastError" type="s" access="read"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+// D-Bus service named org.oikos.Dashboard to control the dashboard while it
+// is sleeping. The object /org/oikos/Dashboard provides the methods Refresh()
+// and SetTemplate(path), as well as the properties Template, LastRender (in
+// seconds since the epoch, or 0), and LastError (empty if none)
+//
+// The wire protocol is implemented here, as the few messages needed do not
+// justify an asynchronous runtime and the dependencies of zbus on devices
+// with little storage.
+pub struct Service {
+    bus: Bus,
+    stream: Option<UnixStream>,
+    serial: u32,
+    status: SharedStatus,
+}
+
+impl Service {
+    // Timeout for the bus to answer, and for the rest of a started message
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn connect(bus: Bus, status: SharedStatus) -> Result<Self, Error> {
+        let mut service = Service {
+            bus,
+            stream: None,
+            serial: 0,
+            status,
+        };
+        service.reconnect()?;
+        Ok(service)
+    }
+
+    // File descriptor of the connection, if connected
+    pub fn fd(&self) -> Option<RawFd> {
+        self.stream.as_ref().map(|s| s.as_raw_fd())
+    }
+
+    // Connects and acquires the name again after losing the connection, e.g.
+    // because the bus has been restarted
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        let stream = UnixStream::connect_addr(&self.bus.socket_addr()?)?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+        self.stream = Some(stream);
+        self.serial = 0;
+
+        let result = self.register();
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    fn register(&mut self) -> Result<(), Error> {
+        self.authenticate()?;
+        self.call("Hello", &[])?;
+
+        let reply = self.call("RequestName", &[Value::Str(NAME.into()), Value::U32(0x4)])?;
+        // 1 is the primary owner, 4 is already the owner
+        let mut r = Reader::new(&reply.body, reply.big_endian);
+        match r.u32()? {
+            1 | 4 => debug!("Acquired D-Bus name {} on the {} bus", NAME, self.bus),
+            _ => return Err(Error::NameTaken(NAME)),
+        }
+        Ok(())
+    }
+
+    fn stream(&mut self) -> Result<&mut UnixStream, Error> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected).into())
+    }
+
+    fn authenticate(&mut self) -> Result<(), Error> {
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex = uid.bytes().map(|b| format!("{:02x}", b)).collect::<String>();
+        let stream = self.stream()?;
+        stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+
+        // read byte by byte, as nothing may be read past the line
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        while !line.ends_with(b"\r\n") {
+            stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        if !line.starts_with(b"OK ") {
+            return Err(Error::Auth);
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+        Ok(())
+    }
+
+    fn send(&mut self, msg: &Message) -> Result<u32, Error> {
+        self.serial += 1;
+        let data = msg.encode(self.serial);
+        self.stream()?.write_all(&data)?;
+        Ok(self.serial)
+    }
+
+    // Calls a method of the bus itself and waits for the reply
+    fn call(&mut self, member: &'static str, args: &[Value]) -> Result<Message, Error> {
+        let call = Message::method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            member,
+        )
+        .with_body(args);
+        let serial = self.send(&call)?;
+        loop {
+            let msg = Message::read(self.stream()?)?;
+            match msg.kind {
+                METHOD_RETURN if msg.reply_serial == Some(serial) => return Ok(msg),
+                ERROR if msg.reply_serial == Some(serial) => {
+                    return Err(Error::Call(member, msg.error_name.unwrap_or_default()))
+                }
+                // e.g. the NameAcquired signal
+                _ => continue,
+            }
+        }
+    }
+
+    // Answers all pending method calls and returns the last requested command,
+    // if any. Failing calls are logged, as they must not stop the dashboard.
+    // The connection is closed on errors, as the stream cannot be resumed
+    // after an incomplete or malformed message.
+    pub fn handle(&mut self) -> Result<Option<Command>, Error> {
+        let result = self.handle_pending();
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    fn handle_pending(&mut self) -> Result<Option<Command>, Error> {
+        let mut command = None;
+        while self.pending()? {
+            let msg = Message::read(self.stream()?)?;
+            if msg.kind != METHOD_CALL {
+                continue;
+            }
+
+            let (reply, cmd) = match self.dispatch(&msg) {
+                Ok((args, cmd)) => (Message::reply_to(&msg, METHOD_RETURN).with_body(&args), cmd),
+                Err((name, text)) => {
+                    debug!("Failed D-Bus call {:?}: {}", msg.member.as_deref().unwrap_or(""), text);
+                    let mut reply = Message::reply_to(&msg, ERROR).with_body(&[Value::Str(text)]);
+                    reply.error_name = Some(name.to_string());
+                    (reply, None)
+                }
+            };
+            if msg.flags & NO_REPLY_EXPECTED == 0 {
+                self.send(&reply)?;
+            }
+            command = cmd.or(command);
+        }
+        Ok(command)
+    }
+
+    // Returns true if data can be read without blocking
+    fn pending(&mut self) -> Result<bool, Error> {
+        let fd = self.stream()?.as_raw_fd();
+        let mut byte = 0u8;
+        let flags = libc::MSG_PEEK | libc::MSG_DONTWAIT;
+        let n = unsafe { libc::recv(fd, (&mut byte as *mut u8).cast(), 1, flags) };
+        match n {
+            0 => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            n if n > 0 => Ok(true),
+            _ => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+                err => Err(err.into()),
+            },
+        }
+    }
+
+    fn dispatch(&self, msg: &Message) -> Reply {
+        const UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+        const INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+        if msg.path.as_deref() != Some(PATH) {
+            return Err((
+                "org.freedesktop.DBus.Error.UnknownObject",
+                String::from("Unknown object"),
+            ));
+        }
+        let args = msg
+            .strings()
+            .map_err(|_| (INVALID_ARGS, String::from("Unsupported arguments")))?;
+        let member = msg.member.as_deref().unwrap_or("");
+        let interface = msg.interface.as_deref();
+
+        match (interface, member, &args[..]) {
+            (Some(INTERFACE) | None, "Refresh", []) => Ok((vec![], Some(Command::Refresh))),
+            (Some(INTERFACE) | None, "SetTemplate", [path]) if !path.is_empty() => {
+                Ok((vec![], Some(Command::SetTemplate(PathBuf::from(path)))))
+            }
+            (Some("org.freedesktop.DBus.Properties") | None, "Get", [iface, name]) if iface == INTERFACE => {
+                let (_, value) = self
+                    .properties()
+                    .into_iter()
+                    .find(|(n, _)| n == name)
+                    .ok_or((INVALID_ARGS, format!("Unknown property {:?}", name)))?;
+                Ok((vec![Value::Variant(Box::new(value))], None))
+            }
+            (Some("org.freedesktop.DBus.Properties") | None, "GetAll", [iface]) if iface == INTERFACE => {
+                Ok((vec![Value::Dict(self.properties())], None))
+            }
+            (Some("org.freedesktop.DBus.Introspectable") | None, "Introspect", []) => {
+                Ok((vec![Value::Str(INTROSPECTION.into())], None))
+            }
+            (Some("org.freedesktop.DBus.Peer") | None, "Ping", []) => Ok((vec![], None)),
+            _ => Err((UNKNOWN_METHOD, format!("Unknown method {:?}", member))),
+        }
+    }
+
+    fn properties(&self) -> Vec<(String, Value)> {
+        let status = self.status.borrow();
+        let last_render = status
+            .last_render
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        vec![
+            (
+                String::from("Template"),
+                Value::Str(status.template.to_string_lossy().into()),
+            ),
+            (String::from("LastRender"), Value::U64(last_render)),
+            (
+                String::from("LastError"),
+                Value::Str(status.last_error.clone().unwrap_or_default()),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_reader_round_trip() {
+        let mut w = Writer::default();
+        w.buf.push(7);
+        w.u32(0xdead_beef);
+        w.string("Dashboard");
+        w.signature("a{sv}");
+        w.value(&Value::Path(PATH.into()));
+
+        let mut r = Reader::new(&w.buf, false);
+        assert_eq!(r.u8().unwrap(), 7);
+        assert_eq!(r.u32().unwrap(), 0xdead_beef);
+        assert_eq!(r.pos, 8);
+        assert_eq!(r.string().unwrap(), "Dashboard");
+        assert_eq!(r.signature().unwrap(), "a{sv}");
+        assert_eq!(r.value("o").unwrap(), Value::Path(PATH.into()));
+        assert_eq!(r.pos, w.buf.len());
+        assert!(r.u8().is_err());
+    }
+
+    #[test]
+    fn writer_dict() {
+        let mut w = Writer::default();
+        w.value(&Value::Dict(vec![
+            ("LastRender".into(), Value::U64(1)),
+            ("LastError".into(), Value::Str("".into())),
+        ]));
+
+        let mut r = Reader::new(&w.buf, false);
+        let len = r.u32().unwrap() as usize;
+        // entries are 8 byte aligned, the length excludes the padding
+        r.align(8);
+        assert_eq!(r.pos + len, w.buf.len());
+        assert_eq!(r.string().unwrap(), "LastRender");
+        assert_eq!(r.signature().unwrap(), "t");
+        r.align(8);
+        assert_eq!(r.bytes(8).unwrap(), 1u64.to_le_bytes());
+        r.align(8);
+        assert_eq!(r.string().unwrap(), "LastError");
+        assert_eq!(r.signature().unwrap(), "s");
+        assert_eq!(r.string().unwrap(), "");
+        assert_eq!(r.pos, w.buf.len());
+    }
+
+    #[test]
+    fn reader_big_endian() {
+        let buf = [0, 0, 0, 3, b'a', b'b', b'c', 0];
+        let mut r = Reader::new(&buf, true);
+        assert_eq!(r.string().unwrap(), "abc");
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        let call = Message::method_call(NAME, PATH, INTERFACE, "SetTemplate")
+            .with_body(&[Value::Str("/tmp/a.svg".into()), Value::Path(PATH.into())]);
+        a.write_all(&call.encode(42)).unwrap();
+
+        let msg = Message::read(&mut b).unwrap();
+        assert_eq!(msg.kind, METHOD_CALL);
+        assert_eq!(msg.serial, 42);
+        assert_eq!(msg.path.as_deref(), Some(PATH));
+        assert_eq!(msg.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(msg.member.as_deref(), Some("SetTemplate"));
+        assert_eq!(msg.destination.as_deref(), Some(NAME));
+        assert_eq!(msg.signature, "so");
+        assert_eq!(msg.strings().unwrap(), ["/tmp/a.svg", PATH]);
+
+        let mut reply = Message::reply_to(&msg, ERROR);
+        reply.error_name = Some("org.oikos.Error".into());
+        a.write_all(&reply.encode(43)).unwrap();
+        let msg = Message::read(&mut b).unwrap();
+        assert_eq!(msg.kind, ERROR);
+        assert_eq!(msg.reply_serial, Some(42));
+        assert_eq!(msg.error_name.as_deref(), Some("org.oikos.Error"));
+        assert!(msg.body.is_empty());
+    }
+
+    #[test]
+    fn message_big_endian() {
+        // Refresh() call with the path and member fields in big endian
+        let mut data = vec![b'B', METHOD_CALL, 0, 1];
+        data.extend(0u32.to_be_bytes());
+        data.extend(7u32.to_be_bytes());
+        let mut fields = vec![FIELD_PATH, 1, b'o', 0];
+        fields.extend((PATH.len() as u32).to_be_bytes());
+        fields.extend(PATH.as_bytes());
+        fields.push(0);
+        while fields.len() % 8 != 0 {
+            fields.push(0);
+        }
+        fields.extend([FIELD_MEMBER, 1, b's', 0]);
+        fields.extend(7u32.to_be_bytes());
+        fields.extend(b"Refresh\0");
+        data.extend((fields.len() as u32).to_be_bytes());
+        data.extend(&fields);
+        while data.len() % 8 != 0 {
+            data.push(0);
+        }
+
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        a.write_all(&data).unwrap();
+        let msg = Message::read(&mut b).unwrap();
+        assert!(msg.big_endian);
+        assert_eq!(msg.serial, 7);
+        assert_eq!(msg.path.as_deref(), Some(PATH));
+        assert_eq!(msg.member.as_deref(), Some("Refresh"));
+    }
+
+    #[test]
+    fn message_malformed() {
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        let mut data = Message::method_call(NAME, PATH, INTERFACE, "Refresh").encode(1);
+        data[0] = b'x';
+        a.write_all(&data).unwrap();
+        assert!(matches!(Message::read(&mut b), Err(Error::Malformed)));
+
+        // lengths are checked before allocating
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        let mut data = vec![b'l', METHOD_CALL, 0, 1];
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        a.write_all(&data).unwrap();
+        assert!(matches!(Message::read(&mut b), Err(Error::Malformed)));
+    }
+}
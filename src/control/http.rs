@@ -0,0 +1,192 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use percent_encoding::percent_decode_str;
+
+use super::{Command, SharedStatus};
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: &'static str, body: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", body).into_bytes(),
+        }
+    }
+
+    fn write_to(&self, mut stream: &TcpStream) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        stream.write_all(&self.body)
+    }
+}
+
+// Reads from the stream until the deadline, no matter how slowly the client
+// sends its request
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request not received in time"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+// Decodes %XX escapes and '+' in a query string value
+fn percent_decode(s: &str) -> String {
+    percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+// Minimal HTTP server to control the dashboard while it is sleeping:
+//
+//   GET  /status              status as JSON
+//   GET  /image.png           bitmap currently shown on screen
+//   POST /refresh             refresh immediately
+//   POST /template?path=FILE  switch to another template, which must be
+//                             given on the command line or be inside the
+//                             resources directory
+pub struct Server {
+    listener: TcpListener,
+    status: SharedStatus,
+}
+
+impl Server {
+    // Clients have to send their request within this time
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    // Request line and headers together must not be larger than this
+    const MAX_REQUEST_SIZE: u64 = 8192;
+
+    pub fn bind(addr: SocketAddr, status: SharedStatus) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        debug!("Listening for HTTP requests on {}", addr);
+        Ok(Server { listener, status })
+    }
+
+    // Answers all pending requests and returns the last requested command, if
+    // any. Failing requests are logged, as they must not stop the dashboard
+    pub fn handle(&self) -> Option<Command> {
+        let mut command = None;
+        loop {
+            let (stream, peer) = match self.listener.accept() {
+                Ok(conn) => conn,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return command,
+                Err(err) => {
+                    warn!("Failed to accept HTTP connection: {}", err);
+                    return command;
+                }
+            };
+            match self.serve(&stream) {
+                Ok(cmd) => command = cmd.or(command),
+                Err(err) => warn!("Failed to handle HTTP request from {}: {}", peer, err),
+            }
+        }
+    }
+
+    fn serve(&self, stream: &TcpStream) -> io::Result<Option<Command>> {
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+
+        let deadline = Instant::now() + Self::TIMEOUT;
+        let mut reader = BufReader::new(Deadline { stream, deadline }.take(Self::MAX_REQUEST_SIZE));
+        let mut read_line = |line: &mut String| {
+            let n = reader.read_line(line)?;
+            match !line.ends_with('\n') && reader.get_ref().limit() == 0 {
+                true => Err(io::Error::new(io::ErrorKind::InvalidData, "request too large")),
+                false => Ok(n),
+            }
+        };
+        let mut request = String::new();
+        read_line(&mut request)?;
+        // headers are not needed, but have to be read before responding
+        let mut header = String::new();
+        while read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        debug!("HTTP request: {} {}", method, path);
+
+        let (response, command) = self.route(method, path, query);
+        response.write_to(stream)?;
+        Ok(command)
+    }
+
+    fn route(&self, method: &str, path: &str, query: &str) -> (Response, Option<Command>) {
+        let status = self.status.borrow();
+        match (method, path) {
+            ("GET", "/status") => {
+                let response = Response {
+                    status: "200 OK",
+                    content_type: "application/json",
+                    body: status.to_json().into_bytes(),
+                };
+                (response, None)
+            }
+            ("GET", "/image.png") => match status.image.as_ref().map(|image| image.encode_png()) {
+                Some(Ok(png)) => {
+                    let response = Response {
+                        status: "200 OK",
+                        content_type: "image/png",
+                        body: png,
+                    };
+                    (response, None)
+                }
+                Some(Err(err)) => (Response::text("500 Internal Server Error", &err.to_string()), None),
+                None => (Response::text("404 Not Found", "Nothing rendered yet"), None),
+            },
+            ("POST", "/refresh") => (Response::text("202 Accepted", "Refreshing"), Some(Command::Refresh)),
+            ("POST", "/template") => match query_param(query, "path").filter(|p| !p.is_empty()) {
+                Some(path) => (
+                    Response::text("202 Accepted", "Switching template"),
+                    Some(Command::SetTemplate(PathBuf::from(path))),
+                ),
+                None => (Response::text("400 Bad Request", "Missing path parameter"), None),
+            },
+            (_, "/status" | "/image.png" | "/refresh" | "/template") => {
+                (Response::text("405 Method Not Allowed", "Method not allowed"), None)
+            }
+            _ => (Response::text("404 Not Found", "Not found"), None),
+        }
+    }
+}
+
+impl AsRawFd for Server {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
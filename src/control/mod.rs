@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use tiny_skia::Pixmap;
+
+use crate::exit::json_string;
+
+pub use self::dbus::{Bus as DbusBus, Service as DbusService};
+pub use self::http::Server as HttpServer;
+pub use self::socket::Server as SocketServer;
+
+mod dbus;
+mod http;
+mod socket;
+
+// Request received from a remote control interface
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Refresh,
+    SetTemplate(PathBuf),
+    Pause,
+    Resume,
+}
+
+// State of the dashboard reported to remote control interfaces
+#[derive(Debug, Default)]
+pub struct Status {
+    pub template: PathBuf,
+    pub last_render: Option<SystemTime>,
+    pub last_error: Option<String>,
+    // periodic refreshes are paused
+    pub paused: bool,
+    // the bitmap currently shown on screen
+    pub image: Option<Pixmap>,
+}
+
+// Status shared between the main loop and the control interfaces
+pub type SharedStatus = Rc<RefCell<Status>>;
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        let or_null = |s: Option<String>| s.map(|s| json_string(&s)).unwrap_or(String::from("null"));
+        let last_render = self
+            .last_render
+            .map(|t| humantime::format_rfc3339_seconds(t).to_string());
+
+        format!(
+            "{{\"template\":{},\"last_render\":{},\"last_error\":{},\"paused\":{}}}",
+            json_string(&self.template.to_string_lossy()),
+            or_null(last_render),
+            or_null(self.last_error.clone()),
+            self.paused,
+        )
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use super::{Command, SharedStatus};
+
+// Line based control socket for devices without D-Bus. Each connection sends
+// a single command and receives a single line in response:
+//
+//   refresh  refresh immediately
+//   pause    stop refreshing periodically
+//   resume   refresh and continue periodically
+//   status   status as JSON
+//
+// Only the owner may connect, as the socket is created with mode 0600.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    status: SharedStatus,
+}
+
+impl Server {
+    // Clients have to send their command within this time
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn bind(path: &Path, status: SharedStatus) -> io::Result<Self> {
+        // a socket left behind by a previous run cannot be bound again, but
+        // other files and sockets still in use are never removed
+        match fs::symlink_metadata(path) {
+            Ok(meta) if !meta.file_type().is_socket() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "path exists and is not a socket",
+                ));
+            }
+            Ok(_) if UnixStream::connect(path).is_ok() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "socket is in use by another process",
+                ));
+            }
+            Ok(_) => fs::remove_file(path)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        debug!("Listening for commands on {:?}", path);
+        Ok(Server {
+            listener,
+            path: path.to_path_buf(),
+            status,
+        })
+    }
+
+    // Answers all pending connections and returns the last requested command,
+    // if any. Failing connections are logged, as they must not stop the dashboard
+    pub fn handle(&self) -> Option<Command> {
+        let mut command = None;
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return command,
+                Err(err) => {
+                    warn!("Failed to accept control connection: {}", err);
+                    return command;
+                }
+            };
+            match self.serve(&stream) {
+                Ok(cmd) => command = cmd.or(command),
+                Err(err) => warn!("Failed to handle control command: {}", err),
+            }
+        }
+    }
+
+    fn serve(&self, mut stream: &UnixStream) -> io::Result<Option<Command>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        debug!("Control command: {}", line.trim());
+
+        let (response, command) = match line.trim() {
+            "refresh" => (String::from("ok"), Some(Command::Refresh)),
+            "pause" => (String::from("ok"), Some(Command::Pause)),
+            "resume" => (String::from("ok"), Some(Command::Resume)),
+            "status" => (self.status.borrow().to_json(), None),
+            other => (format!("error: unknown command {:?}", other), None),
+        };
+        writeln!(stream, "{}", response)?;
+        Ok(command)
+    }
+}
+
+impl AsRawFd for Server {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if fs::symlink_metadata(&self.path).is_ok_and(|m| m.file_type().is_socket()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use log::{debug, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{self, ForkResult, Pid};
+
+// Process ID file, which is removed again once the dashboard exits normally
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        write_pid(path, unistd::getpid())?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        remove_pid_file(&self.path);
+    }
+}
+
+// Removes the PID file, e.g. before exiting without unwinding
+pub fn remove_pid_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        warn!("Failed to remove PID file {:?}: {}", path, err);
+    }
+}
+
+fn write_pid(path: &Path, pid: Pid) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", pid)
+}
+
+// Detaches the process from its controlling terminal by forking twice, like
+// classic init scripts expect. The original process only exits once the PID
+// file has been written, so that failures are still reported on the terminal.
+// Standard input and output are redirected to /dev/null, log messages are
+// thus only kept if a log file is configured.
+//
+// The working directory is kept, as relative paths from the command line are
+// resolved lazily. This must be called before any threads are spawned.
+pub fn daemonize(pid_file: Option<&Path>) -> io::Result<Option<PidFile>> {
+    let dev_null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+
+    // the parent waits for the intermediate child to report the outcome
+    if let ForkResult::Parent { child } = unsafe { unistd::fork() }? {
+        let code = match waitpid(child, None)? {
+            WaitStatus::Exited(_, code) => code,
+            _ => 1,
+        };
+        process::exit(code);
+    }
+
+    // the intermediate child becomes session leader to lose the terminal,
+    // the daemon itself is forked off so it can never acquire a new one
+    let daemon = unistd::setsid().and_then(|_| unsafe { unistd::fork() });
+    match daemon {
+        Ok(ForkResult::Parent { child }) => {
+            if let Some(path) = pid_file {
+                if let Err(err) = write_pid(path, child) {
+                    eprintln!("Error: Failed to write PID file {:?}: {}", path.to_string_lossy(), err);
+                    let _ = kill(child, Signal::SIGTERM);
+                    process::exit(1);
+                }
+            }
+            process::exit(0);
+        }
+        Ok(ForkResult::Child) => (),
+        Err(err) => {
+            eprintln!("Error: Failed to detach from terminal: {}", err);
+            process::exit(1);
+        }
+    }
+
+    for fd in [
+        io::stdin().as_raw_fd(),
+        io::stdout().as_raw_fd(),
+        io::stderr().as_raw_fd(),
+    ] {
+        unistd::dup2(dev_null.as_raw_fd(), fd)?;
+    }
+    debug!("Running as daemon with PID {}", unistd::getpid());
+
+    // the file has been written by the intermediate child
+    Ok(pid_file.map(|path| PidFile {
+        path: path.to_path_buf(),
+    }))
+}
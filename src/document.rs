@@ -24,6 +24,20 @@ pub enum Error {
     ElementNotSupported(String),
     #[error("Operation can only be performed on singelton selection")]
     SingletonRequired,
+    #[error("Operation not supported on the document root")]
+    RootNotSupported,
+    #[error("Invalid selector: `{0}`")]
+    InvalidSelector(String),
+    #[error("Invalid color: `{0}`")]
+    InvalidColor(String),
+    #[error("Unknown theme: `{0}`")]
+    UnknownTheme(String),
+    #[error("Invalid or missing numeric attribute: `{0}`")]
+    InvalidAttribute(String),
+    #[error("No sibling with class `track` found")]
+    TrackNotFound,
+    #[error("Failed to encode QR code: {0}")]
+    QrCode(#[from] qrcode::types::QrError),
 }
 
 #[derive(Clone, Debug)]
@@ -46,83 +60,503 @@ impl Path {
         Ok(Path { path, target })
     }
 
-    fn resolve_in<'a>(&self, root: &'a Element) -> Result<&'a Element, Error> {
-        let mut node = root;
+    // Returns the current index path of the target element. If the element is
+    // no longer found at its original position (e.g. because a preceding sibling
+    // has been removed), the tree is searched for the target id instead.
+    fn locate(&self, root: &Element) -> Result<Vec<usize>, Error> {
+        let mut node = Some(root);
         for idx in &self.path {
-            node = node.get_child(*idx).ok_or(Error::SelectionInvalidated)?;
+            node = node.and_then(|n| n.get_child(*idx));
+        }
+
+        match node.map(|n| n.get_attr("id")) {
+            Some(Some(target)) if target == self.target => Ok(self.path.clone()),
+            Some(None) => Err(Error::UnlabeledNode),
+            _ => find_path(root, &self.target).ok_or(Error::SelectionInvalidated),
         }
+    }
 
-        match node.get_attr("id") {
-            Some(target) if target == self.target => Ok(node),
-            Some(_) => Err(Error::SelectionInvalidated),
-            None => Err(Error::UnlabeledNode),
+    fn resolve_in<'a>(&self, root: &'a Element) -> Result<&'a Element, Error> {
+        let mut node = root;
+        for idx in self.locate(root)? {
+            node = node.get_child(idx).ok_or(Error::SelectionInvalidated)?;
         }
+        Ok(node)
     }
 
     fn resolve_in_mut<'a>(&self, root: &'a mut Element) -> Result<&'a mut Element, Error> {
         let mut node = root;
-        for idx in &self.path {
-            node = node.get_child_mut(*idx).ok_or(Error::SelectionInvalidated)?;
+        for idx in self.locate(node)? {
+            node = node.get_child_mut(idx).ok_or(Error::SelectionInvalidated)?;
+        }
+        Ok(node)
+    }
+}
+
+// Searches the tree for the element with the given id and returns its index path
+fn find_path(root: &Element, id: &str) -> Option<Vec<usize>> {
+    let mut stack = vec![(root, vec![])];
+    while let Some((node, path)) = stack.pop() {
+        if node.get_attr("id") == Some(id) {
+            return Some(path);
+        }
+
+        for (idx, child) in node.children().enumerate() {
+            let mut path = path.clone();
+            path.push(idx);
+            stack.push((child, path));
+        }
+    }
+    None
+}
+
+// A CSS selector supporting type, class, id and attribute selectors, combined
+// with descendant and child combinators. Alternatives are separated by commas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    alternatives: Vec<ComplexSelector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ComplexSelector {
+    // combinators[i] relates parts[i] to parts[i + 1]
+    parts: Vec<CompoundSelector>,
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct CompoundSelector {
+    tag: Option<String>,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Id(String),
+    Class(String),
+    Attr(String, Option<(AttrOp, String)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttrOp {
+    Equals,
+    Includes,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+impl Selector {
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        let mut parser = SelectorParser {
+            input,
+            chars: input.chars().collect(),
+            pos: 0,
+        };
+
+        let mut alternatives = vec![parser.complex()?];
+        while parser.peek() == Some(',') {
+            parser.pos += 1;
+            alternatives.push(parser.complex()?);
+        }
+
+        match parser.peek() {
+            None => Ok(Selector { alternatives }),
+            Some(_) => Err(parser.error()),
+        }
+    }
+
+    pub fn matches(&self, elem: &Element, ancestors: &[&Element]) -> bool {
+        self.alternatives.iter().any(|complex| {
+            let (last, parts) = complex.parts.split_last().expect("selector is not empty");
+            last.matches(elem) && match_ancestors(parts, &complex.combinators, ancestors)
+        })
+    }
+}
+
+// Matches the remaining compound selectors (right to left) against the ancestors
+fn match_ancestors(parts: &[CompoundSelector], combinators: &[Combinator], ancestors: &[&Element]) -> bool {
+    let (Some((part, parts)), Some((combinator, combinators))) = (parts.split_last(), combinators.split_last()) else {
+        return true;
+    };
+
+    match combinator {
+        Combinator::Child => match ancestors.split_last() {
+            Some((parent, ancestors)) => part.matches(parent) && match_ancestors(parts, combinators, ancestors),
+            None => false,
+        },
+        Combinator::Descendant => (0..ancestors.len())
+            .rev()
+            .any(|i| part.matches(ancestors[i]) && match_ancestors(parts, combinators, &ancestors[..i])),
+    }
+}
+
+impl CompoundSelector {
+    fn matches(&self, elem: &Element) -> bool {
+        if let Some(tag) = &self.tag {
+            if elem.tag().name() != tag {
+                return false;
+            }
+        }
+
+        self.conditions.iter().all(|cond| match cond {
+            Condition::Id(id) => elem.get_attr("id") == Some(id),
+            Condition::Class(class) => elem
+                .get_attr("class")
+                .map(|c| c.split_whitespace().any(|c| c == class))
+                .unwrap_or(false),
+            Condition::Attr(key, None) => elem.get_attr(key.as_str()).is_some(),
+            Condition::Attr(key, Some((op, value))) => match elem.get_attr(key.as_str()) {
+                Some(attr) => match op {
+                    AttrOp::Equals => attr == value,
+                    AttrOp::Includes => attr.split_whitespace().any(|a| a == value),
+                    AttrOp::Prefix => attr.starts_with(value.as_str()),
+                    AttrOp::Suffix => attr.ends_with(value.as_str()),
+                    AttrOp::Substring => attr.contains(value.as_str()),
+                },
+                None => false,
+            },
+        })
+    }
+}
+
+struct SelectorParser<'a> {
+    input: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl SelectorParser<'_> {
+    fn error(&self) -> Error {
+        Error::InvalidSelector(self.input.to_string())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        match self.peek() {
+            Some(next) if next == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    // Skips whitespace and returns true if any was found
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().map(char::is_whitespace).unwrap_or(false) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn ident(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+        while self
+            .peek()
+            .map(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            .unwrap_or(false)
+        {
+            self.pos += 1;
         }
 
-        match node.get_attr("id") {
-            Some(target) if target == self.target => Ok(node),
-            Some(_) => Err(Error::SelectionInvalidated),
-            None => Err(Error::UnlabeledNode),
+        if self.pos == start {
+            return Err(self.error());
         }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn complex(&mut self) -> Result<ComplexSelector, Error> {
+        self.skip_whitespace();
+        let mut parts = vec![self.compound()?];
+        let mut combinators = vec![];
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                None | Some(',') => break,
+                Some('>') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                    Combinator::Child
+                }
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(_) => return Err(self.error()),
+            };
+            combinators.push(combinator);
+            parts.push(self.compound()?);
+        }
+
+        Ok(ComplexSelector { parts, combinators })
+    }
+
+    fn compound(&mut self) -> Result<CompoundSelector, Error> {
+        let mut compound = CompoundSelector::default();
+        let universal = self.peek() == Some('*');
+        if universal {
+            self.pos += 1;
+        } else if self.peek().map(char::is_alphabetic).unwrap_or(false) {
+            compound.tag = Some(self.ident()?);
+        }
+
+        loop {
+            let condition = match self.peek() {
+                Some('#') => {
+                    self.pos += 1;
+                    Condition::Id(self.ident()?)
+                }
+                Some('.') => {
+                    self.pos += 1;
+                    Condition::Class(self.ident()?)
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    self.attribute()?
+                }
+                _ => break,
+            };
+            compound.conditions.push(condition);
+        }
+
+        if !universal && compound.tag.is_none() && compound.conditions.is_empty() {
+            return Err(self.error());
+        }
+        Ok(compound)
+    }
+
+    fn attribute(&mut self) -> Result<Condition, Error> {
+        self.skip_whitespace();
+        let key = self.ident()?;
+        self.skip_whitespace();
+
+        let op = match self.peek() {
+            Some(']') => {
+                self.pos += 1;
+                return Ok(Condition::Attr(key, None));
+            }
+            Some('=') => AttrOp::Equals,
+            Some('~') => AttrOp::Includes,
+            Some('^') => AttrOp::Prefix,
+            Some('$') => AttrOp::Suffix,
+            Some('*') => AttrOp::Substring,
+            _ => return Err(self.error()),
+        };
+        self.pos += 1;
+        if op != AttrOp::Equals {
+            self.expect('=')?;
+        }
+        self.skip_whitespace();
+
+        let value = match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().map(|c| c != quote).unwrap_or(false) {
+                    self.pos += 1;
+                }
+                let value = self.chars[start..self.pos].iter().collect();
+                self.expect(quote)?;
+                value
+            }
+            _ => self.ident()?,
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+
+        Ok(Condition::Attr(key, Some((op, value))))
     }
 }
 
 pub type ElementId = String;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Operation {
     pub target: ElementId,
     pub args: Arguments,
 }
 
-#[derive(Debug, Clone)]
+impl Operation {
+    // Returns the ids of all elements the operation depends on
+    fn referenced_ids(&self) -> Vec<&str> {
+        let mut ids = vec![self.target.as_str()];
+        match &self.args {
+            Arguments::Rotation(Rotation { center, .. }) | Arguments::Scale(Scale { center, .. }) => {
+                ids.extend(center.as_deref())
+            }
+            Arguments::Alignment(Alignment { relative_to, .. }) => ids.extend(relative_to.as_deref()),
+            Arguments::Distribution(d) => {
+                ids.extend(d.elements.iter().map(String::as_str));
+                ids.extend(d.relative_to.as_deref());
+            }
+            Arguments::Stack(s) => ids.extend(s.elements.iter().map(String::as_str)),
+            Arguments::Translate(_)
+            | Arguments::FitText(_)
+            | Arguments::WrapText(_)
+            | Arguments::Theme(_)
+            | Arguments::QrCode(_) => {}
+        }
+        ids
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Arguments {
     Rotation(Rotation),
     Alignment(Alignment),
+    Scale(Scale),
+    Translate(Translate),
+    Distribution(Distribution),
+    Stack(Stack),
+    FitText(FitText),
+    WrapText(WrapText),
+    Theme(Theme),
+    QrCode(QrCode),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Alignment {
     pub orientation: Orientation,
     pub relative_to: Option<ElementId>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Horizontal {
     Left,
     Center,
     Right,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Vertical {
     Top,
     Middle,
     Bottom,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Orientation {
     Horizontal(Horizontal),
     Vertical(Vertical),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Rotation {
     pub angle: f64,
     pub center: Option<ElementId>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub sx: f64,
+    pub sy: f64,
+    pub center: Option<ElementId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translate {
+    pub dx: f64,
+    pub dy: f64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub direction: Direction,
+    // All distributed elements in document order
+    pub elements: Vec<ElementId>,
+    pub relative_to: Option<ElementId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stack {
+    pub direction: Direction,
+    pub gap: f64,
+    // All stacked elements in document order
+    pub elements: Vec<ElementId>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Overflow {
+    // Truncate the text and append an ellipsis
+    Ellipsis,
+    // Reduce the font size
+    Shrink,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitText {
+    pub max_width: f64,
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrapText {
+    pub max_width: f64,
+    // Relative to the font size
+    pub line_height: f64,
+}
+
+// Modules of a QR code in row-major order, `true` for dark modules
+#[derive(Debug, Clone, PartialEq)]
+pub struct QrCode {
+    pub width: usize,
+    pub modules: Vec<bool>,
+}
+
+pub type Color = svgtypes::Color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Theme {
+    // Invert all colors
+    Invert,
+    // Convert all colors to their luminance
+    Grayscale,
+    // Replace colors according to the map, unmapped colors are kept
+    Palette(Vec<(Color, Color)>),
+}
+
+impl Theme {
+    // Returns one of the built-in themes: `light`, `dark` or `eink`. Templates
+    // are assumed to be designed for a light background.
+    pub fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "light" => Ok(Theme::Palette(vec![])),
+            "dark" => Ok(Theme::Invert),
+            "eink" => Ok(Theme::Grayscale),
+            _ => Err(Error::UnknownTheme(name.to_string())),
+        }
+    }
+
+    pub fn palette<'a>(colors: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self, Error> {
+        let parse = |c: &str| c.parse::<Color>().map_err(|_| Error::InvalidColor(c.to_string()));
+        let palette = colors
+            .into_iter()
+            .map(|(from, to)| Ok((parse(from)?, parse(to)?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(Theme::Palette(palette))
+    }
+}
+
+#[derive(Clone, Debug)]
 struct Shared {
     root: Element,
     ops: Vec<Operation>,
+    // Ids generated for unlabeled nodes, which are stripped before rendering
+    synthetic_ids: HashSet<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -151,9 +585,18 @@ static SUPPORTS_TRANSFORM: &'static [&'static str] = &[
     "use",
 ];
 
-fn label_nodes<'root>(root: &'root mut Element) -> Result<String, Error> {
+// Text operations are applied to the whole text element, including its tspans
+static SUPPORTS_TEXT: &[&str] = &["text"];
+
+// Assigns randomly generated ids to any unlabeled nodes in the tree. Existing ids
+// must neither be duplicated within the tree nor conflict with `known_ids`.
+// The generated ids are recorded in `synthetic_ids`.
+fn label_new_nodes(
+    root: &mut Element,
+    known_ids: &mut HashSet<String>,
+    synthetic_ids: &mut HashSet<String>,
+) -> Result<(), Error> {
     // first pass: collect all known element ids and detect duplicates
-    let mut known_ids = HashSet::<String>::new();
     let mut queue = vec![&*root];
     while let Some(node) = queue.pop() {
         if let Some(id) = node.get_attr("id") {
@@ -178,6 +621,7 @@ fn label_nodes<'root>(root: &'root mut Element) -> Result<String, Error> {
                     break id;
                 }
             };
+            synthetic_ids.insert(id.clone());
             node.set_attr("id", id);
         }
 
@@ -186,7 +630,43 @@ fn label_nodes<'root>(root: &'root mut Element) -> Result<String, Error> {
         }
     }
 
-    root.get_attr("id").map(String::from).ok_or(Error::UnlabeledNode)
+    Ok(())
+}
+
+fn collect_ids(root: &Element) -> HashSet<String> {
+    let mut known_ids = HashSet::new();
+    let mut queue = vec![root];
+    while let Some(node) = queue.pop() {
+        if let Some(id) = node.get_attr("id") {
+            known_ids.insert(id.to_string());
+        }
+        queue.extend(node.children());
+    }
+    known_ids
+}
+
+// Assigns new unique ids to the node and all its descendants, derived from their
+// previous id. Ids derived from synthetic ids are synthetic themselves. Returns
+// the new id of the node.
+fn relabel_nodes(root: &mut Element, known_ids: &mut HashSet<String>, synthetic_ids: &mut HashSet<String>) -> String {
+    let mut queue = vec![&mut *root];
+    while let Some(node) = queue.pop() {
+        let (base, synthetic) = match node.get_attr("id") {
+            Some(id) => (id.to_string(), synthetic_ids.contains(id)),
+            None => ("id".to_string(), true),
+        };
+        let id = (1..)
+            .map(|n| format!("{}-{}", base, n))
+            .find(|id| known_ids.insert(id.clone()))
+            .expect("unbounded id search");
+        if synthetic {
+            synthetic_ids.insert(id.clone());
+        }
+        node.set_attr("id", id);
+        queue.extend(node.children_mut());
+    }
+
+    root.get_attr("id").map(String::from).expect("node has been labeled")
 }
 
 impl Document {
@@ -195,19 +675,133 @@ impl Document {
     }
 
     fn new(mut root: Element) -> Result<Self, Error> {
-        let root_label = label_nodes(&mut root)?;
+        let mut synthetic_ids = HashSet::new();
+        label_new_nodes(&mut root, &mut HashSet::new(), &mut synthetic_ids)?;
+        let root_label = root.get_attr("id").map(String::from).ok_or(Error::UnlabeledNode)?;
         let root_selection = Path::new(vec![], root_label);
         Ok(Document {
             shared: Rc::new(RefCell::new(Shared {
                 root: root,
                 ops: Vec::new(),
+                synthetic_ids,
             })),
             selection: Rc::new(vec![root_selection]),
         })
     }
 
+    // Creates an independent copy of the document tree and its queued operations,
+    // retaining the current selection. Unlike `clone()`, modifications of the copy
+    // are not visible in the original document.
+    pub fn deep_copy(&self) -> Self {
+        let shared = self.shared.borrow().clone();
+        Document {
+            shared: Rc::new(RefCell::new(shared)),
+            selection: self.selection.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.selection.is_empty()
+    }
+
     pub fn select_by_attr(&self, key: &str, value: &str) -> Result<Self, Error> {
-        let path = self.select_nodes(|n| n.get_attr(key).map(|a| a == value).unwrap_or(false))?;
+        let path = self.select_nodes(|n, _| n.get_attr(key).map(|a| a == value).unwrap_or(false))?;
+        Ok(Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(path),
+        })
+    }
+
+    pub fn select_by_attr_prefix(&self, key: &str, prefix: &str) -> Result<Self, Error> {
+        let path = self.select_nodes(|n, _| n.get_attr(key).map(|a| a.starts_with(prefix)).unwrap_or(false))?;
+        Ok(Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(path),
+        })
+    }
+
+    pub fn select_by_tag(&self, tag: &str) -> Result<Self, Error> {
+        let path = self.select_nodes(|n, _| n.tag().name() == tag)?;
+        Ok(Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(path),
+        })
+    }
+
+    // Selects the parent elements of the selected elements
+    pub fn parent(&self) -> Result<Self, Error> {
+        let shared = self.shared.borrow();
+        let mut result: Vec<Path> = Vec::new();
+        for node in &*self.selection {
+            let mut path = node.locate(&shared.root)?;
+            path.pop().ok_or(Error::RootNotSupported)?;
+
+            let mut parent = &shared.root;
+            for idx in &path {
+                parent = parent.get_child(*idx).ok_or(Error::SelectionInvalidated)?;
+            }
+
+            let target = parent.get_attr("id").ok_or(Error::UnlabeledNode)?;
+            if !result.iter().any(|p| p.target == target) {
+                result.push(Path::new(path, target.to_owned()));
+            }
+        }
+
+        Ok(Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(result),
+        })
+    }
+
+    // Selects the direct children of the selected elements
+    pub fn children(&self) -> Result<Self, Error> {
+        let shared = self.shared.borrow();
+        let mut result: Vec<Path> = Vec::new();
+        for node in &*self.selection {
+            let path = Path::new(node.locate(&shared.root)?, node.target.clone());
+            for (idx, child) in path.resolve_in(&shared.root)?.children().enumerate() {
+                result.push(path.push_child(idx, child)?);
+            }
+        }
+
+        Ok(Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(result),
+        })
+    }
+
+    // Narrows the selection down to the element at the given index of the
+    // current selection, in document order. The selection is empty if the
+    // index is out of bounds.
+    pub fn nth(&self, index: usize) -> Self {
+        let shared = self.shared.borrow();
+        let mut selection = self
+            .selection
+            .iter()
+            .map(|p| (p.locate(&shared.root).unwrap_or_else(|_| p.path.clone()), p))
+            .collect::<Vec<_>>();
+        selection.sort_by(|a, b| a.0.cmp(&b.0));
+        Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(selection.get(index).map(|(_, p)| (*p).clone()).into_iter().collect()),
+        }
+    }
+
+    pub fn first(&self) -> Self {
+        self.nth(0)
+    }
+
+    pub fn last(&self) -> Self {
+        match self.selection.len() {
+            0 => self.nth(0),
+            len => self.nth(len - 1),
+        }
+    }
+
+    // Selects all elements matching the CSS selector within the current selection
+    pub fn select(&self, selector: &str) -> Result<Self, Error> {
+        let selector = Selector::parse(selector)?;
+        let path = self.select_nodes(|n, ancestors| selector.matches(n, ancestors))?;
         Ok(Document {
             shared: self.shared.clone(),
             selection: Rc::new(path),
@@ -224,6 +818,109 @@ impl Document {
         Ok(())
     }
 
+    // Replaces the content of the selected text elements with one `tspan` per
+    // line, spaced by `line_height` (relative to the font size). The first
+    // existing `tspan` is used as template to preserve its styling.
+    pub fn text_lines<S: AsRef<str>>(&self, lines: &[S], line_height: f64) -> Result<(), Error> {
+        let mut shared = self.shared.borrow_mut();
+        let shared = &mut *shared;
+        let mut known_ids = collect_ids(&shared.root);
+        for node in &*self.selection {
+            let elem = node.resolve_in_mut(&mut shared.root)?;
+            let mut template = match elem.children().next() {
+                Some(child) if child.tag().name() == "tspan" => child.clone(),
+                _ => match elem.tag().ns() {
+                    Some(ns) => Element::new_with_namespaces((ns, "tspan"), elem),
+                    None => Element::new("tspan"),
+                },
+            };
+            template.retain_children(|_| false);
+            template.set_tail("");
+            for key in ["y", "dy"] {
+                template.remove_attr(key);
+            }
+
+            // without an `x`, the lines would continue where the previous one ended
+            let x = template
+                .get_attr("x")
+                .or_else(|| elem.get_attr("x"))
+                .unwrap_or("0")
+                .to_string();
+            template.set_attr("x", x);
+
+            elem.retain_children(|_| false);
+            elem.set_text("");
+            for (n, line) in lines.iter().enumerate() {
+                let mut tspan = template.clone();
+                relabel_nodes(&mut tspan, &mut known_ids, &mut shared.synthetic_ids);
+                if n > 0 {
+                    tspan.set_attr("dy", format!("{}em", line_height));
+                }
+                tspan.set_text(line.as_ref());
+                elem.append_child(tspan);
+            }
+        }
+        Ok(())
+    }
+
+    // Sets the text of all selected elements and their descendants carrying a
+    // `data-bind` attribute to the value returned by `lookup` for the attribute
+    // value. Elements for which no value is found are left unchanged.
+    pub fn bind(&self, lookup: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        let bound = self.select_nodes(|n, _| n.get_attr("data-bind").is_some())?;
+
+        let mut shared = self.shared.borrow_mut();
+        for node in &bound {
+            let elem = node.resolve_in_mut(&mut shared.root)?;
+            let key = elem.get_attr("data-bind").unwrap_or_default();
+            if let Some(value) = lookup(key) {
+                elem.retain_children(|_| false);
+                elem.set_text(value);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the text content of the selected element, including the text of
+    // all its descendants such as `tspan` elements
+    pub fn get_text(&self) -> Result<String, Error> {
+        fn collect(elem: &Element, buf: &mut String) {
+            buf.push_str(elem.text());
+            for child in elem.children() {
+                collect(child, buf);
+                buf.push_str(child.tail());
+            }
+        }
+
+        self.with_singleton(|elem| {
+            let mut buf = String::new();
+            collect(elem, &mut buf);
+            buf
+        })
+    }
+
+    // Detaches all selected elements from the document. Any selection referring
+    // to a removed element is invalidated.
+    pub fn remove(&self) -> Result<(), Error> {
+        let mut shared = self.shared.borrow_mut();
+        for node in &*self.selection {
+            let path = match node.locate(&shared.root) {
+                Ok(path) => path,
+                // already removed, e.g. together with a selected ancestor
+                Err(Error::SelectionInvalidated) => continue,
+                Err(err) => return Err(err),
+            };
+
+            let (idx, parent_path) = path.split_last().ok_or(Error::RootNotSupported)?;
+            let mut parent = &mut shared.root;
+            for i in parent_path {
+                parent = parent.get_child_mut(*i).ok_or(Error::SelectionInvalidated)?;
+            }
+            parent.remove_child(*idx);
+        }
+        Ok(())
+    }
+
     pub fn attr(&self, key: &str, value: &str) -> Result<(), Error> {
         let mut shared = self.shared.borrow_mut();
         for node in &*self.selection {
@@ -232,108 +929,433 @@ impl Document {
         Ok(())
     }
 
-    pub fn push_alignment(&self, orientation: Orientation, relative_to: Option<&Document>) -> Result<(), Error> {
-        if self.selection.is_empty() {
-            return Ok(());
+    pub fn get_attr(&self, key: &str) -> Result<Option<String>, Error> {
+        self.with_singleton(|elem| elem.get_attr(key).map(String::from))
+    }
+
+    // Sets a presentation attribute such as `fill`. Because the inline `style`
+    // attribute takes precedence over presentation attributes, the property is
+    // also removed from any existing inline style.
+    pub fn presentation_attr(&self, key: &str, value: &str) -> Result<(), Error> {
+        let mut shared = self.shared.borrow_mut();
+        for node in &*self.selection {
+            let elem = node.resolve_in_mut(&mut shared.root)?;
+            if let Some(style) = elem.get_attr("style") {
+                let style = style
+                    .split(';')
+                    .filter(|decl| decl.split(':').next().map(str::trim) != Some(key))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                elem.set_attr("style", style);
+            }
+            elem.set_attr(key, value);
+        }
+        Ok(())
+    }
+
+    fn singleton(&self) -> Result<&Path, Error> {
+        match self.selection.as_slice() {
+            [] => Err(Error::EmptySelection),
+            [node] => Ok(node),
+            _ => Err(Error::SingletonRequired),
+        }
+    }
+
+    // Calls `f` with the selected element, which must be a singleton selection
+    fn with_singleton<R>(&self, f: impl FnOnce(&Element) -> R) -> Result<R, Error> {
+        let node = self.singleton()?;
+        let shared = self.shared.borrow();
+        Ok(f(node.resolve_in(&shared.root)?))
+    }
+
+    // Resizes the selected bar to the given fraction of the track along the
+    // direction. Vertical bars grow upwards from the bottom of the track. If no
+    // track is given, a sibling element with class `track` is used.
+    pub fn progress(&self, track: Option<&Document>, fraction: f64, direction: Direction) -> Result<(), Error> {
+        fn rect_attr(elem: &Element, key: &str) -> Result<f64, Error> {
+            let value = elem.get_attr(key).unwrap_or("0");
+            value.parse().map_err(|_| Error::InvalidAttribute(key.to_string()))
         }
 
-        let relative_to = match relative_to.map(|doc| doc.selection.as_slice()) {
-            Some(&[]) => return Err(Error::EmptySelection),
-            Some(&[ref s]) => Some(&s.target),
-            Some(_) => return Err(Error::SingletonRequired),
+        fn ensure_rect(elem: &Element) -> Result<(), Error> {
+            match elem.tag().name() {
+                "rect" => Ok(()),
+                tag => Err(Error::ElementNotSupported(tag.to_string())),
+            }
+        }
+
+        let node = self.singleton()?;
+        let track = match track {
+            Some(track) => Some(track.singleton()?.clone()),
             None => None,
         };
+        let fraction = fraction.clamp(0., 1.);
+
+        let mut shared = self.shared.borrow_mut();
+        let track = match track {
+            Some(track) => track.resolve_in(&shared.root)?,
+            None => {
+                let path = node.locate(&shared.root)?;
+                let (_, parent_path) = path.split_last().ok_or(Error::RootNotSupported)?;
+                let mut parent = &shared.root;
+                for idx in parent_path {
+                    parent = parent.get_child(*idx).ok_or(Error::SelectionInvalidated)?;
+                }
+                parent
+                    .children()
+                    .find(|c| {
+                        c.get_attr("class")
+                            .map(|c| c.split_whitespace().any(|c| c == "track"))
+                            .unwrap_or(false)
+                    })
+                    .ok_or(Error::TrackNotFound)?
+            }
+        };
+        ensure_rect(track)?;
+        let (x, y) = (rect_attr(track, "x")?, rect_attr(track, "y")?);
+        let (width, height) = (rect_attr(track, "width")?, rect_attr(track, "height")?);
+
+        let bar = node.resolve_in_mut(&mut shared.root)?;
+        ensure_rect(bar)?;
+        match direction {
+            Direction::Horizontal => {
+                bar.set_attr("x", x.to_string());
+                bar.set_attr("width", (width * fraction).to_string());
+            }
+            Direction::Vertical => {
+                bar.set_attr("y", (y + height * (1. - fraction)).to_string());
+                bar.set_attr("height", (height * fraction).to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Adds a `<style>` element with the given CSS to the end of the document
+    pub fn inject_css(&self, css: &str) -> Result<(), Error> {
+        let mut shared = self.shared.borrow_mut();
+        let shared = &mut *shared;
+
+        let root = &mut shared.root;
+        let mut style = match root.tag().ns() {
+            Some(ns) => Element::new_with_namespaces((ns, "style"), root),
+            None => Element::new("style"),
+        };
+        style.set_attr("type", "text/css");
+        style.set_text(css);
+
+        let mut known_ids = collect_ids(root);
+        label_new_nodes(&mut style, &mut known_ids, &mut shared.synthetic_ids)?;
+        root.append_child(style);
+        Ok(())
+    }
+
+    // Appends a deep copy of the selected element to its parent. All ids within
+    // the copy are replaced with newly generated ones. Returns the selected copy.
+    pub fn clone_element(&self) -> Result<Self, Error> {
+        let node = self.singleton()?;
+
+        let mut shared = self.shared.borrow_mut();
+        let shared = &mut *shared;
+        let node_path = node.locate(&shared.root)?;
+        let (_, parent_path) = node_path.split_last().ok_or(Error::RootNotSupported)?;
+        let mut copy = node.resolve_in(&shared.root)?.clone();
+        let mut known_ids = collect_ids(&shared.root);
+        let target = relabel_nodes(&mut copy, &mut known_ids, &mut shared.synthetic_ids);
+
+        let mut parent = &mut shared.root;
+        for idx in parent_path {
+            parent = parent.get_child_mut(*idx).ok_or(Error::SelectionInvalidated)?;
+        }
+        parent.append_child(copy);
+
+        let mut path = parent_path.to_vec();
+        path.push(parent.child_count() - 1);
+        Ok(Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(vec![Path::new(path, target)]),
+        })
+    }
+
+    // Parses an SVG fragment and appends its top-level nodes as children of each
+    // selected element. The fragment may contain multiple top-level nodes and uses
+    // the SVG namespace by default. Copies appended to all but the first selected
+    // element are relabeled, like clones.
+    pub fn append_svg(&self, snippet: &str) -> Result<(), Error> {
+        let wrapped = format!(
+            r#"<g xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">{}</g>"#,
+            snippet
+        );
+        let mut fragment = Element::from_reader(io::Cursor::new(wrapped))?;
+
+        let mut shared = self.shared.borrow_mut();
+        let shared = &mut *shared;
+        let mut known_ids = collect_ids(&shared.root);
+        for child in fragment.children_mut() {
+            label_new_nodes(child, &mut known_ids, &mut shared.synthetic_ids)?;
+        }
+
+        // check all targets first, so that the document is left unchanged on error
+        for node in &*self.selection {
+            node.locate(&shared.root)?;
+        }
+
+        for (n, node) in self.selection.iter().enumerate() {
+            let mut fragment = fragment.clone();
+            if n > 0 {
+                for child in fragment.children_mut() {
+                    relabel_nodes(child, &mut known_ids, &mut shared.synthetic_ids);
+                }
+            }
+
+            let elem = node.resolve_in_mut(&mut shared.root)?;
+            while let Some(child) = fragment.remove_child(0) {
+                elem.append_child(child);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the id of the element selected by `doc`, which must be a singleton selection
+    fn singleton_target(doc: Option<&Document>) -> Result<Option<ElementId>, Error> {
+        match doc.map(|doc| doc.selection.as_slice()) {
+            Some([]) => Err(Error::EmptySelection),
+            Some([s]) => Ok(Some(s.target.clone())),
+            Some(_) => Err(Error::SingletonRequired),
+            None => Ok(None),
+        }
+    }
+
+    // Queues an operation for each selected element, which must support the operation
+    fn push_operation(&self, args: Arguments) -> Result<(), Error> {
+        let supported = match args {
+            Arguments::FitText(_) | Arguments::WrapText(_) => SUPPORTS_TEXT,
+            _ => SUPPORTS_TRANSFORM,
+        };
 
         let mut shared = self.shared.borrow_mut();
         for node in &*self.selection {
             let tag = node.resolve_in(&shared.root)?.tag().name();
-            if !SUPPORTS_TRANSFORM.contains(&tag) {
+            if !supported.contains(&tag) {
                 return Err(Error::ElementNotSupported(tag.to_string()));
             }
 
             shared.ops.push(Operation {
                 target: node.target.to_owned(),
-                args: Arguments::Alignment(Alignment {
-                    orientation: orientation,
-                    relative_to: relative_to.cloned(),
-                }),
+                args: args.clone(),
             });
         }
 
         Ok(())
     }
 
+    pub fn push_alignment(&self, orientation: Orientation, relative_to: Option<&Document>) -> Result<(), Error> {
+        if self.selection.is_empty() {
+            return Ok(());
+        }
+
+        let relative_to = Document::singleton_target(relative_to)?;
+        self.push_operation(Arguments::Alignment(Alignment {
+            orientation,
+            relative_to,
+        }))
+    }
+
     pub fn push_rotation(&self, angle: f64, center: Option<&Document>) -> Result<(), Error> {
         if self.selection.is_empty() {
             return Ok(());
         }
 
-        let center = match center.map(|doc| doc.selection.as_slice()) {
-            Some(&[]) => return Err(Error::EmptySelection),
-            Some(&[ref s]) => Some(&s.target),
-            Some(_) => return Err(Error::SingletonRequired),
-            None => None,
-        };
+        let center = Document::singleton_target(center)?;
+        self.push_operation(Arguments::Rotation(Rotation { angle, center }))
+    }
 
-        let mut shared = self.shared.borrow_mut();
+    pub fn push_scale(&self, sx: f64, sy: f64, center: Option<&Document>) -> Result<(), Error> {
+        if self.selection.is_empty() {
+            return Ok(());
+        }
+
+        let center = Document::singleton_target(center)?;
+        self.push_operation(Arguments::Scale(Scale { sx, sy, center }))
+    }
+
+    pub fn push_distribution(&self, direction: Direction, relative_to: Option<&Document>) -> Result<(), Error> {
+        if self.selection.is_empty() {
+            return Ok(());
+        }
+
+        let relative_to = Document::singleton_target(relative_to)?;
+        self.push_operation(Arguments::Distribution(Distribution {
+            direction,
+            elements: self.selected_in_document_order(),
+            relative_to,
+        }))
+    }
+
+    pub fn push_stack(&self, direction: Direction, gap: f64) -> Result<(), Error> {
+        if self.selection.is_empty() {
+            return Ok(());
+        }
+
+        self.push_operation(Arguments::Stack(Stack {
+            direction,
+            gap,
+            elements: self.selected_in_document_order(),
+        }))
+    }
+
+    pub fn push_fit_text(&self, max_width: f64, overflow: Overflow) -> Result<(), Error> {
+        self.push_operation(Arguments::FitText(FitText { max_width, overflow }))
+    }
+
+    pub fn push_wrap_text(&self, max_width: f64, line_height: f64) -> Result<(), Error> {
+        self.push_operation(Arguments::WrapText(WrapText { max_width, line_height }))
+    }
+
+    // Draws a QR code encoding the text into the bounding box of each selected
+    // element. The QR code is added as child of groups, and on top of others.
+    pub fn push_qrcode(&self, text: &str) -> Result<(), Error> {
+        let code = qrcode::QrCode::new(text)?;
+        self.push_operation(Arguments::QrCode(QrCode {
+            width: code.width(),
+            modules: code.to_colors().into_iter().map(|c| c == qrcode::Color::Dark).collect(),
+        }))
+    }
+
+    // Recolors the selected elements and all their descendants
+    pub fn push_theme(&self, theme: Theme) -> Result<(), Error> {
+        // the document root has no counterpart in the render tree, so its
+        // graphical children are recolored instead
+        let mut targets = Vec::new();
         for node in &*self.selection {
-            let tag = node.resolve_in(&shared.root)?.tag().name();
-            if !SUPPORTS_TRANSFORM.contains(&tag) {
-                return Err(Error::ElementNotSupported(tag.to_string()));
+            if !node.path.is_empty() {
+                targets.push(node.clone());
+                continue;
             }
 
-            shared.ops.push(Operation {
-                target: node.target.to_owned(),
-                args: Arguments::Rotation(Rotation {
-                    angle: angle,
-                    center: center.cloned(),
-                }),
-            });
+            let shared = self.shared.borrow();
+            for (idx, child) in node.resolve_in(&shared.root)?.children().enumerate() {
+                if SUPPORTS_TRANSFORM.contains(&child.tag().name()) {
+                    targets.push(node.push_child(idx, child)?);
+                }
+            }
         }
 
-        Ok(())
+        let targets = Document {
+            shared: self.shared.clone(),
+            selection: Rc::new(targets),
+        };
+        targets.push_operation(Arguments::Theme(theme))
+    }
+
+    fn selected_in_document_order(&self) -> Vec<ElementId> {
+        let mut selection = self.selection.iter().collect::<Vec<_>>();
+        selection.sort_by(|a, b| a.path.cmp(&b.path));
+        selection.into_iter().map(|p| p.target.clone()).collect()
+    }
+
+    pub fn push_translation(&self, dx: f64, dy: f64) -> Result<(), Error> {
+        if self.selection.is_empty() {
+            return Ok(());
+        }
+
+        self.push_operation(Arguments::Translate(Translate { dx, dy }))
     }
 
-    fn select_nodes(&self, predicate: impl Fn(&Element) -> bool) -> Result<Vec<Path>, Error> {
+    // Collects all selected elements and their descendants matching the predicate,
+    // which is passed the element and its ancestors, starting from the root.
+    fn select_nodes(&self, predicate: impl Fn(&Element, &[&Element]) -> bool) -> Result<Vec<Path>, Error> {
         let mut result: Vec<Path> = Vec::new();
 
         let shared = self.shared.borrow();
         for path in &*self.selection {
-            let source = path.resolve_in(&shared.root)?;
+            let path = Path::new(path.locate(&shared.root)?, path.target.clone());
 
-            let mut stack: Vec<(&Element, Path)> = Vec::new();
-            stack.push((&source, path.clone()));
+            let mut ancestors = Vec::new();
+            let mut source = &shared.root;
+            for idx in &path.path {
+                ancestors.push(source);
+                source = source.get_child(*idx).ok_or(Error::SelectionInvalidated)?;
+            }
 
-            while !stack.is_empty() {
-                let (node, path) = stack.pop().unwrap();
+            let depth = ancestors.len();
+            let mut stack: Vec<(&Element, Path, usize)> = Vec::new();
+            stack.push((source, path, depth));
 
-                if predicate(node) {
+            while let Some((node, path, depth)) = stack.pop() {
+                ancestors.truncate(depth);
+                if predicate(node, &ancestors) {
                     result.push(path.clone());
                 }
 
                 for (idx, child) in node.children().enumerate() {
                     let path = path.push_child(idx, child)?;
-                    stack.push((&child, path));
+                    stack.push((child, path, depth + 1));
                 }
+                ancestors.push(node);
             }
         }
 
         Ok(result)
     }
 
-    pub fn prepare(&self) -> Result<(Vec<u8>, Vec<Operation>), Error> {
-        let mut buf = Vec::new();
+    // Returns the root element together with the queued operations. Synthetic
+    // ids are removed, unless they are referenced by an operation.
+    pub fn prepare(&self) -> (Element, Vec<Operation>) {
+        let shared = self.shared.borrow();
+        let referenced = shared
+            .ops
+            .iter()
+            .flat_map(Operation::referenced_ids)
+            .collect::<HashSet<_>>();
+
+        let mut root = shared.root.clone();
+        let mut queue = vec![&mut root];
+        while let Some(node) = queue.pop() {
+            if let Some(id) = node.get_attr("id") {
+                if shared.synthetic_ids.contains(id) && !referenced.contains(id) {
+                    node.remove_attr("id");
+                }
+            }
+            queue.extend(node.children_mut());
+        }
+
+        (root, shared.ops.clone())
+    }
+}
+
+// Serializes a prepared element for parsing by usvg
+pub fn serialize(root: &Element) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    root.to_writer_with_options(
+        &mut buf,
+        elementtree::WriteOptions::new()
+            .set_autopad_comments(false)
+            .set_line_separator("")
+            .set_perform_indent(false),
+    )?;
+    Ok(buf)
+}
 
-        self.shared.borrow().root.to_writer_with_options(
-            &mut buf,
-            elementtree::WriteOptions::new()
-                .set_autopad_comments(false)
-                .set_line_separator("")
-                .set_perform_indent(false),
-        )?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let ops = self.shared.borrow().ops.clone();
+    #[test]
+    fn clone_after_removing_sibling() {
+        let doc = Document::from_bytes(r#"<svg><g id="a"/><g id="b"><rect id="row"/></g><g id="c"/></svg>"#).unwrap();
+        let row = doc.select_by_attr("id", "row").unwrap();
+        doc.select_by_attr("id", "a").unwrap().remove().unwrap();
 
-        Ok((buf, ops))
+        let copy = row.clone_element().unwrap();
+        assert_eq!(copy.parent().unwrap().get_attr("id").unwrap().as_deref(), Some("b"));
+        assert_eq!(
+            doc.select_by_attr("id", "c")
+                .unwrap()
+                .children()
+                .unwrap()
+                .selection
+                .len(),
+            0
+        );
     }
 }
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
 use std::fs::{File, OpenOptions};
 use std::io;
@@ -7,6 +8,7 @@ use std::ops::{Deref, DerefMut};
 use std::os::fd::{AsRawFd, RawFd};
 use std::os::unix::prelude::OpenOptionsExt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use glob::glob;
 use log::debug;
@@ -14,8 +16,10 @@ use nix::errno::Errno;
 use thiserror::Error;
 
 mod sys;
+mod touch;
 
 pub use sys::KeyCode;
+pub use touch::{SwipeDirection, TouchDevice, TouchDeviceBuilder, TouchEvent};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -33,6 +37,13 @@ pub enum Error {
     NoInputDevicesFound,
 }
 
+impl Error {
+    // Returns true if the device was unplugged
+    pub fn is_disconnected(&self) -> bool {
+        matches!(self, Error::OsError(Errno::ENODEV))
+    }
+}
+
 struct BitSet(Box<[u8]>);
 
 impl BitSet {
@@ -59,6 +70,100 @@ impl DerefMut for BitSet {
     }
 }
 
+fn open_device(path: &Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|err| Error::OpenError(path.to_path_buf(), err))
+}
+
+fn device_name(dev: &File) -> Result<String, Error> {
+    let mut buf = [0u8; 128];
+    unsafe {
+        sys::evdev_get_name(dev.as_raw_fd(), &mut buf)?;
+    };
+
+    Ok(CStr::from_bytes_until_nul(&buf)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?
+        .to_string_lossy()
+        .to_string())
+}
+
+fn supports_event(dev: &File, event_type: u16) -> Result<bool, Error> {
+    let mut events = BitSet::with_size(sys::EV_CNT as usize);
+    unsafe {
+        sys::evdev_get_event_bits(dev.as_raw_fd(), &mut events)?;
+    }
+    Ok(events.is_set(event_type as usize))
+}
+
+// Reads the next event, or returns None if no event is pending
+fn read_event(dev: &File) -> Result<Option<libc::input_event>, Error> {
+    unsafe {
+        let event_len = mem::size_of::<libc::input_event>();
+        let mut event = MaybeUninit::<libc::input_event>::zeroed();
+        let errno = libc::read(dev.as_raw_fd(), event.as_mut_ptr() as *mut _, event_len);
+        match Errno::result(errno) {
+            Ok(n) if n == event_len as isize => Ok(Some(event.assume_init())),
+            Ok(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof))?,
+            Err(Errno::EWOULDBLOCK) => Ok(None),
+            Err(err) => Err(err)?,
+        }
+    }
+}
+
+// Capabilities of an input device, e.g. to find out which keys to bind
+#[derive(Debug)]
+pub struct DeviceInfo {
+    pub path: PathBuf,
+    pub name: String,
+    pub event_types: Vec<&'static str>,
+    pub keys: Vec<KeyCode>,
+}
+
+impl DeviceInfo {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let dev = open_device(path)?;
+
+        let mut events = BitSet::with_size(sys::EV_CNT as usize);
+        unsafe {
+            sys::evdev_get_event_bits(dev.as_raw_fd(), &mut events)?;
+        }
+        let event_types = sys::EVENT_TYPES
+            .iter()
+            .filter(|(event_type, _)| events.is_set(*event_type as usize))
+            .map(|(_, name)| *name)
+            .collect();
+
+        let mut keys = BitSet::with_size(KeyCode::COUNT);
+        if events.is_set(sys::EV_KEY as usize) {
+            unsafe {
+                sys::evdev_get_event_key_bits(dev.as_raw_fd(), &mut keys)?;
+            }
+        }
+        let keys = (0..KeyCode::COUNT)
+            .filter(|key| keys.is_set(*key))
+            .map(|key| KeyCode::from(key as u16))
+            .collect();
+
+        Ok(DeviceInfo {
+            path: path.to_path_buf(),
+            name: device_name(&dev)?,
+            event_types,
+            keys,
+        })
+    }
+}
+
+// Returns the paths of all input devices matching the pattern
+pub fn device_paths(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+    // sort event10 after event9
+    paths.sort_by_key(|p| (p.as_os_str().len(), p.clone()));
+    Ok(paths)
+}
+
 pub struct KeyDeviceBuilder {
     keys: Vec<KeyCode>,
 }
@@ -95,33 +200,59 @@ impl KeyDeviceBuilder {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyEvent {
+    Pressed(KeyCode),
+    // Released after being held down for the given duration
+    Released(KeyCode, Duration),
+}
+
+impl KeyEvent {
+    pub fn code(self) -> KeyCode {
+        match self {
+            KeyEvent::Pressed(code) | KeyEvent::Released(code, _) => code,
+        }
+    }
+}
+
+// Tracks which keys are currently held down, possibly across multiple devices
+#[derive(Debug, Default)]
+pub struct KeyState {
+    held: HashSet<KeyCode>,
+}
+
+impl KeyState {
+    pub fn update(&mut self, event: KeyEvent) {
+        match event {
+            KeyEvent::Pressed(code) => self.held.insert(code),
+            KeyEvent::Released(code, _) => self.held.remove(&code),
+        };
+    }
+
+    // Returns true if all of the keys are held down at the same time
+    pub fn all_held(&self, keys: &[KeyCode]) -> bool {
+        keys.iter().all(|key| self.held.contains(key))
+    }
+}
+
 pub struct KeyDevice {
     dev: File,
     filter: HashSet<KeyCode>,
+    // timestamps of keys currently held down
+    pressed: RefCell<HashMap<KeyCode, Duration>>,
 }
 
 impl KeyDevice {
     fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let path = path.as_ref();
-        let dev = OpenOptions::new()
-            .read(true)
-            .custom_flags(libc::O_NONBLOCK)
-            .open(path)
-            .map_err(|err| Error::OpenError(path.to_path_buf(), err))?;
-
         Ok(KeyDevice {
-            dev: dev,
+            dev: open_device(path.as_ref())?,
             filter: HashSet::new(),
+            pressed: RefCell::new(HashMap::new()),
         })
     }
 
     fn with_filter(mut self, keycodes: impl IntoIterator<Item = KeyCode>) -> Result<Option<Self>, Error> {
-        let mut events = BitSet::with_size(sys::EV_CNT as usize);
-        unsafe {
-            sys::evdev_get_event_bits(self.dev.as_raw_fd(), &mut events)?;
-        }
-
-        if !events.is_set(sys::EV_KEY as usize) {
+        if !supports_event(&self.dev, sys::EV_KEY)? {
             return Ok(None);
         }
 
@@ -147,40 +278,38 @@ impl KeyDevice {
         Ok(Some(self))
     }
 
-    fn device_name(&self) -> Result<String, Error> {
-        let mut buf = [0u8; 128];
-        unsafe {
-            sys::evdev_get_name(self.dev.as_raw_fd(), &mut buf)?;
-        };
-
-        Ok(CStr::from_bytes_until_nul(&buf)
-            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?
-            .to_string_lossy()
-            .to_string())
+    pub fn device_name(&self) -> Result<String, Error> {
+        device_name(&self.dev)
     }
 
-    pub fn next_key_press(&self) -> Result<Option<KeyCode>, Error> {
+    pub fn next_key_event(&self) -> Result<Option<KeyEvent>, Error> {
         loop {
-            // Loop until a keypress matches a filter or read returns EWOULDBLOCK
-            let event = unsafe {
-                let event_len = mem::size_of::<libc::input_event>();
-                let mut event = MaybeUninit::<libc::input_event>::zeroed();
-                let errno = libc::read(self.dev.as_raw_fd(), event.as_mut_ptr() as *mut _, event_len);
-                match Errno::result(errno) {
-                    Ok(n) if n == event_len as isize => event.assume_init(),
-                    Ok(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof))?,
-                    Err(Errno::EWOULDBLOCK) => return Ok(None),
-                    Err(err) => Err(err)?,
-                }
+            // Loop until a key event matches a filter or read returns EWOULDBLOCK
+            let Some(event) = read_event(&self.dev)? else {
+                return Ok(None);
             };
 
-            if event.type_ != sys::EV_KEY || event.value == 0 {
-                continue; // not a keypress
+            let keycode = KeyCode::from(event.code);
+            if event.type_ != sys::EV_KEY || !self.filter.contains(&keycode) {
+                continue;
             }
 
-            let keycode = KeyCode::from(event.code);
-            if self.filter.contains(&keycode) {
-                return Ok(Some(keycode));
+            let time = Duration::new(event.time.tv_sec as u64, event.time.tv_usec as u32 * 1000);
+            match event.value {
+                // key down
+                1 => {
+                    self.pressed.borrow_mut().insert(keycode, time);
+                    return Ok(Some(KeyEvent::Pressed(keycode)));
+                }
+                // key up
+                0 => {
+                    let Some(pressed) = self.pressed.borrow_mut().remove(&keycode) else {
+                        continue; // pressed before the device was opened
+                    };
+                    return Ok(Some(KeyEvent::Released(keycode, time.saturating_sub(pressed))));
+                }
+                // autorepeat
+                _ => continue,
             }
         }
     }
@@ -1,14 +1,58 @@
-use nix::ioctl_read_buf;
+use std::mem;
+use std::os::fd::RawFd;
+
+use nix::errno::Errno;
+use nix::{ioctl_read_buf, request_code_read};
 
 include!(concat!(env!("OUT_DIR"), "/input-event-codes.rs"));
 
+pub const EV_SYN: u16 = 0;
 pub const EV_KEY: u16 = 1;
+pub const EV_ABS: u16 = 3;
 pub const EV_CNT: u16 = 32;
 
+// Names of the event types reported by EVIOCGBIT
+pub const EVENT_TYPES: [(u16, &str); 12] = [
+    (0x00, "EV_SYN"),
+    (0x01, "EV_KEY"),
+    (0x02, "EV_REL"),
+    (0x03, "EV_ABS"),
+    (0x04, "EV_MSC"),
+    (0x05, "EV_SW"),
+    (0x11, "EV_LED"),
+    (0x12, "EV_SND"),
+    (0x14, "EV_REP"),
+    (0x15, "EV_FF"),
+    (0x16, "EV_PWR"),
+    (0x17, "EV_FF_STATUS"),
+];
+
+pub const SYN_REPORT: u16 = 0;
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const ABS_CNT: u16 = 0x40;
+
 const EVIO_IOC_MAGIC: u8 = b'E';
 const EVIOCGNAME: u8 = 0x06;
 const EVIOCGBIT: u8 = 0x20;
+const EVIOCGABS: u8 = 0x40;
 
 ioctl_read_buf!(evdev_get_name, EVIO_IOC_MAGIC, EVIOCGNAME, u8);
 ioctl_read_buf!(evdev_get_event_bits, EVIO_IOC_MAGIC, EVIOCGBIT, u8);
 ioctl_read_buf!(evdev_get_event_key_bits, EVIO_IOC_MAGIC, EVIOCGBIT + EV_KEY as u8, u8);
+ioctl_read_buf!(evdev_get_event_abs_bits, EVIO_IOC_MAGIC, EVIOCGBIT + EV_ABS as u8, u8);
+
+// The axis is part of the request number, so it cannot be expressed with the
+// ioctl_read! macro
+pub unsafe fn evdev_get_abs_info(fd: RawFd, axis: u16, info: *mut libc::input_absinfo) -> nix::Result<libc::c_int> {
+    let request = request_code_read!(
+        EVIO_IOC_MAGIC,
+        EVIOCGABS + axis as u8,
+        mem::size_of::<libc::input_absinfo>()
+    );
+    Errno::result(libc::ioctl(fd, request as _, info))
+}
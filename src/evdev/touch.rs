@@ -0,0 +1,212 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::Path;
+use std::str::FromStr;
+
+use glob::glob;
+use log::debug;
+
+use super::{device_name, open_device, read_event, supports_event, sys, BitSet, Error, KeyCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl FromStr for SwipeDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(SwipeDirection::Left),
+            "right" => Ok(SwipeDirection::Right),
+            "up" => Ok(SwipeDirection::Up),
+            "down" => Ok(SwipeDirection::Down),
+            _ => Err(format!("Unknown swipe direction {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SwipeDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SwipeDirection::Left => "left",
+            SwipeDirection::Right => "right",
+            SwipeDirection::Up => "up",
+            SwipeDirection::Down => "down",
+        })
+    }
+}
+
+// Gesture recognized from absolute pointer events. Coordinates are relative
+// to the range of the touchscreen axes, i.e. between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchEvent {
+    Tap(f32, f32),
+    Swipe(SwipeDirection),
+}
+
+impl TouchEvent {
+    // Minimum distance a touch has to move to be a swipe, relative to the screen size
+    const MIN_SWIPE: f32 = 0.15;
+
+    fn from_movement((x0, y0): (f32, f32), (x1, y1): (f32, f32)) -> Self {
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        if dx.abs().max(dy.abs()) < Self::MIN_SWIPE {
+            return TouchEvent::Tap(x1, y1);
+        }
+
+        // the direction is determined by the dominant axis
+        let direction = match (dx.abs() >= dy.abs(), dx < 0., dy < 0.) {
+            (true, true, _) => SwipeDirection::Left,
+            (true, false, _) => SwipeDirection::Right,
+            (false, _, true) => SwipeDirection::Up,
+            (false, _, false) => SwipeDirection::Down,
+        };
+        TouchEvent::Swipe(direction)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Axis {
+    code: u16,
+    min: i32,
+    max: i32,
+}
+
+impl Axis {
+    fn query(dev: &File, code: u16) -> Result<Self, Error> {
+        let info = unsafe {
+            let mut info = MaybeUninit::<libc::input_absinfo>::zeroed();
+            sys::evdev_get_abs_info(dev.as_raw_fd(), code, info.as_mut_ptr())?;
+            info.assume_init()
+        };
+        Ok(Axis {
+            code,
+            min: info.minimum,
+            max: info.maximum,
+        })
+    }
+
+    fn normalize(&self, value: i32) -> f32 {
+        let range = (self.max - self.min).max(1) as f32;
+        ((value - self.min) as f32 / range).clamp(0., 1.)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TouchState {
+    x: i32,
+    y: i32,
+    touching: bool,
+    // whether a touch was in progress at the last SYN_REPORT
+    was_touching: bool,
+    // position where the current touch started
+    start: (i32, i32),
+}
+
+pub struct TouchDevice {
+    dev: File,
+    x: Axis,
+    y: Axis,
+    state: RefCell<TouchState>,
+}
+
+impl TouchDevice {
+    fn open(path: &Path) -> Result<Option<Self>, Error> {
+        let dev = open_device(path)?;
+        if !supports_event(&dev, sys::EV_ABS)? {
+            return Ok(None);
+        }
+
+        let mut axes = BitSet::with_size(sys::ABS_CNT as usize);
+        unsafe {
+            sys::evdev_get_event_abs_bits(dev.as_raw_fd(), &mut axes)?;
+        }
+
+        // prefer multitouch axes, as some drivers do not emulate single touch
+        let (x, y) = if axes.is_set(sys::ABS_MT_POSITION_X as usize) && axes.is_set(sys::ABS_MT_POSITION_Y as usize) {
+            (sys::ABS_MT_POSITION_X, sys::ABS_MT_POSITION_Y)
+        } else if axes.is_set(sys::ABS_X as usize) && axes.is_set(sys::ABS_Y as usize) {
+            (sys::ABS_X, sys::ABS_Y)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(TouchDevice {
+            x: Axis::query(&dev, x)?,
+            y: Axis::query(&dev, y)?,
+            dev,
+            state: RefCell::new(TouchState::default()),
+        }))
+    }
+
+    pub fn next_touch_event(&self) -> Result<Option<TouchEvent>, Error> {
+        let mut state = self.state.borrow_mut();
+        // Loop until a gesture is complete or read returns EWOULDBLOCK
+        while let Some(event) = read_event(&self.dev)? {
+            match (event.type_, event.code) {
+                (sys::EV_ABS, code) if code == self.x.code => state.x = event.value,
+                (sys::EV_ABS, code) if code == self.y.code => state.y = event.value,
+                (sys::EV_ABS, sys::ABS_MT_TRACKING_ID) => state.touching = event.value >= 0,
+                (sys::EV_KEY, code) if KeyCode::from(code) == KeyCode::BTN_TOUCH => state.touching = event.value != 0,
+                (sys::EV_SYN, sys::SYN_REPORT) => {
+                    let (pressed, released) = (
+                        !state.was_touching && state.touching,
+                        state.was_touching && !state.touching,
+                    );
+                    state.was_touching = state.touching;
+                    if pressed {
+                        state.start = (state.x, state.y);
+                    } else if released {
+                        let start = (self.x.normalize(state.start.0), self.y.normalize(state.start.1));
+                        let end = (self.x.normalize(state.x), self.y.normalize(state.y));
+                        return Ok(Some(TouchEvent::from_movement(start, end)));
+                    }
+                }
+                _ => continue,
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl AsRawFd for TouchDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.dev.as_raw_fd()
+    }
+}
+
+pub struct TouchDeviceBuilder;
+
+impl TouchDeviceBuilder {
+    pub fn find(pattern: &str) -> Result<impl Iterator<Item = TouchDevice>, Error> {
+        let mut devices = Vec::new();
+        for path in glob(pattern)? {
+            let path = path?;
+            let Some(dev) = TouchDevice::open(&path)? else {
+                continue;
+            };
+
+            if log::log_enabled!(log::Level::Debug) {
+                let name = device_name(&dev.dev)?;
+                debug!("Opened evdev touch device {:?}: {:?}", path, name);
+            }
+
+            devices.push(dev);
+        }
+
+        if devices.is_empty() {
+            return Err(Error::NoInputDevicesFound);
+        }
+
+        Ok(devices.into_iter())
+    }
+}
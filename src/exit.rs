@@ -0,0 +1,133 @@
+use std::fmt;
+use std::process::ExitCode;
+
+// Exit code used if the program exits because an exit key was pressed
+pub const KEY_PRESSED: u8 = 10;
+
+// Category of fatal errors, reported to supervisors via distinct exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Template,
+    Script,
+    Render,
+    Device,
+    Network,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Template => 2,
+            Failure::Script => 3,
+            Failure::Render => 4,
+            Failure::Device => 5,
+            Failure::Network => 6,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Failure::Template => "template",
+            Failure::Script => "script",
+            Failure::Render => "render",
+            Failure::Device => "device",
+            Failure::Network => "network",
+        }
+    }
+
+    // Creates a new error with the given message classified as this failure
+    pub fn error(self, message: impl fmt::Display) -> anyhow::Error {
+        anyhow::Error::new(Classified {
+            failure: self,
+            message: message.to_string(),
+        })
+    }
+}
+
+// Error context which carries the classification of the error it wraps. It is
+// displayed as the plain message so that error messages remain unchanged.
+#[derive(Debug)]
+struct Classified {
+    failure: Failure,
+    message: String,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Classified {}
+
+pub trait Classify<T> {
+    fn classify<M: fmt::Display>(self, failure: Failure, message: impl FnOnce() -> M) -> Result<T, anyhow::Error>;
+}
+
+impl<T, E> Classify<T> for Result<T, E>
+where
+    E: Into<anyhow::Error>,
+{
+    fn classify<M: fmt::Display>(self, failure: Failure, message: impl FnOnce() -> M) -> Result<T, anyhow::Error> {
+        self.map_err(|err| {
+            err.into().context(Classified {
+                failure,
+                message: message().to_string(),
+            })
+        })
+    }
+}
+
+// Returns the outermost classification of the error, if any
+pub fn failure_of(err: &anyhow::Error) -> Option<Failure> {
+    err.downcast_ref::<Classified>().map(|c| c.failure)
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Formats the error and its causes as a single line JSON object
+pub fn to_json(err: &anyhow::Error) -> String {
+    let failure = failure_of(err);
+    let causes = err
+        .chain()
+        .skip(1)
+        .map(|e| json_string(&e.to_string()))
+        .collect::<Vec<_>>();
+
+    format!(
+        "{{\"error\":{},\"code\":{},\"message\":{},\"causes\":[{}]}}",
+        json_string(failure.map(Failure::name).unwrap_or("other")),
+        exit_code(err),
+        json_string(&err.to_string()),
+        causes.join(",")
+    )
+}
+
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    failure_of(err).map(Failure::code).unwrap_or(1)
+}
+
+// Prints the error to stderr and returns the exit code of its classification
+pub fn report(err: &anyhow::Error, json: bool) -> ExitCode {
+    match json {
+        true => eprintln!("{}", to_json(err)),
+        false => eprintln!("Error: {:?}", err),
+    }
+    ExitCode::from(exit_code(err))
+}
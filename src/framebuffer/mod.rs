@@ -1,10 +1,12 @@
 use std::borrow::Cow;
 use std::ffi::CStr;
+use std::fmt;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::mem::MaybeUninit;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use bitflags::bitflags;
 use log::debug;
@@ -13,6 +15,7 @@ use thiserror::Error;
 use tiny_skia::{ColorU8, Pixmap};
 
 use self::sys::{fb_fix_screeninfo, fb_var_screeninfo};
+use crate::rendering::ScreenRotation;
 
 mod sys;
 
@@ -30,14 +33,56 @@ pub enum Error {
 
 type PixelDepth = u32;
 
+// Position of a single color channel within a pixel, as described by
+// the `fb_bitfield` of the variable screen info.
+#[derive(Copy, Clone, Debug)]
+struct Channel {
+    offset: u32,
+    length: u32,
+    msb_right: bool,
+}
+
+impl Channel {
+    fn from_bitfield(bitfield: sys::fb_bitfield) -> Self {
+        Channel {
+            offset: bitfield.offset,
+            length: bitfield.length,
+            msb_right: bitfield.msb_right != 0,
+        }
+    }
+
+    fn fits_in(&self, bits_per_pixel: PixelDepth) -> bool {
+        self.offset
+            .checked_add(self.length)
+            .map(|end| end <= bits_per_pixel)
+            .unwrap_or(false)
+    }
+
+    // Scales an 8-bit color value to the channel length and moves it into
+    // its position within the pixel
+    fn encode(&self, value: u8) -> u32 {
+        if self.length == 0 {
+            return 0;
+        }
+
+        let max = (1u64 << self.length) - 1;
+        let mut v = ((value as u64 * max + 127) / u8::MAX as u64) as u32;
+        if self.msb_right {
+            v = v.reverse_bits() >> (u32::BITS - self.length);
+        }
+
+        v << self.offset
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 enum PixelFormat {
-    RGBA8888,
-    BGRA8888,
-    RGB888,
-    BGR888,
-    RGB565,
-    BGR565,
+    Rgb {
+        red: Channel,
+        green: Channel,
+        blue: Channel,
+        transp: Channel,
+    },
     Grayscale8,
     Grayscale16,
     Grayscale32,
@@ -52,6 +97,37 @@ fn luma(c: ColorU8) -> f64 {
     (0.2126 * red + 0.7152 * green + 0.0722 * blue).clamp(0., 1.)
 }
 
+// Reduces the pixmap to the given number of gray levels with Floyd-Steinberg
+// error diffusion. Panels which show few levels would band gradients and lose
+// thin anti-aliased lines otherwise.
+fn dither(pixmap: &Pixmap, levels: u8) -> Vec<u8> {
+    let width = pixmap.width() as usize;
+    let step = 1. / (levels - 1) as f64;
+    let mut values: Vec<f64> = pixmap.pixels().iter().map(|p| luma(p.demultiply())).collect();
+    let mut gray = vec![0; values.len()];
+    for i in 0..values.len() {
+        let (x, last_row) = (i % width, i + width >= values.len());
+        let old = values[i].clamp(0., 1.);
+        let new = (old / step).round() * step;
+        gray[i] = (new * u8::MAX as f64).round() as u8;
+
+        let error = old - new;
+        if x + 1 < width {
+            values[i + 1] += error * 7. / 16.;
+        }
+        if !last_row {
+            if x > 0 {
+                values[i + width - 1] += error * 3. / 16.;
+            }
+            values[i + width] += error * 5. / 16.;
+            if x + 1 < width {
+                values[i + width + 1] += error / 16.;
+            }
+        }
+    }
+    gray
+}
+
 fn invert(c: ColorU8) -> ColorU8 {
     ColorU8::from_rgba(
         u8::MAX - c.red(),
@@ -71,38 +147,26 @@ fn write_varint<const N: usize>(dst: &mut [u8], mut src: [u8; N]) {
     dst[..N].copy_from_slice(&src)
 }
 
+// Writes the lowest `len` bytes of `src` to the slice `dst` in native endian order.
+fn write_uint(dst: &mut [u8], src: u32, len: usize) {
+    #[cfg(target_endian = "little")]
+    dst[..len].copy_from_slice(&src.to_le_bytes()[..len]);
+    #[cfg(target_endian = "big")]
+    dst[..len].copy_from_slice(&src.to_be_bytes()[4 - len..]);
+}
+
 impl PixelFormat {
-    fn draw(&self, c: ColorU8, buf: &mut [u8]) {
+    fn draw(&self, c: ColorU8, buf: &mut [u8], bits_per_pixel: PixelDepth) {
         match self {
-            PixelFormat::RGBA8888 => {
-                write_varint(buf, [c.red(), c.green(), c.blue(), c.alpha()]);
-            }
-            PixelFormat::BGRA8888 => {
-                write_varint(buf, [c.blue(), c.green(), c.red(), c.alpha()]);
-            }
-            PixelFormat::RGB888 => {
-                write_varint(buf, [c.red(), c.green(), c.blue()]);
-            }
-            PixelFormat::BGR888 => {
-                write_varint(buf, [c.blue(), c.green(), c.red()]);
-            }
-            PixelFormat::RGB565 => {
-                write_varint(
-                    buf,
-                    [
-                        (c.red() & 0b1111_1000) | (c.green() & 0b0000_0111 >> 5),
-                        (c.green() & 0b1110_0000) | (c.blue() & 0b0001_1111 >> 3),
-                    ],
-                );
-            }
-            PixelFormat::BGR565 => {
-                write_varint(
-                    buf,
-                    [
-                        (c.blue() & 0b1111_1000) | (c.green() & 0b0000_0111 >> 5),
-                        (c.green() & 0b1110_0000) | (c.red() & 0b0001_1111 >> 3),
-                    ],
-                );
+            PixelFormat::Rgb {
+                red,
+                green,
+                blue,
+                transp,
+            } => {
+                let v =
+                    red.encode(c.red()) | green.encode(c.green()) | blue.encode(c.blue()) | transp.encode(c.alpha());
+                write_uint(buf, v, bits_per_pixel as usize / 8);
             }
             PixelFormat::Grayscale8 => {
                 let v = (luma(c) * u8::MAX as f64).round() as u8;
@@ -130,44 +194,30 @@ fn pixel_format(var_screeninfo: fb_var_screeninfo) -> Result<(PixelFormat, Pixel
         };
     }
 
-    let r = var_screeninfo.red;
-    let g = var_screeninfo.green;
-    let b = var_screeninfo.blue;
-    let a = var_screeninfo.transp;
-
-    let pixel_format = (
-        (r.offset, r.length, r.msb_right),
-        (g.offset, g.length, g.msb_right),
-        (b.offset, b.length, b.msb_right),
-        (a.offset, a.length, a.msb_right),
-    );
-
-    const NONE: (u32, u32, u32) = (0, 0, 0);
-
-    const BIT8_0: (u32, u32, u32) = (0, 8, 0);
-    const BIT8_1: (u32, u32, u32) = (8, 8, 0);
-    const BIT8_2: (u32, u32, u32) = (16, 8, 0);
-    const BIT8_3: (u32, u32, u32) = (24, 8, 0);
-
-    const BIT5_0: (u32, u32, u32) = (0, 5, 0);
-    const BIT6_1: (u32, u32, u32) = (5, 6, 0);
-    const BIT5_2: (u32, u32, u32) = (11, 5, 0);
-
-    match var_screeninfo.bits_per_pixel {
-        bpp @ (32 | 24) => match pixel_format {
-            (BIT8_0, BIT8_1, BIT8_2, BIT8_3) => Ok((PixelFormat::RGBA8888, bpp)),
-            (BIT8_2, BIT8_1, BIT8_0, BIT8_3) => Ok((PixelFormat::BGRA8888, bpp)),
-            (BIT8_0, BIT8_1, BIT8_2, NONE) => Ok((PixelFormat::RGB888, bpp)),
-            (BIT8_2, BIT8_1, BIT8_0, NONE) => Ok((PixelFormat::BGR888, bpp)),
-            _ => Err(Error::UnsupportedPixelFormat),
-        },
-        bpp @ 16 => match pixel_format {
-            (BIT5_0, BIT6_1, BIT5_2, NONE) => Ok((PixelFormat::RGB565, bpp)),
-            (BIT5_2, BIT6_1, BIT5_0, NONE) => Ok((PixelFormat::BGR565, bpp)),
-            _ => Err(Error::UnsupportedPixelFormat),
-        },
-        _ => Err(Error::UnsupportedPixelDepth),
+    let bpp = match var_screeninfo.bits_per_pixel {
+        bpp @ (8 | 16 | 24 | 32) => bpp,
+        _ => return Err(Error::UnsupportedPixelDepth),
+    };
+
+    let red = Channel::from_bitfield(var_screeninfo.red);
+    let green = Channel::from_bitfield(var_screeninfo.green);
+    let blue = Channel::from_bitfield(var_screeninfo.blue);
+    let transp = Channel::from_bitfield(var_screeninfo.transp);
+
+    let channels = [red, green, blue, transp];
+    if channels.iter().any(|c| !c.fits_in(bpp)) || channels[..3].iter().all(|c| c.length == 0) {
+        return Err(Error::UnsupportedPixelFormat);
     }
+
+    Ok((
+        PixelFormat::Rgb {
+            red,
+            green,
+            blue,
+            transp,
+        },
+        bpp,
+    ))
 }
 
 fn device_id<'a>(fix_screeninfo: &'a fb_fix_screeninfo) -> Cow<'a, str> {
@@ -179,22 +229,106 @@ bitflags! {
     struct DeviceFeatures: u32 {
         const INVERTED_COLOR = 0b0000_0001;
         const KINDLE_LEGACY_EINK_REFRESH = 0b0000_0010;
+        const DITHER_GRAY16 = 0b0000_0100;
+        const DITHER_MONO = 0b0000_1000;
     }
 }
 
 impl DeviceFeatures {
     fn from_id(device_id: &str) -> Self {
         match device_id {
-            "eink_fb" => DeviceFeatures::KINDLE_LEGACY_EINK_REFRESH | DeviceFeatures::INVERTED_COLOR,
+            "eink_fb" => Preset::Kindle4.features(),
             _ => DeviceFeatures::empty(),
         }
     }
+
+    // Number of gray levels the panel can show, if it needs dithering
+    fn gray_levels(self) -> Option<u8> {
+        if self.contains(DeviceFeatures::DITHER_MONO) {
+            Some(2)
+        } else if self.contains(DeviceFeatures::DITHER_GRAY16) {
+            Some(16)
+        } else {
+            None
+        }
+    }
+}
+
+// Known devices whose quirks are applied in addition to the ones detected from
+// the framebuffer id, e.g. if the driver reports a generic id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    Kindle4,
+    KoboClara,
+    Waveshare7in5,
+}
+
+impl Preset {
+    pub fn screen_size(self) -> (u32, u32) {
+        match self {
+            Preset::Kindle4 => (600, 800),
+            Preset::KoboClara => (1072, 1448),
+            Preset::Waveshare7in5 => (800, 480),
+        }
+    }
+
+    // Templates are expected in portrait orientation, so landscape panels are
+    // rotated, leaving the screen size as reported by the device
+    pub fn rotation(self) -> ScreenRotation {
+        match self {
+            Preset::Kindle4 | Preset::KoboClara => ScreenRotation::None,
+            Preset::Waveshare7in5 => ScreenRotation::Clockwise,
+        }
+    }
+
+    // Full refreshes are only supported through the legacy Kindle interface
+    pub fn eink_refresh_rate(self) -> u32 {
+        match self {
+            Preset::Kindle4 => 5,
+            Preset::KoboClara | Preset::Waveshare7in5 => 0,
+        }
+    }
+
+    fn features(self) -> DeviceFeatures {
+        match self {
+            Preset::Kindle4 => DeviceFeatures::KINDLE_LEGACY_EINK_REFRESH | DeviceFeatures::INVERTED_COLOR,
+            Preset::KoboClara => DeviceFeatures::DITHER_GRAY16,
+            Preset::Waveshare7in5 => DeviceFeatures::DITHER_MONO,
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kindle4" => Ok(Preset::Kindle4),
+            "kobo-clara" => Ok(Preset::KoboClara),
+            "waveshare-7in5" => Ok(Preset::Waveshare7in5),
+            _ => Err(format!(
+                "Unknown device {:?}, expected kindle4, kobo-clara, or waveshare-7in5",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Preset::Kindle4 => f.write_str("kindle4"),
+            Preset::KoboClara => f.write_str("kobo-clara"),
+            Preset::Waveshare7in5 => f.write_str("waveshare-7in5"),
+        }
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Builder {
     device: PathBuf,
     eink_refresh_rate: u32,
+    features: Option<DeviceFeatures>,
 }
 
 impl Builder {
@@ -202,9 +336,16 @@ impl Builder {
         Builder {
             device: device,
             eink_refresh_rate: 0,
+            features: None,
         }
     }
 
+    // Applies the quirks of the device in addition to the detected ones
+    pub fn preset(mut self, preset: Preset) -> Self {
+        self.features = Some(preset.features());
+        self
+    }
+
     pub fn eink_refresh_rate(mut self, rate: u32) -> Self {
         self.eink_refresh_rate = rate;
         self
@@ -213,6 +354,10 @@ impl Builder {
     pub fn open(self) -> Result<Framebuffer, Error> {
         let mut fb = Framebuffer::open(self.device)?;
         fb.eink_refresh_rate = self.eink_refresh_rate;
+        if let Some(features) = self.features {
+            fb.features |= features;
+            debug!("Using device features {:?}", fb.features);
+        }
         Ok(fb)
     }
 }
@@ -304,18 +449,26 @@ impl Framebuffer {
         (self.xres, self.yres)
     }
 
-    pub fn draw(&mut self, pixmap: Pixmap) -> Result<(), Error> {
+    pub fn draw(&mut self, pixmap: &Pixmap) -> Result<(), Error> {
         let pixel_len = self.bits_per_pixel / 8;
+        let gray = self.features.gray_levels().map(|levels| dither(pixmap, levels));
         for y in 0..pixmap.height().min(self.yres) {
             for x in 0..pixmap.width().min(self.xres) {
                 let offset = (y * self.stride) + (x * pixel_len);
 
-                let mut c = pixmap.pixel(x, y).expect("invalid pixel").demultiply();
+                let mut c = match &gray {
+                    Some(gray) => {
+                        let v = gray[(y * pixmap.width() + x) as usize];
+                        ColorU8::from_rgba(v, v, v, u8::MAX)
+                    }
+                    None => pixmap.pixel(x, y).expect("invalid pixel").demultiply(),
+                };
                 if self.features.contains(DeviceFeatures::INVERTED_COLOR) {
                     c = invert(c)
                 }
 
-                self.pixel_format.draw(c, &mut self.buf[offset as usize..]);
+                self.pixel_format
+                    .draw(c, &mut self.buf[offset as usize..], self.bits_per_pixel);
             }
         }
 
@@ -1,71 +1,108 @@
+use std::cell::RefCell;
 use std::fmt::Debug;
 use std::fs;
+use std::os::fd::AsRawFd;
 
-use std::path::Path;
-use std::path::PathBuf;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::rc::Rc;
 use std::thread;
 use std::time::Duration;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
-use anyhow::bail;
-use anyhow::{format_err, Context};
+use anyhow::Context;
 use log::debug;
 use log::error;
+use log::info;
+use log::warn;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{SigSet, Signal};
 use tiny_skia::Pixmap;
 
+use crate::control::{Command, DbusService, HttpServer, SocketServer, Status};
 use crate::document::Document;
-use crate::evdev::KeyDeviceBuilder;
-use crate::framebuffer::Framebuffer;
-use crate::rendering::Renderer;
-use crate::scripting::Script;
-use crate::sleep::Sleeper;
+use crate::evdev::{KeyCode, KeyDeviceBuilder, KeyEvent};
+use crate::exit::{Classify, Failure};
+use crate::framebuffer::{Framebuffer, Preset};
+use crate::logfile::RotatingFile;
+use crate::rendering::{Renderer, ScaleMode, ScreenRotation};
+use crate::scripting::{Script, Wakeup};
 use crate::sleep::WakeupReason;
+use crate::sleep::{KeyAction, KeyBinding, Rect, Sleeper, TapBinding, TapTarget};
 use crate::timer::Timer;
+use crate::timing::{TimingLog, Timings};
+use crate::watch::Watcher;
 
+mod control;
+mod daemon;
 mod document;
 mod evdev;
+mod exit;
 mod framebuffer;
+mod logfile;
+mod mqtt;
 mod opts;
 mod rendering;
 mod scripting;
 mod sleep;
+mod systemd;
 mod timer;
+mod timing;
+mod watch;
 
 #[derive(Debug)]
 enum Canvas {
     Framebuffer(Framebuffer),
-    Image(PathBuf),
+    Image {
+        path: PathBuf,
+        // size the image is rendered at, instead of the size of the template
+        size: Option<(u32, u32)>,
+    },
+    // bitmaps are discarded when validating
+    None,
 }
 
 impl Canvas {
-    fn from_opts(ouput: opts::Output) -> Result<Self, anyhow::Error> {
+    fn from_opts(ouput: opts::Output, preset: Option<Preset>) -> Result<Self, anyhow::Error> {
         Ok(match ouput {
             opts::Output::Framebuffer {
                 device,
                 eink_refresh_rate,
+                on_crash: _,
             } => {
-                let fb = framebuffer::Builder::with_device(device)
-                    .eink_refresh_rate(eink_refresh_rate)
-                    .open()?;
-                Canvas::Framebuffer(fb)
+                let eink_refresh_rate = eink_refresh_rate.or(preset.map(Preset::eink_refresh_rate));
+                let mut builder =
+                    framebuffer::Builder::with_device(device).eink_refresh_rate(eink_refresh_rate.unwrap_or(5));
+                if let Some(preset) = preset {
+                    builder = builder.preset(preset);
+                }
+                Canvas::Framebuffer(builder.open()?)
             }
-            opts::Output::Image(path) => Canvas::Image(path),
+            // the framebuffer reports its own size, images are rendered at the size of the device
+            opts::Output::Image { path, size } => Canvas::Image {
+                path,
+                size: size.or(preset.map(Preset::screen_size)),
+            },
+            opts::Output::Validate { .. } => Canvas::None,
         })
     }
 
     fn screen_size(&self) -> Option<(u32, u32)> {
         match self {
             Canvas::Framebuffer(fb) => Some(fb.screen_size()),
-            Canvas::Image(_path) => None,
+            Canvas::Image { size, .. } => *size,
+            Canvas::None => None,
         }
     }
 
-    fn draw(&mut self, bitmap: Pixmap) -> Result<(), anyhow::Error> {
+    fn draw(&mut self, bitmap: &Pixmap) -> Result<(), anyhow::Error> {
         debug!("Drawing bitmap with {}x{} pixels", bitmap.width(), bitmap.height());
 
         match self {
             Canvas::Framebuffer(fb) => fb.draw(bitmap)?,
-            Canvas::Image(path) => bitmap.save_png(path)?,
+            Canvas::Image { path, .. } => bitmap.save_png(path)?,
+            Canvas::None => {}
         };
         Ok(())
     }
@@ -92,120 +129,886 @@ impl WaitForNetwork {
             thread::sleep(Self::INTERVAL)
         }
 
-        Err(format_err!(
+        Err(Failure::Network.error(format!(
             "Timed out waiting for network: Unable to reach host {:?} after {:?}",
-            self.host,
-            self.timeout
-        ))
+            self.host, self.timeout
+        )))
     }
 }
 
+// Returns the current local time for display in error messages
+fn timestamp() -> String {
+    match tzdb::now::local() {
+        Ok(now) => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            now.year(),
+            now.month(),
+            now.month_day(),
+            now.hour(),
+            now.minute(),
+            now.second()
+        ),
+        Err(_) => String::from("unknown time"),
+    }
+}
+
+#[derive(Debug)]
+struct Retry {
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl Retry {
+    const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+    // Returns the delay before the given retry attempt, doubling with each attempt
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.backoff.saturating_mul(factor).min(Self::MAX_BACKOFF)
+    }
+}
+
+#[derive(Debug)]
+struct ErrorPanel {
+    element: String,
+}
+
+impl ErrorPanel {
+    // Built-in panel used if the template has no element for error messages
+    const FALLBACK: &'static str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}">
+  <rect width="100%" height="100%" fill="#ffffff"/>
+  <g font-family="sans-serif" font-size="20" fill="#000000">
+    <text id="error-time" x="20" y="40"/>
+    <text id="error" x="20" y="80"/>
+  </g>
+</svg>"##;
+
+    // Draws the error into the designated element of the template, or onto the
+    // built-in panel if the template has no such element or cannot be rendered
+    fn show(
+        &self,
+        template: &Document,
+        renderer: &mut Renderer,
+        canvas: &mut Canvas,
+        err: &anyhow::Error,
+    ) -> Result<(), anyhow::Error> {
+        let lines = [format!("Error at {}", timestamp()), format!("{:#}", err)];
+
+        let designated = self.designated(template, &lines).and_then(|doc| match doc {
+            Some(doc) => draw_document(doc, renderer, canvas).map(|()| true),
+            None => Ok(false),
+        });
+        match designated {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(err) => warn!("Failed to show error in element `{}`: {:#}", self.element, err),
+        }
+
+        let doc = self.builtin(template, renderer, &lines)?;
+        draw_document(doc, renderer, canvas)
+    }
+
+    fn designated(&self, template: &Document, lines: &[String]) -> Result<Option<Document>, anyhow::Error> {
+        let doc = template.deep_copy();
+        let target = doc.select_by_attr("id", &self.element)?;
+        if target.is_empty() {
+            return Ok(None);
+        }
+
+        target.text_lines(lines, 1.25)?;
+        target.attr("display", "inline")?;
+        Ok(Some(doc))
+    }
+
+    fn builtin(&self, template: &Document, renderer: &Renderer, lines: &[String]) -> Result<Document, anyhow::Error> {
+        // Use the rendered size of the template, unless the template itself is broken
+        let (width, height) = renderer.document_size(template).unwrap_or_else(|err| {
+            debug!("Using default size for error panel: {:#}", err);
+            (600, 800)
+        });
+        let svg = Self::FALLBACK
+            .replace("{width}", &width.to_string())
+            .replace("{height}", &height.to_string());
+        let doc = Document::from_bytes(svg)?;
+        let max_width = width as f64 - 40.;
+        doc.select_by_attr("id", "error-time")?.text(&lines[0])?;
+        let target = doc.select_by_attr("id", "error")?;
+        target.text(&lines[1])?;
+        target.push_wrap_text(max_width, 1.25)?;
+        Ok(doc)
+    }
+}
+
+#[derive(Debug)]
+struct Fallback {
+    template: Document,
+    after: u32,
+}
+
+impl Fallback {
+    // Fills in elements of the fallback template bound to `failed-since` and `error`
+    fn document(&self, failed_since: &str, err: &anyhow::Error) -> Result<Document, anyhow::Error> {
+        let doc = self.template.deep_copy();
+        doc.bind(|key| match key {
+            "failed-since" => Some(failed_since.to_string()),
+            "error" => Some(format!("{:#}", err)),
+            _ => None,
+        })?;
+        Ok(doc)
+    }
+}
+
+// Renders and draws an error screen
+fn draw_document(doc: Document, renderer: &mut Renderer, canvas: &mut Canvas) -> Result<(), anyhow::Error> {
+    let bitmap = renderer
+        .render(doc, &mut Timings::new())
+        .context("Failed to render error screen")?;
+    canvas.draw(bitmap)
+}
+
+#[derive(Debug, Clone)]
+struct CrashScreen {
+    device: PathBuf,
+    action: opts::CrashAction,
+    renderer: rendering::Configuration,
+    // removed when exiting on a signal, which skips dropping the guard in main
+    pid_file: Option<PathBuf>,
+}
+
+impl CrashScreen {
+    const STAMP: &'static str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="40">
+  <rect width="100%" height="100%" fill="#000000"/>
+  <text id="message" x="10" y="27" font-family="sans-serif" font-size="20" fill="#ffffff"/>
+</svg>"##;
+
+    // Draws a banner with the message across the top of the screen, or clears it
+    fn draw(&self, message: &str) -> Result<(), anyhow::Error> {
+        let mut fb = framebuffer::Builder::with_device(self.device.clone()).open()?;
+        let (width, height) = fb.screen_size();
+
+        match self.action {
+            opts::CrashAction::None => {}
+            opts::CrashAction::Clear => {
+                let mut pixmap = Pixmap::new(width, height).context("Invalid screen size")?;
+                pixmap.fill(tiny_skia::Color::WHITE);
+                fb.draw(&pixmap)?;
+            }
+            opts::CrashAction::Stamp => {
+                let doc = Document::from_bytes(Self::STAMP.replace("{width}", &width.to_string()))?;
+                doc.select_by_attr("id", "message")?.text(message)?;
+                let mut renderer = Renderer::from_config(self.renderer.clone());
+                let bitmap = renderer.render(doc, &mut Timings::new())?;
+                fb.draw(bitmap)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Draws onto the screen if the program panics or is terminated by a signal
+    fn install(self) -> Result<(), anyhow::Error> {
+        let screen = self.clone();
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+            if let Err(err) = screen.draw(&format!("Crashed at {}", timestamp())) {
+                error!("Failed to draw crash screen: {:#}", err);
+            }
+        }));
+
+        // Signals are blocked in all threads and received by a dedicated one
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGINT);
+        signals.add(Signal::SIGTERM);
+        signals.add(Signal::SIGQUIT);
+        signals.thread_block()?;
+        thread::spawn(move || {
+            let signal = match signals.wait() {
+                Ok(signal) => signal,
+                Err(err) => return error!("Failed to wait for signals: {}", err),
+            };
+            debug!("Received {}. Exiting", signal);
+            systemd::notify("STOPPING=1");
+            if let Err(err) = self.draw(&format!("Stopped at {}", timestamp())) {
+                error!("Failed to draw crash screen: {:#}", err);
+            }
+            if let Some(path) = &self.pid_file {
+                daemon::remove_pid_file(path);
+            }
+            process::exit(128 + signal as i32);
+        });
+
+        Ok(())
+    }
+}
+
+fn load_template(path: &Path) -> Result<Document, anyhow::Error> {
+    Ok(Document::from_bytes(fs::read(path)?)?)
+}
+
+// Templates requested remotely must be given on the command line or be inside
+// the resources directory, so that clients cannot load arbitrary files
+fn check_remote_template(
+    path: &Path,
+    templates: &[PathBuf],
+    resources_dir: Option<&Path>,
+) -> Result<(), anyhow::Error> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve template {:?}", path))?;
+    let listed = templates
+        .iter()
+        .filter_map(|t| t.canonicalize().ok())
+        .any(|t| t == canonical);
+    let inside = resources_dir
+        .and_then(|dir| dir.canonicalize().ok())
+        .is_some_and(|dir| canonical.starts_with(dir));
+    match listed || inside {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!(
+            "Template {:?} is neither given on the command line nor inside the resources directory",
+            path
+        )),
+    }
+}
+
+// Loads the template and injects the optional stylesheet into it
+fn load_document(template: &Path, stylesheet: Option<&Path>) -> Result<Document, anyhow::Error> {
+    debug!("Loading document: {:?}", template);
+    let doc = load_template(template).classify(Failure::Template, || {
+        format!("Failed to load template {:?}", template.to_string_lossy())
+    })?;
+    if let Some(stylesheet) = stylesheet {
+        debug!("Loading stylesheet: {:?}", stylesheet);
+        let css = fs::read_to_string(stylesheet).classify(Failure::Template, || {
+            format!("Failed to load stylesheet {:?}", stylesheet.to_string_lossy())
+        })?;
+        doc.inject_css(&css)?;
+    }
+    Ok(doc)
+}
+
+fn watcher_from_opts(opts: &opts::Options) -> Result<Watcher, anyhow::Error> {
+    let mut watcher = Watcher::new()?;
+    for template in &opts.templates {
+        watcher.file(template)?;
+    }
+    if let Some(stylesheet) = &opts.stylesheet {
+        watcher.file(stylesheet)?;
+    }
+    for script in &opts.scripting.scripts {
+        watcher.file(script)?;
+    }
+
+    // resources are resolved relative to the first template by default
+    let resources_dir = match &opts.resources_dir {
+        Some(dir) => dir.clone(),
+        None => opts.templates[0]
+            .canonicalize()?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+    watcher.dir(&resources_dir)?;
+    if let opts::Output::Image { path, .. } = &opts.output {
+        watcher.ignore(path);
+    }
+
+    Ok(watcher)
+}
+
 fn sleeper_from_opts(sleep: opts::Sleep) -> Result<Sleeper, anyhow::Error> {
-    let ticker = match sleep.suspend {
-        true => Timer::realtime_alarm(sleep.wakeup_rtc)?,
-        false => Timer::monotonic()?,
+    let ticker = match (sleep.wakeup_and_exit, sleep.suspend) {
+        // alarm timers are disarmed on exit, so the RTC has to be used
+        (true, _) => Timer::rtc(&sleep.wakeup_rtc).classify(Failure::Device, || "Failed to open RTC alarm")?,
+        (false, true) => {
+            Timer::realtime_alarm(&sleep.wakeup_rtc).classify(Failure::Device, || "Failed to open RTC alarm")?
+        }
+        (false, false) => Timer::monotonic().classify(Failure::Device, || "Failed to create timer")?,
     };
 
-    let mut sleeper = Sleeper::new(sleep.duration, ticker);
+    let mut sleeper = Sleeper::new(sleep.interval, ticker);
     if sleep.suspend {
         sleeper.suspend(true);
         sleeper.suspend_grace(sleep.suspend_grace);
+        sleeper.suspend_on_power(!sleep.no_suspend_on_power);
+    }
+    if let Some(quiet_hours) = sleep.quiet_hours {
+        sleeper.quiet_hours(quiet_hours);
+    }
+    sleeper.rules(sleep.rules);
+    if let Some(interval) = systemd::watchdog_interval() {
+        sleeper.watchdog(interval);
+    }
+    if let Some(jitter) = sleep.jitter {
+        sleeper.jitter(jitter);
+    }
+    if let Some(chunk) = sleep.wakeup_chunk {
+        sleeper.chunk(chunk);
+    }
+    if let Some(interval) = sleep.carousel {
+        sleeper.carousel(interval);
     }
+    let mut key_groups = Vec::new();
     if let Some(e) = sleep.exit_on_keypress {
-        let key_devices = KeyDeviceBuilder::with_keys(e.keys)
-            .find(&e.devices)
-            .context("Failed to access input devices")?;
-        sleeper.wakeup_keys(key_devices);
+        let bindings = e.keys.into_iter().map(|key| KeyBinding {
+            keys: vec![key],
+            long_press: false,
+            action: KeyAction::Exit,
+        });
+        key_groups.push((bindings.collect::<Vec<_>>(), e.devices));
+    }
+    if let Some(r) = sleep.refresh_on_keypress {
+        let bindings = r.keys.into_iter().map(|key| KeyBinding {
+            keys: vec![key],
+            long_press: false,
+            action: KeyAction::Refresh,
+        });
+        key_groups.push((bindings.collect(), r.devices));
+    }
+    if let Some(k) = sleep.key_bindings {
+        key_groups.push((k.bindings, k.devices));
+    }
+    for (bindings, devices) in key_groups {
+        sleeper
+            .bind_keys(&devices, bindings)
+            .classify(Failure::Device, || "Failed to access input devices")?;
+    }
+    if let Some(t) = sleep.touch_bindings {
+        sleeper
+            .touch_devices(&t.devices)
+            .classify(Failure::Device, || "Failed to access touchscreen")?;
+        sleeper.bind_swipes(t.swipes);
+    }
+    if let Some(m) = sleep.mqtt {
+        sleeper.subscribe(mqtt::Client::new(&m.broker, m.topics));
+    }
+    if let Some(addr) = sleep.wakeup_udp {
+        sleeper
+            .listen_udp(addr)
+            .classify(Failure::Device, || "Failed to set up UDP wakeup")?;
     }
 
     Ok(sleeper)
 }
 
+// Resolves tap targets to regions of the rendered document. Elements which
+// are not part of the document are skipped
+fn tap_regions(bindings: &[TapBinding], renderer: &Renderer) -> Vec<(Rect, KeyAction)> {
+    bindings
+        .iter()
+        .filter_map(|binding| {
+            let rect = match &binding.target {
+                TapTarget::Rect(rect) => *rect,
+                TapTarget::Element(id) => {
+                    let Some(bbox) = renderer.element_bounds(id) else {
+                        debug!("Tap target #{} not found in document", id);
+                        return None;
+                    };
+                    Rect {
+                        x: bbox.x(),
+                        y: bbox.y(),
+                        width: bbox.width(),
+                        height: bbox.height(),
+                    }
+                }
+            };
+            Some((rect, binding.action.clone()))
+        })
+        .collect()
+}
+
+// Template shown on one page of the carousel
+struct Page {
+    path: PathBuf,
+    template: Document,
+}
+
 enum ControlFlow {
-    Continue,
+    Continue(Wakeup),
     Exit,
+    Terminate(Signal),
+    Reload,
+    Switch(PathBuf),
+    Pause(bool),
 }
 
 fn run(
-    template: &[u8],
-    template_path: &Path,
+    template: &Document,
     wait_for_network: &Option<WaitForNetwork>,
-    script: &Option<Script>,
-    renderer: &Renderer,
+    script: Option<&Script>,
+    renderer: &mut Renderer,
     canvas: &mut Canvas,
-    sleeper: &Option<Sleeper>,
-) -> Result<ControlFlow, anyhow::Error> {
-    // Parse document template
-    let mut doc = Document::from_bytes(template)
-        .with_context(|| format!("Failed to load template {:?}", template_path.to_string_lossy()))?;
+    catch_script_errors: bool,
+    timing_log: &mut Option<TimingLog>,
+) -> Result<Option<Duration>, anyhow::Error> {
+    let mut timings = Timings::new();
+
+    // Copy the parsed document template
+    let mut doc = template.deep_copy();
 
     // Wait for network before running script
     if let Some(w) = wait_for_network {
-        w.wait_for_network()?;
+        timings.measure("network", || w.wait_for_network())?;
     }
 
     // Manipulate document tree with user script
+    let mut next_refresh = None;
     if let Some(script) = script {
-        doc = match script.run_with_document(doc) {
-            Ok(doc) => doc,
-            Err(err) if err.is_catchable() && sleeper.is_some() => {
+        doc = match timings.measure("script", || script.run_with_document(doc)) {
+            Ok(output) => {
+                next_refresh = output.next_refresh;
+                output.document
+            }
+            Err(err) if err.is_catchable() && catch_script_errors => {
                 error!("Uncaught script error: {}", err);
-                return Ok(ControlFlow::Continue);
+                return Ok(None);
             }
-            Err(err) => bail!("Failed to execute script: {}", err),
+            Err(err) => return Err(Failure::Script.error(format!("Failed to execute script: {}", err))),
         };
     }
 
     // Render and draw document
-    let bitmap = renderer.render(doc).context("Failed to render document")?;
-    canvas.draw(bitmap)?;
+    let bitmap = renderer
+        .render(doc, &mut timings)
+        .classify(Failure::Render, || "Failed to render document")?;
+    timings
+        .measure("draw", || canvas.draw(bitmap))
+        .classify(Failure::Device, || "Failed to draw document")?;
 
-    // Sleep or exit
-    let Some(sleeper) = sleeper else {
-        return Ok(ControlFlow::Exit);
-    };
+    debug!("Frame timings: {}", timings);
+    if let Some(timing_log) = timing_log {
+        timing_log.write(&timings).context("Failed to write timing log")?;
+    }
+
+    Ok(next_refresh)
+}
 
-    debug!("Sleeping for {:?}", sleeper.duration());
-    let wakeup_reason = sleeper.wait().context("Failed to sleep")?;
-    if let WakeupReason::ExitKeyPressed(code) = wakeup_reason {
-        debug!("Key {} pressed. Exiting", code);
-        return Ok(ControlFlow::Exit);
+// Runs the script against each page once. Failing pages are logged, and the
+// last error is returned to exit with its code
+fn validate_pages(
+    pages: &[Page],
+    scripts: &mut [Script],
+    renderer: &mut Renderer,
+    canvas: &mut Canvas,
+) -> Result<ExitCode, anyhow::Error> {
+    let shared = scripts.len() == 1;
+    let mut failed = None;
+    for (page, Page { path, template }) in pages.iter().enumerate() {
+        let mut script = scripts.get_mut(if shared { 0 } else { page });
+        if let Some(script) = &mut script {
+            script.set_wakeup(&Wakeup::new("start"));
+            script.set_constant("PAGE", page as i64);
+        }
+        let res = run(template, &None, script.as_deref(), renderer, canvas, false, &mut None);
+        match res.with_context(|| format!("Template {:?} failed validation", path.to_string_lossy())) {
+            Ok(_) => info!("Template {:?} is valid", path.to_string_lossy()),
+            Err(err) => {
+                if let Some(previous) = failed.replace(err) {
+                    error!("{:?}", previous);
+                }
+            }
+        }
+    }
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(ExitCode::SUCCESS),
     }
+}
 
-    Ok(ControlFlow::Continue)
+fn sleep(sleeper: &Sleeper, next_refresh: Option<Duration>) -> Result<ControlFlow, anyhow::Error> {
+    let wakeup_reason = sleeper
+        .wait(next_refresh)
+        .classify(Failure::Device, || "Failed to sleep")?;
+    match wakeup_reason {
+        WakeupReason::KeyPressed(code, KeyAction::Exit) => {
+            debug!("Key {} pressed. Exiting", code);
+            Ok(ControlFlow::Exit)
+        }
+        WakeupReason::KeyPressed(code, action) => {
+            debug!("Key {} pressed. Refreshing with action {}", code, action);
+            Ok(ControlFlow::Continue(Wakeup {
+                reason: "key",
+                key: Some(code.to_string()),
+                action: Some(action.to_string()),
+                payload: None,
+            }))
+        }
+        WakeupReason::Tapped(KeyAction::Exit) => {
+            debug!("Screen tapped. Exiting");
+            Ok(ControlFlow::Exit)
+        }
+        WakeupReason::Tapped(action) => {
+            debug!("Screen tapped. Refreshing with action {}", action);
+            Ok(ControlFlow::Continue(Wakeup {
+                action: Some(action.to_string()),
+                ..Wakeup::new("tap")
+            }))
+        }
+        WakeupReason::Swiped(direction, KeyAction::Exit) => {
+            debug!("Screen swiped {}. Exiting", direction);
+            Ok(ControlFlow::Exit)
+        }
+        WakeupReason::Swiped(direction, action) => {
+            debug!("Screen swiped {}. Refreshing with action {}", direction, action);
+            Ok(ControlFlow::Continue(Wakeup {
+                action: Some(action.to_string()),
+                ..Wakeup::new("swipe")
+            }))
+        }
+        WakeupReason::ReloadRequested => {
+            debug!("Received SIGHUP. Reloading");
+            Ok(ControlFlow::Reload)
+        }
+        WakeupReason::TerminationRequested(signal) => {
+            debug!("Received {}. Exiting", signal);
+            Ok(ControlFlow::Terminate(signal))
+        }
+        WakeupReason::FilesChanged => {
+            debug!("Files changed. Reloading");
+            Ok(ControlFlow::Reload)
+        }
+        WakeupReason::DatagramReceived(sender) => {
+            debug!("Received datagram from {}. Refreshing", sender);
+            Ok(ControlFlow::Continue(Wakeup::new("network")))
+        }
+        WakeupReason::CommandReceived(Command::Refresh) => {
+            debug!("Refresh requested remotely");
+            Ok(ControlFlow::Continue(Wakeup::new("remote")))
+        }
+        WakeupReason::CommandReceived(Command::SetTemplate(path)) => {
+            debug!("Switching to template {:?}", path);
+            Ok(ControlFlow::Switch(path))
+        }
+        WakeupReason::CommandReceived(Command::Pause) => Ok(ControlFlow::Pause(true)),
+        WakeupReason::CommandReceived(Command::Resume) => Ok(ControlFlow::Pause(false)),
+        WakeupReason::MessageReceived(message) => {
+            debug!("MQTT message received on {}. Refreshing", message.topic);
+            Ok(ControlFlow::Continue(Wakeup {
+                payload: Some(message.payload),
+                ..Wakeup::new("mqtt")
+            }))
+        }
+        WakeupReason::IntervalTick => Ok(ControlFlow::Continue(Wakeup::new("timer"))),
+        WakeupReason::CarouselTick => Ok(ControlFlow::Continue(Wakeup::new("carousel"))),
+    }
 }
 
-fn main() -> Result<(), anyhow::Error> {
+fn main() -> ExitCode {
     dotenvy::dotenv().ok();
-    env_logger::init();
-    let opts = opts::parse();
+    let mode = opts::parse();
+    let logging = match &mode {
+        opts::Mode::Run(opts) => Some(&opts.logging),
+        _ => None,
+    };
+    if let Err(err) = init_logging(logging) {
+        return exit::report(&err, false);
+    }
+
+    let report = |err: anyhow::Error| exit::report(&err, false);
+    let opts = match mode {
+        opts::Mode::Run(opts) => *opts,
+        opts::Mode::ListInputDevices => return list_input_devices().unwrap_or_else(report),
+        opts::Mode::ListKeycodes => return list_keycodes().unwrap_or_else(report),
+        opts::Mode::IdentifyKeys => return identify_keys().unwrap_or_else(report),
+        opts::Mode::Generate(target) => {
+            opts::generate(target);
+            return ExitCode::SUCCESS;
+        }
+    };
+
+    // kept until the dashboard exits, which removes the PID file again
+    let _pid_file = match start_process(&opts) {
+        Ok(pid_file) => pid_file,
+        Err(err) => return exit::report(&err, opts.error_json),
+    };
+
+    let error_json = opts.error_json;
+    let log_to_file = opts.logging.file.is_some();
+    let res = try_main(opts);
+    systemd::notify("STOPPING=1");
+    match res {
+        Ok(code) => code,
+        Err(err) => {
+            // stderr may not be kept, so fatal errors end up in the log file too
+            if log_to_file {
+                error!("{:#}", err);
+            }
+            exit::report(&err, error_json)
+        }
+    }
+}
+
+// Logs to stderr, or to a rotated log file
+fn init_logging(logging: Option<&opts::Logging>) -> Result<(), anyhow::Error> {
+    // number of previous log files kept when rotating
+    const BACKUPS: u32 = 3;
+
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(logging) = logging {
+        builder.format_timestamp(logging.timestamps);
+        if let Some(path) = &logging.file {
+            let file = RotatingFile::open(path, logging.max_size, BACKUPS).classify(Failure::Device, || {
+                format!("Failed to open log file {:?}", path.to_string_lossy())
+            })?;
+            builder.target(env_logger::Target::Pipe(Box::new(file)));
+            builder.write_style(env_logger::WriteStyle::Never);
+        }
+    }
+    builder.init();
+    Ok(())
+}
+
+// Detaches from the terminal and writes the PID file, if requested
+fn start_process(opts: &opts::Options) -> Result<Option<daemon::PidFile>, anyhow::Error> {
+    let pid_file = opts.pid_file.as_deref();
+    let describe = |path: &Path| format!("Failed to write PID file {:?}", path.to_string_lossy());
+    match (opts.daemon, pid_file) {
+        (true, _) => daemon::daemonize(pid_file).context("Failed to run as daemon"),
+        (false, Some(path)) => daemon::PidFile::create(path)
+            .map(Some)
+            .classify(Failure::Device, || describe(path)),
+        (false, None) => Ok(None),
+    }
+}
+
+// Prints the capabilities of each input device, e.g. to pick keys to bind
+fn list_input_devices() -> Result<ExitCode, anyhow::Error> {
+    let paths = evdev::device_paths("/dev/input/event*").context("Failed to list input devices")?;
+    if paths.is_empty() {
+        println!("No input devices found");
+    }
+    for path in paths {
+        match evdev::DeviceInfo::open(&path) {
+            Ok(info) => {
+                println!("{}: {:?}", info.path.display(), info.name);
+                println!("  events: {}", info.event_types.join(" "));
+                if !info.keys.is_empty() {
+                    let keys: Vec<_> = info.keys.iter().map(|k| format!("{} ({})", k, k.code())).collect();
+                    println!("  keys: {}", keys.join(" "));
+                }
+            }
+            Err(err) => println!("{}: {:#}", path.display(), anyhow::Error::new(err)),
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn list_keycodes() -> Result<ExitCode, anyhow::Error> {
+    for (name, code) in KeyCode::NAMED {
+        println!("{} {}", name, code.code());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+// Prints each key pressed on any input device until interrupted
+fn identify_keys() -> Result<ExitCode, anyhow::Error> {
+    let keys = KeyCode::NAMED.iter().map(|(_, code)| *code);
+    let devices: Vec<_> = KeyDeviceBuilder::with_keys(keys)
+        .find("/dev/input/event*")
+        .context("Failed to open input devices")?
+        .collect();
+    if devices.is_empty() {
+        return Err(anyhow::anyhow!("No input devices with keys found"));
+    }
+
+    println!("Press keys to identify them, or Ctrl-C to exit");
+    loop {
+        let mut fds: Vec<_> = devices
+            .iter()
+            .map(|dev| PollFd::new(dev.as_raw_fd(), PollFlags::POLLIN))
+            .collect();
+        poll(&mut fds, -1).context("Failed to wait for input")?;
+        for dev in &devices {
+            while let Some(event) = dev.next_key_event().context("Failed to read input")? {
+                if let KeyEvent::Pressed(code) = event {
+                    println!("{} {} ({})", code, code.code(), dev.device_name().unwrap_or_default());
+                }
+            }
+        }
+    }
+}
+
+fn try_main(opts: opts::Options) -> Result<ExitCode, anyhow::Error> {
+    // SIGHUP reloads the template while sleeping. It has to be blocked before
+    // any other threads are spawned, as they would otherwise receive it. SIGTERM
+    // and SIGINT end the loop, so that the PID file is removed, unless the crash
+    // screen handles them.
+    let crash_screen = matches!(&opts.output, opts::Output::Framebuffer { on_crash, .. } if !matches!(on_crash, opts::CrashAction::None));
+    let reload_signal = match opts.sleep.is_some() {
+        true => {
+            Some(sleep::reload_signal(!crash_screen).classify(Failure::Device, || "Failed to set up reload signal")?)
+        }
+        false => None,
+    };
 
     // Template options
-    debug!("Loading document: {:?}", &opts.template);
-    let template = fs::read(&opts.template)?;
-    let template_path = opts.template.as_path();
+    let mut pages = Vec::new();
+    for path in &opts.templates {
+        pages.push(Page {
+            path: path.clone(),
+            template: load_document(path, opts.stylesheet.as_deref())?,
+        });
+    }
+    let mut page = 0;
+    let mut watcher = match opts.watch {
+        true => Some(watcher_from_opts(&opts).classify(Failure::Device, || "Failed to watch files")?),
+        false => None,
+    };
 
     // Output options
-    let mut canvas = Canvas::from_opts(opts.output)?;
+    let base_dir = opts.templates[0].canonicalize()?.parent().map(|p| p.to_path_buf());
+    let crash_screen = match &opts.output {
+        opts::Output::Framebuffer {
+            on_crash: opts::CrashAction::None,
+            ..
+        } => None,
+        opts::Output::Framebuffer { device, on_crash, .. } => Some(CrashScreen {
+            device: device.clone(),
+            action: *on_crash,
+            renderer: rendering::Configuration {
+                base_dir: base_dir.clone(),
+                resources_dir: None,
+                fonts_dir: opts.fonts_dir.clone(),
+                system_fonts: opts.system_fonts,
+                screen_size: None,
+                supersample: 1.,
+                scale_mode: ScaleMode::None,
+                rotation: ScreenRotation::None,
+            },
+            pid_file: opts.pid_file.clone(),
+        }),
+        opts::Output::Image { .. } | opts::Output::Validate { .. } => None,
+    };
+    if let Some(crash_screen) = crash_screen {
+        crash_screen.install()?;
+    }
+    if let opts::Output::Validate { mocks } = &opts.output {
+        for (url, file) in mocks {
+            scripting::mock_fetch(url.clone(), file.clone());
+        }
+    }
+    let validate = matches!(opts.output, opts::Output::Validate { .. });
+    let mut canvas =
+        Canvas::from_opts(opts.output, opts.device).classify(Failure::Device, || "Failed to open output")?;
 
     // Script options
-    let script = opts.script.map(Script::new);
+    if let Some(dir) = opts.scripting.state_dir {
+        scripting::set_state_dir(dir);
+    }
+    // scripts and remote clients may only access files in here
+    let resources_dir = opts.resources_dir.clone().or_else(|| base_dir.clone());
+    if let Some(dir) = resources_dir.clone() {
+        scripting::set_resources_dir(dir);
+    }
+    if opts.scripting.allow_exec {
+        scripting::allow_exec();
+    }
+    let mut scripts: Vec<Script> = opts.scripting.scripts.into_iter().map(Script::new).collect();
+    for script in &mut scripts {
+        script.set_args(&opts.scripting.args);
+    }
+    if opts.sleep.as_ref().is_some_and(|s| s.mqtt.is_some()) {
+        for script in &mut scripts {
+            // defined before the first message, so scripts can refer to it
+            script.set_constant("mqtt_payload", ());
+        }
+    }
 
     // Template and rendering options
-    let base_dir = opts.template.canonicalize()?.parent().map(|p| p.to_path_buf());
-    let renderer = Renderer::from_config(rendering::Configuration {
+    let mut renderer = Renderer::from_config(rendering::Configuration {
         base_dir: base_dir,
         resources_dir: opts.resources_dir,
         fonts_dir: opts.fonts_dir,
         system_fonts: opts.system_fonts,
         screen_size: canvas.screen_size(),
+        supersample: opts.supersample,
+        scale_mode: opts.scale_mode,
+        rotation: opts.device.map(Preset::rotation).unwrap_or_default(),
     });
 
+    // Validation runs each page once instead of refreshing
+    if validate {
+        return validate_pages(&pages, &mut scripts, &mut renderer, &mut canvas);
+    }
+
     // Sleep options
-    let sleeper = match opts.sleep {
+    let wakeup_and_exit = opts.sleep.as_ref().is_some_and(|s| s.wakeup_and_exit);
+    let listen = opts.sleep.as_ref().and_then(|s| s.listen);
+    let dbus = opts.sleep.as_ref().and_then(|s| s.dbus);
+    let control_socket = opts.sleep.as_ref().and_then(|s| s.control_socket.clone());
+    let tap_bindings = opts
+        .sleep
+        .as_ref()
+        .and_then(|s| s.touch_bindings.as_ref())
+        .map(|t| t.taps.clone())
+        .unwrap_or_default();
+    let mut sleeper = match opts.sleep {
         Some(sleep) => Some(sleeper_from_opts(sleep)?),
         None => None,
     };
+    // Keep running after errors if we refresh periodically or on changes
+    let keep_running = sleeper.is_some() || watcher.is_some();
+    if let Some(sleeper) = &mut sleeper {
+        if let Some(signal) = reload_signal {
+            sleeper.reload_on(signal);
+        }
+        if let Some(watcher) = watcher.take() {
+            sleeper.watch(watcher);
+        }
+    }
+
+    // Remote control options
+    let status = (listen.is_some() || dbus.is_some() || control_socket.is_some()).then(|| {
+        Rc::new(RefCell::new(Status {
+            template: pages[page].path.clone(),
+            ..Default::default()
+        }))
+    });
+    if let (Some(sleeper), Some(addr), Some(status)) = (&mut sleeper, listen, &status) {
+        let server = HttpServer::bind(addr, status.clone())
+            .classify(Failure::Device, || format!("Failed to listen on {}", addr))?;
+        sleeper.serve_http(server);
+    }
+    if let (Some(sleeper), Some(bus), Some(status)) = (&mut sleeper, dbus, &status) {
+        let service = DbusService::connect(bus, status.clone())
+            .classify(Failure::Device, || format!("Failed to connect to the {} bus", bus))?;
+        sleeper.serve_dbus(service);
+    }
+    if let (Some(sleeper), Some(path), Some(status)) = (&mut sleeper, &control_socket, &status) {
+        let server = SocketServer::bind(path, status.clone())
+            .classify(Failure::Device, || format!("Failed to listen on {:?}", path))?;
+        sleeper.serve_control(server);
+    }
+    let mut paused = false;
+
+    // Diagnostic options
+    let mut timing_log = match opts.timing_log {
+        Some(path) => Some(
+            TimingLog::open(&path)
+                .with_context(|| format!("Failed to open timing log {:?}", path.to_string_lossy()))?,
+        ),
+        None => None,
+    };
+
+    // Error options
+    let error_panel = opts.error_panel.map(|e| ErrorPanel { element: e.element });
+    let fallback = match opts.fallback {
+        Some(f) => {
+            debug!("Loading fallback template: {:?}", &f.template);
+            let template = load_template(&f.template).classify(Failure::Template, || {
+                format!("Failed to load fallback template {:?}", f.template.to_string_lossy())
+            })?;
+            Some(Fallback {
+                template,
+                after: f.after,
+            })
+        }
+        None => None,
+    };
+    let retry = opts.retry.map(|r| Retry {
+        attempts: r.attempts,
+        backoff: r.backoff,
+    });
 
     // Network options
     let wait_for_network = opts.wait_for_network.map(|w| WaitForNetwork {
@@ -213,23 +1016,228 @@ fn main() -> Result<(), anyhow::Error> {
         timeout: w.timeout,
     });
 
+    // Script errors are logged and ignored when refreshing periodically, unless
+    // they are shown on the error panel or fallback template. Retries come first.
+    let catch_script_errors = keep_running && error_panel.is_none() && fallback.is_none();
+
+    let mut attempt = 0;
+    let mut failures = 0;
+    let mut failed_since = None;
+    let mut ready = false;
+    let mut iteration = 0;
+    let mut wakeup = Wakeup::new("start");
     loop {
+        // A single script is shared by all pages
+        let shared = scripts.len() == 1;
+        let mut script = scripts.get_mut(if shared { 0 } else { page });
+
+        // Let the script know why it is run, and which page is shown
+        if let Some(script) = &mut script {
+            script.set_wakeup(&wakeup);
+            script.set_constant("PAGE", page as i64);
+        }
+
+        // Let the script know which sleep rule is active
+        if let (Some(script), Some(sleeper)) = (&mut script, &sleeper) {
+            let rule = sleeper.active_rule().map(|r| r.to_string()).unwrap_or_default();
+            script.set_constant("SLEEP_RULE", rule);
+        }
+
         let res = run(
-            &template,
-            template_path,
+            &pages[page].template,
             &wait_for_network,
-            &script,
-            &renderer,
+            script.as_deref(),
+            &mut renderer,
             &mut canvas,
-            &sleeper,
+            catch_script_errors && retry.as_ref().is_none_or(|r| attempt >= r.attempts),
+            &mut timing_log,
         );
 
+        // Let systemd know we are alive, and ready once the first draw succeeded
+        systemd::notify("WATCHDOG=1");
+        if res.is_ok() && !ready {
+            systemd::notify("READY=1");
+            ready = true;
+        }
+
+        let next_refresh = *res.as_ref().unwrap_or(&None);
+        let mut retry_delay = None;
+        match (&res, retry.as_ref().filter(|r| attempt < r.attempts)) {
+            (Err(err), Some(retry)) => {
+                let delay = retry.delay(attempt);
+                attempt += 1;
+                warn!("{:#}", err);
+                info!("Retrying in {:?} (attempt {}/{})", delay, attempt, retry.attempts);
+                retry_delay = Some(delay);
+            }
+            _ => attempt = 0,
+        }
+
+        if let Some(status) = &status {
+            let mut status = status.borrow_mut();
+            match &res {
+                Ok(_) => {
+                    status.last_render = Some(SystemTime::now());
+                    status.last_error = None;
+                }
+                Err(err) => status.last_error = Some(format!("{:#}", err)),
+            }
+        }
+
         match res {
-            Ok(ControlFlow::Continue) => continue,
-            Ok(ControlFlow::Exit) => break,
-            Err(err) => return Err(err),
+            // the error is handled once all retries have failed
+            Err(_) if retry_delay.is_some() => {}
+            Err(err) => {
+                failures += 1;
+                let failed_since: &str = failed_since.get_or_insert_with(timestamp);
+
+                // Show the error on screen and keep going if we are refreshing periodically.
+                // Failing to do so is logged, as it must not stop the loop either.
+                let shown = match (&fallback, &error_panel) {
+                    (Some(fallback), _) if failures >= fallback.after => Some(
+                        fallback
+                            .document(failed_since, &err)
+                            .and_then(|doc| draw_document(doc, &mut renderer, &mut canvas)),
+                    ),
+                    (_, Some(error_panel)) => {
+                        Some(error_panel.show(&pages[page].template, &mut renderer, &mut canvas, &err))
+                    }
+                    _ => None,
+                };
+                if let Some(Err(err)) = &shown {
+                    error!("Failed to show error screen: {:#}", err);
+                }
+                let shown = shown.is_some();
+                if !keep_running || (!shown && fallback.is_none()) {
+                    return Err(err);
+                }
+                error!("{:#}", err);
+            }
+            Ok(_) => {
+                failures = 0;
+                failed_since = None;
+            }
+        }
+
+        if retry_delay.is_none() {
+            iteration += 1;
+            if opts.iterations.is_some_and(|n| iteration >= n) {
+                debug!("Completed {} iterations. Exiting", iteration);
+                break;
+            }
+        }
+
+        // Leave sleeping to an external script
+        if let Some(sleeper) = sleeper.as_ref().filter(|_| wakeup_and_exit && retry_delay.is_none()) {
+            let wakeup = sleeper
+                .program_wakeup(next_refresh)
+                .classify(Failure::Device, || "Failed to set wakeup alarm")?;
+            info!("Wakeup alarm set for {}", humantime::format_rfc3339_seconds(wakeup));
+            break;
+        }
+
+        // Tap regions follow the elements of the last rendered document
+        if let Some(sleeper) = sleeper.as_mut().filter(|_| !tap_bindings.is_empty()) {
+            let regions = tap_regions(&tap_bindings, &renderer);
+            sleeper.tap_regions(regions, renderer.output_size().unwrap_or_default());
+        }
+
+        // Show the bitmap currently on screen to remote clients
+        if let Some(status) = &status {
+            status.borrow_mut().image = renderer.last_render().cloned();
+        }
+
+        // Sleep, wait for changes, or exit
+        let flow = loop {
+            let flow = match (&sleeper, &watcher) {
+                // exit keys and remote commands are still handled while waiting to retry
+                (Some(sleeper), _) => sleep(sleeper, retry_delay.or(next_refresh))?,
+                (None, _) if retry_delay.is_some() => {
+                    thread::sleep(retry_delay.unwrap_or_default());
+                    ControlFlow::Continue(wakeup.clone())
+                }
+                (None, Some(watcher)) => {
+                    watcher.wait().classify(Failure::Device, || "Failed to watch files")?;
+                    debug!("Files changed. Reloading");
+                    ControlFlow::Reload
+                }
+                // refresh immediately if a number of iterations was requested
+                (None, None) if opts.iterations.is_some() => ControlFlow::Continue(Wakeup::new("timer")),
+                (None, None) => break None,
+            };
+            match flow {
+                ControlFlow::Pause(pause) => {
+                    paused = pause;
+                    if let Some(status) = &status {
+                        status.borrow_mut().paused = pause;
+                    }
+                    if pause {
+                        debug!("Pausing periodic refreshes");
+                        continue;
+                    }
+                    debug!("Resuming periodic refreshes");
+                    break Some(ControlFlow::Continue(Wakeup::new("remote")));
+                }
+                // other wakeups still refresh while paused
+                ControlFlow::Continue(Wakeup { reason: "timer", .. }) if paused => {
+                    debug!("Paused. Skipping periodic refresh");
+                }
+                flow => break Some(flow),
+            }
+        };
+        let Some(flow) = flow else {
+            break;
+        };
+        match flow {
+            ControlFlow::Exit => return Ok(ExitCode::from(exit::KEY_PRESSED)),
+            ControlFlow::Terminate(signal) => return Ok(ExitCode::from(128 + signal as u8)),
+            ControlFlow::Reload => {
+                for page in &mut pages {
+                    match load_document(&page.path, opts.stylesheet.as_deref()) {
+                        Ok(doc) => page.template = doc,
+                        Err(err) => error!("Failed to reload template, keeping the previous one: {:#}", err),
+                    }
+                }
+                renderer.invalidate();
+                wakeup = Wakeup::new("reload");
+            }
+            ControlFlow::Switch(path) => {
+                let doc = check_remote_template(&path, &opts.templates, resources_dir.as_deref())
+                    .and_then(|()| load_document(&path, opts.stylesheet.as_deref()));
+                match doc {
+                    Ok(doc) => {
+                        renderer.invalidate();
+                        if let Some(status) = &status {
+                            status.borrow_mut().template = path.clone();
+                        }
+                        pages[page] = Page { path, template: doc };
+                    }
+                    Err(err) => error!("Failed to switch template, keeping the previous one: {:#}", err),
+                }
+                wakeup = Wakeup::new("reload");
+            }
+            ControlFlow::Continue(reason) => {
+                let next = match (reason.reason, reason.action.as_deref()) {
+                    ("carousel", _) | (_, Some("next-page")) => (page + 1) % pages.len(),
+                    (_, Some("previous-page")) => (page + pages.len() - 1) % pages.len(),
+                    _ => page,
+                };
+                if next != page {
+                    debug!("Showing page {} of {}", next + 1, pages.len());
+                    page = next;
+                    if let Some(status) = &status {
+                        status.borrow_mut().template = pages[page].path.clone();
+                    }
+                    // the new page is shown for a full carousel interval
+                    if let Some(sleeper) = &sleeper {
+                        sleeper.restart_carousel();
+                    }
+                }
+                wakeup = reason;
+            }
+            ControlFlow::Pause(_) => unreachable!(),
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
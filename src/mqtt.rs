@@ -0,0 +1,269 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use log::debug;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to connect to MQTT broker {0:?}: {1}")]
+    Connect(String, io::Error),
+    #[error("MQTT broker rejected connection with return code {0}")]
+    Rejected(u8),
+    #[error("MQTT broker rejected subscription to {0:?}")]
+    SubscriptionRejected(String),
+    #[error("Unexpected MQTT packet type {0}")]
+    UnexpectedPacket(u8),
+    #[error("Malformed MQTT packet")]
+    Malformed,
+    #[error("MQTT packet of {0} bytes exceeds the size limit")]
+    TooLarge(usize),
+    #[error("MQTT connection failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+// Larger packets are rejected instead of allocating what the broker claims
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+// Message received on a subscribed topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+}
+
+fn push_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend((s.len() as u16).to_be_bytes());
+    buf.extend(s.as_bytes());
+}
+
+fn read_string(buf: &[u8]) -> Result<(String, &[u8]), Error> {
+    let [hi, lo, rest @ ..] = buf else {
+        return Err(Error::Malformed);
+    };
+    let len = u16::from_be_bytes([*hi, *lo]) as usize;
+    if rest.len() < len {
+        return Err(Error::Malformed);
+    }
+    let (s, rest) = rest.split_at(len);
+    Ok((String::from_utf8_lossy(s).into_owned(), rest))
+}
+
+// Minimal MQTT 3.1.1 client which subscribes to topics with QoS 0. A lost
+// connection is re-established before the next sleep, e.g. after the broker
+// dropped it while the device was suspended
+pub struct Client {
+    broker: String,
+    topics: Vec<String>,
+    client_id: String,
+    stream: Option<TcpStream>,
+    // wall clock time, as it advances while the device is suspended
+    last_sent: SystemTime,
+}
+
+impl Client {
+    // Timeout for the broker to answer, and for the rest of a started packet
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    const DEFAULT_PORT: u16 = 1883;
+    // The broker closes the connection after 1.5 times this without packets
+    const KEEP_ALIVE: Duration = Duration::from_secs(60);
+    // Interval in which keep_alive has to be called
+    pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+    pub fn new(broker: &str, topics: impl IntoIterator<Item = String>) -> Self {
+        let broker = match broker.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => broker.to_string(),
+            _ => format!("{}:{}", broker, Self::DEFAULT_PORT),
+        };
+        Client {
+            broker,
+            topics: topics.into_iter().collect(),
+            client_id: format!("oikos-{}", process::id()),
+            stream: None,
+            last_sent: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    // File descriptor of the connection, if connected
+    pub fn fd(&self) -> Option<RawFd> {
+        self.stream.as_ref().map(|s| s.as_raw_fd())
+    }
+
+    // Connects and subscribes to the topics, unless already connected
+    pub fn connect(&mut self) -> Result<(), Error> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        debug!("Connecting to MQTT broker {}", self.broker);
+        let mut stream = self
+            .connect_stream()
+            .map_err(|err| Error::Connect(self.broker.clone(), err))?;
+        stream.set_read_timeout(Some(Self::TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::TIMEOUT))?;
+
+        let mut connect = Vec::new();
+        push_string(&mut connect, "MQTT");
+        connect.push(4); // protocol level 3.1.1
+        connect.push(0x02); // clean session
+        connect.extend((Self::KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        push_string(&mut connect, &self.client_id);
+        write_packet(&mut stream, CONNECT << 4, &connect)?;
+
+        match read_packet(&mut stream)? {
+            (CONNACK, body) if body.len() == 2 && body[1] == 0 => {}
+            (CONNACK, body) => return Err(Error::Rejected(body.get(1).copied().unwrap_or(0))),
+            (kind, _) => return Err(Error::UnexpectedPacket(kind)),
+        }
+
+        let mut subscribe = Vec::new();
+        subscribe.extend(1u16.to_be_bytes()); // packet identifier
+        for topic in &self.topics {
+            push_string(&mut subscribe, topic);
+            subscribe.push(0); // QoS 0
+        }
+        write_packet(&mut stream, SUBSCRIBE << 4 | 0x02, &subscribe)?;
+
+        match read_packet(&mut stream)? {
+            (SUBACK, body) => {
+                // return codes follow the packet identifier, 0x80 marks a failure
+                let codes = body.get(2..).unwrap_or_default();
+                if let Some(pos) = codes.iter().position(|&code| code == 0x80) {
+                    return Err(Error::SubscriptionRejected(self.topics[pos].clone()));
+                }
+            }
+            (kind, _) => return Err(Error::UnexpectedPacket(kind)),
+        }
+
+        debug!("Subscribed to MQTT topics {:?}", self.topics);
+        self.stream = Some(stream);
+        self.last_sent = SystemTime::now();
+        Ok(())
+    }
+
+    // Tries each address of the broker, as a blocking connect can take minutes
+    // to time out, e.g. while the network is down
+    fn connect_stream(&self) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address found");
+        for addr in self.broker.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, Self::TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    // Sends a ping if nothing has been sent for the ping interval, so that the
+    // broker keeps the connection open. The connection is closed if this fails
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+        let idle = self.last_sent.elapsed().unwrap_or_default();
+        if idle < Self::PING_INTERVAL {
+            return Ok(());
+        }
+
+        let result = write_packet(stream, PINGREQ << 4, &[]);
+        match result {
+            Ok(()) => self.last_sent = SystemTime::now(),
+            Err(_) => self.stream = None,
+        }
+        result
+    }
+
+    // Reads all pending packets and returns the last received message. The
+    // connection is closed if this fails
+    pub fn receive(&mut self) -> Result<Option<Message>, Error> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(None);
+        };
+
+        let result = read_messages(stream);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+fn write_packet(stream: &mut TcpStream, header: u8, body: &[u8]) -> Result<(), Error> {
+    let mut packet = vec![header];
+    // remaining length is encoded with 7 bits per byte
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    stream.write_all(&packet)?;
+    Ok(())
+}
+
+// Reads a packet and returns its type and the body after the fixed header
+fn read_packet(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), Error> {
+    let mut header = [0u8];
+    stream.read_exact(&mut header)?;
+
+    let mut len = 0usize;
+    for shift in (0..4).map(|n| n * 7) {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            if len > MAX_PACKET_SIZE {
+                return Err(Error::TooLarge(len));
+            }
+            let mut body = vec![0u8; len];
+            stream.read_exact(&mut body)?;
+            return Ok((header[0] >> 4, body));
+        }
+    }
+
+    Err(Error::Malformed)
+}
+
+fn read_messages(stream: &mut TcpStream) -> Result<Option<Message>, Error> {
+    let mut message = None;
+    loop {
+        // check for more data without blocking
+        stream.set_nonblocking(true)?;
+        let pending = stream.peek(&mut [0u8]);
+        stream.set_nonblocking(false)?;
+        match pending {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(message),
+            Err(err) => return Err(err.into()),
+        }
+
+        match read_packet(stream)? {
+            (PUBLISH, body) => {
+                let (topic, payload) = read_string(&body)?;
+                debug!("Received MQTT message on {:?}", topic);
+                message = Some(Message {
+                    topic,
+                    payload: String::from_utf8_lossy(payload).into_owned(),
+                });
+            }
+            (PINGRESP, _) => {}
+            (kind, _) => return Err(Error::UnexpectedPacket(kind)),
+        }
+    }
+}
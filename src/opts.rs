@@ -1,15 +1,41 @@
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use bpaf::{construct, long, positional, Parser};
+use bpaf::doc::Section;
+use bpaf::{construct, long, positional, Args, OptionParser, Parser, ShellComp};
+use env_logger::TimestampPrecision;
 use humantime;
 
+use crate::control::DbusBus;
 use crate::evdev::KeyCode;
+use crate::framebuffer::Preset;
+use crate::rendering::ScaleMode;
+use crate::sleep::{Interval, KeyBinding, Schedule, SleepRule, SwipeBinding, TapBinding, TimeRange};
+use crate::timer::RtcDevice;
 
 #[derive(Debug)]
 pub enum Output {
-    Framebuffer { device: PathBuf, eink_refresh_rate: u32 },
-    Image(PathBuf),
+    Framebuffer {
+        device: PathBuf,
+        eink_refresh_rate: Option<u32>,
+        on_crash: CrashAction,
+    },
+    Image {
+        path: PathBuf,
+        size: Option<(u32, u32)>,
+    },
+    // Runs each page once without drawing, fetching the given URLs from files
+    Validate {
+        mocks: Vec<(String, PathBuf)>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CrashAction {
+    None,
+    Clear,
+    Stamp,
 }
 
 fn framebuffer() -> impl Parser<Output> {
@@ -24,24 +50,68 @@ fn framebuffer() -> impl Parser<Output> {
         .fallback(PathBuf::from("/dev/fb0"));
     let eink_refresh_rate = long("framebuffer-eink-refresh")
         .env("OIKOS_FRAMEBUFFER_EINK_REFRESH")
-        .help("Refresh e-ink backed framebuffers every N updates (default: 5)")
+        .help("Refresh e-ink backed framebuffers every N updates (default: 5, or as set by --device)")
         .argument::<u32>("N")
-        .fallback(5);
+        .optional();
+    let on_crash = long("framebuffer-on-crash")
+        .env("OIKOS_FRAMEBUFFER_ON_CRASH")
+        .help("Stamp or clear the screen on crashes and termination: stamp, clear, or none (default: none)")
+        .argument::<String>("ACTION")
+        .parse(|s| match s.as_str() {
+            "stamp" => Ok(CrashAction::Stamp),
+            "clear" => Ok(CrashAction::Clear),
+            "none" => Ok(CrashAction::None),
+            _ => Err(format!("Unknown crash action {:?}", s)),
+        })
+        .fallback(CrashAction::None);
 
     let output = construct!(Output::Framebuffer {
         device,
-        eink_refresh_rate
+        eink_refresh_rate,
+        on_crash,
     });
 
     construct!(framebuffer, output).map(|((), output)| output)
 }
 
+fn validate() -> impl Parser<Output> {
+    let validate = long("validate")
+        .env("OIKOS_VALIDATE")
+        .help("Run the script against each template once and report errors without drawing or sleeping")
+        .req_flag(());
+    let mocks = long("validate-mock")
+        .env("OIKOS_VALIDATE_MOCK")
+        .help("Return the contents of FILE when the script fetches URL, e.g. https://example.com/api=response.json")
+        .argument::<String>("URL=FILE")
+        .parse(|s| match s.rsplit_once('=') {
+            Some((url, file)) if !url.is_empty() && !file.is_empty() => Ok((url.to_string(), PathBuf::from(file))),
+            _ => Err(format!("Expected URL=FILE, got {:?}", s)),
+        })
+        .many();
+    construct!(validate, mocks).map(|((), mocks)| Output::Validate { mocks })
+}
+
+// Parses a size such as 600x800
+fn parse_size(s: String) -> Result<(u32, u32), String> {
+    let size = s
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        .filter(|&(w, h)| w > 0 && h > 0);
+    size.ok_or(format!("Expected WxH, got {:?}", s))
+}
+
 fn image() -> impl Parser<Output> {
-    long("image")
+    let path = long("image")
         .env("OIKOS_IMAGE")
         .help("Write resulting image to PNG file")
-        .argument::<PathBuf>("FILE")
-        .map(Output::Image)
+        .argument::<PathBuf>("FILE");
+    let size = long("size")
+        .env("OIKOS_SIZE")
+        .help("Size of the image, e.g. 600x800 (default: size of the template)")
+        .argument::<String>("WxH")
+        .parse(parse_size)
+        .optional();
+    construct!(Output::Image { path, size })
 }
 
 #[derive(Debug)]
@@ -50,13 +120,53 @@ pub struct ExitOnKeypress {
     pub devices: String,
 }
 
+#[derive(Debug)]
+pub struct RefreshOnKeypress {
+    pub keys: Vec<KeyCode>,
+    pub devices: String,
+}
+
+#[derive(Debug)]
+pub struct KeyBindings {
+    pub bindings: Vec<KeyBinding>,
+    pub devices: String,
+}
+
+#[derive(Debug)]
+pub struct TouchBindings {
+    pub taps: Vec<TapBinding>,
+    pub swipes: Vec<SwipeBinding>,
+    pub devices: String,
+}
+
+#[derive(Debug)]
+pub struct Mqtt {
+    pub broker: String,
+    pub topics: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct Sleep {
-    pub duration: Duration,
+    pub interval: Interval,
     pub suspend: bool,
     pub suspend_grace: Duration,
-    pub wakeup_rtc: PathBuf,
+    pub no_suspend_on_power: bool,
+    pub wakeup_rtc: RtcDevice,
+    pub quiet_hours: Option<TimeRange>,
+    pub rules: Vec<SleepRule>,
+    pub wakeup_chunk: Option<Duration>,
+    pub jitter: Option<Duration>,
+    pub wakeup_and_exit: bool,
     pub exit_on_keypress: Option<ExitOnKeypress>,
+    pub refresh_on_keypress: Option<RefreshOnKeypress>,
+    pub key_bindings: Option<KeyBindings>,
+    pub touch_bindings: Option<TouchBindings>,
+    pub wakeup_udp: Option<SocketAddr>,
+    pub listen: Option<SocketAddr>,
+    pub dbus: Option<DbusBus>,
+    pub control_socket: Option<PathBuf>,
+    pub carousel: Option<Duration>,
+    pub mqtt: Option<Mqtt>,
 }
 
 fn sleep() -> impl Parser<Option<Sleep>> {
@@ -65,6 +175,21 @@ fn sleep() -> impl Parser<Option<Sleep>> {
         .help("Sleep and refresh image with this interval")
         .argument::<String>("DURATION")
         .parse(|s| humantime::parse_duration(&s));
+    let align = long("sleep-align")
+        .env("OIKOS_SLEEP_ALIGN")
+        .help("Refresh at multiples of the sleep duration on the wall clock, e.g. every full minute")
+        .switch();
+    let duration = construct!(duration, align).map(|(duration, align)| match align {
+        true => Interval::Aligned(duration),
+        false => Interval::Fixed(duration),
+    });
+    let schedule = long("schedule")
+        .env("OIKOS_SCHEDULE")
+        .help("Sleep and refresh image at times matching this cron expression")
+        .argument::<String>("CRON")
+        .parse(|s| s.parse::<Schedule>())
+        .map(Interval::Schedule);
+    let interval = construct!([duration, schedule]);
     let suspend = long("suspend")
         .env("OIKOS_SUSPEND")
         .help("Suspend to RAM while sleeping")
@@ -75,11 +200,47 @@ fn sleep() -> impl Parser<Option<Sleep>> {
         .argument::<String>("DURATION")
         .fallback(String::from("1s"))
         .parse(|s| humantime::parse_duration(&s));
+    let no_suspend_on_power = long("no-suspend-on-power")
+        .env("OIKOS_NO_SUSPEND_ON_POWER")
+        .help("Sleep without suspending to RAM while connected to AC or USB power")
+        .switch();
     let wakeup_rtc = long("wakeup-rtc")
         .env("OIKOS_WAKEUP_RTC")
-        .help("RTC device to wake-up while suspended (default: /dev/rtc0)")
-        .argument::<PathBuf>("DEVICE")
-        .fallback(PathBuf::from("/dev/rtc0"));
+        .help("RTC device to wake-up while suspended without alarm timer support, or \"auto\" to use the first one supporting wakeups (default: auto)")
+        .argument::<RtcDevice>("DEVICE")
+        .fallback(RtcDevice::Auto);
+    let quiet_hours = long("quiet-hours")
+        .env("OIKOS_QUIET_HOURS")
+        .help("Do not refresh during this daily time range, e.g. 23:00-06:00")
+        .argument::<String>("RANGE")
+        .parse(|s| s.parse::<TimeRange>())
+        .optional();
+    let rules = long("sleep-rule")
+        .env("OIKOS_SLEEP_RULE")
+        .help("Use a different sleep duration during a daily time range, e.g. 06:00-09:00=2m")
+        .argument::<String>("RULE")
+        .parse(|s| s.parse::<SleepRule>())
+        .many();
+    let wakeup_chunk = long("wakeup-chunk")
+        .env("OIKOS_WAKEUP_CHUNK")
+        .help("Split long sleeps into alarms of at most this duration to compensate for clock drift")
+        .argument::<String>("DURATION")
+        .parse(|s| humantime::parse_duration(&s))
+        .guard(
+            |d| *d >= Duration::from_secs(10),
+            "Wakeup chunks must be at least 10 seconds",
+        )
+        .optional();
+    let jitter = long("sleep-jitter")
+        .env("OIKOS_SLEEP_JITTER")
+        .help("Delay each refresh by a random duration of up to this maximum")
+        .argument::<String>("DURATION")
+        .parse(|s| humantime::parse_duration(&s))
+        .optional();
+    let wakeup_and_exit = long("wakeup-and-exit")
+        .env("OIKOS_WAKEUP_AND_EXIT")
+        .help("Set the RTC alarm for the next refresh and exit instead of sleeping")
+        .switch();
     let exit_on_keypress_keys = long("exit-on-keypress")
         .env("OIKOS_EXIT_ON_KEYPRESS")
         .help("List of keys which will cause the program to exit when sleeping")
@@ -97,21 +258,161 @@ fn sleep() -> impl Parser<Option<Sleep>> {
     })
     .optional();
 
+    let refresh_on_keypress_keys = long("refresh-on-keypress")
+        .env("OIKOS_REFRESH_ON_KEYPRESS")
+        .help("List of keys which will cause an immediate refresh when sleeping")
+        .argument::<KeyCode>("KEY")
+        .some("No valid keys provided");
+    let refresh_on_keypress_devices = long("refresh-on-keypress-devices")
+        .env("OIKOS_REFRESH_ON_KEYPRESS_DEVICES")
+        .help("Input devices to check for refresh keypresses")
+        .argument::<String>("PATTERN")
+        .fallback(String::from("/dev/input/event*"));
+
+    let refresh_on_keypress = construct!(RefreshOnKeypress{
+        keys(refresh_on_keypress_keys),
+        devices(refresh_on_keypress_devices),
+    })
+    .optional();
+
+    let key_bindings = long("key")
+        .env("OIKOS_KEY")
+        .help("Bind a key to an action when sleeping, e.g. KEY_LEFT=previous KEY_POWER:long=exit for a long press, or KEY_HOME+KEY_POWER=exit for a combination. Other actions than exit and refresh are passed to the script as WAKEUP.action")
+        .argument::<String>("KEY=ACTION")
+        .parse(|s| s.parse::<KeyBinding>())
+        .some("No valid key bindings provided");
+    let key_devices = long("key-devices")
+        .env("OIKOS_KEY_DEVICES")
+        .help("Input devices to check for bound keys")
+        .argument::<String>("PATTERN")
+        .fallback(String::from("/dev/input/event*"));
+
+    let key_bindings = construct!(KeyBindings {
+        bindings(key_bindings),
+        devices(key_devices),
+    })
+    .optional();
+
+    let tap_bindings = long("tap")
+        .env("OIKOS_TAP")
+        .help("Bind a touchscreen region to an action when sleeping, given in screen pixels as X,Y,WIDTH,HEIGHT=ACTION or as the bounding box of an element, e.g. #next-button=next")
+        .argument::<String>("REGION=ACTION")
+        .parse(|s| s.parse::<TapBinding>())
+        .many();
+    let swipe_bindings = long("swipe")
+        .env("OIKOS_SWIPE")
+        .help("Bind a swipe across the touchscreen to an action when sleeping, e.g. left=next or right=previous. Directions are left, right, up, and down")
+        .argument::<String>("DIRECTION=ACTION")
+        .parse(|s| s.parse::<SwipeBinding>())
+        .many();
+    let touch_devices = long("touch-devices")
+        .env("OIKOS_TOUCH_DEVICES")
+        .help("Input devices to check for touchscreen taps and swipes")
+        .argument::<String>("PATTERN")
+        .fallback(String::from("/dev/input/event*"));
+
+    // the touchscreen is only used if any taps or swipes are bound
+    let touch_bindings = construct!(TouchBindings {
+        taps(tap_bindings),
+        swipes(swipe_bindings),
+        devices(touch_devices),
+    })
+    .map(|t| Some(t).filter(|t| !t.taps.is_empty() || !t.swipes.is_empty()));
+
+    let wakeup_udp = long("wakeup-udp")
+        .env("OIKOS_WAKEUP_UDP")
+        .help("Refresh immediately when a datagram is received on this address, e.g. 0.0.0.0:7007. Has no effect while suspended to RAM")
+        .argument::<SocketAddr>("ADDR")
+        .optional();
+
+    let listen = long("listen")
+        .env("OIKOS_LISTEN")
+        .help("Serve HTTP requests on this address while sleeping, e.g. 0.0.0.0:8080. Provides GET /status, GET /image.png, POST /refresh, and POST /template?path=FILE for templates given on the command line or inside the resources directory")
+        .argument::<SocketAddr>("ADDR")
+        .optional();
+
+    let dbus = long("dbus")
+        .env("OIKOS_DBUS")
+        .help("Provide the org.oikos.Dashboard service on the session or system bus while sleeping, with the methods Refresh and SetTemplate, and the properties Template, LastRender, and LastError")
+        .argument::<DbusBus>("BUS")
+        .optional();
+
+    let control_socket = long("control-socket")
+        .env("OIKOS_CONTROL_SOCKET")
+        .help("Accept commands on this Unix socket while sleeping, e.g. /run/oikos.sock. Each connection sends one line with refresh, pause, resume, or status. Only the owner may connect")
+        .argument::<PathBuf>("PATH")
+        .optional();
+
+    let carousel = long("carousel")
+        .env("OIKOS_CAROUSEL")
+        .help("Show the next page with this interval if multiple templates are provided. The key actions next-page and previous-page switch pages immediately")
+        .argument::<String>("DURATION")
+        .parse(|s| humantime::parse_duration(&s))
+        .guard(|d| !d.is_zero(), "Carousel interval must be positive")
+        .optional();
+
+    let mqtt_broker = long("mqtt-broker")
+        .env("OIKOS_MQTT_BROKER")
+        .help("Refresh immediately when a message is published on an MQTT topic of this broker, e.g. 192.168.1.2:1883. The message is passed to the script as mqtt_payload")
+        .argument::<String>("HOST:PORT");
+    let mqtt_topics = long("mqtt-topic")
+        .env("OIKOS_MQTT_TOPIC")
+        .help("MQTT topic to subscribe to, may contain wildcards")
+        .argument::<String>("TOPIC")
+        .some("No MQTT topics provided");
+
+    let mqtt = construct!(Mqtt {
+        broker(mqtt_broker),
+        topics(mqtt_topics),
+    })
+    .optional();
+
     construct!(Sleep {
-        duration,
+        interval,
         suspend,
         suspend_grace,
+        no_suspend_on_power,
         wakeup_rtc,
+        quiet_hours,
+        rules,
+        wakeup_chunk,
+        jitter,
+        wakeup_and_exit,
         exit_on_keypress,
+        refresh_on_keypress,
+        key_bindings,
+        touch_bindings,
+        wakeup_udp,
+        listen,
+        dbus,
+        control_socket,
+        carousel,
+        mqtt,
     })
     .guard(
-        |s| s.duration >= Duration::from_secs(30) || !s.suspend,
+        |s| match s.interval {
+            Interval::Fixed(duration) | Interval::Aligned(duration) => {
+                duration >= Duration::from_secs(30) || !s.suspend
+            }
+            Interval::Schedule(_) => true,
+        },
         "Suspend to RAM requires a --sleep duration of at least 30 seconds",
     )
     .guard(
-        |s| s.suspend_grace < s.duration,
+        |s| match &s.interval {
+            Interval::Fixed(duration) | Interval::Aligned(duration) => s.suspend_grace < *duration,
+            Interval::Schedule(schedule) => s.suspend_grace < schedule.min_interval(),
+        },
         "Suspend grace period may not be longer than overall suspend time",
     )
+    .guard(
+        |s| !s.suspend || s.rules.iter().all(|r| r.duration >= Duration::from_secs(30)),
+        "Suspend to RAM requires --sleep-rule durations of at least 30 seconds",
+    )
+    .guard(
+        |s| s.rules.iter().all(|r| s.suspend_grace < r.duration),
+        "Suspend grace period may not be longer than any --sleep-rule duration",
+    )
     .group_help("Sleep:")
     .optional()
 }
@@ -143,66 +444,396 @@ fn wait_for_network() -> impl Parser<Option<WaitForNetwork>> {
 }
 
 #[derive(Debug)]
-pub struct Options {
-    // Input template
+pub struct ErrorPanel {
+    pub element: String,
+}
+
+fn error_panel() -> impl Parser<Option<ErrorPanel>> {
+    let error_panel = long("error-panel")
+        .env("OIKOS_ERROR_PANEL")
+        .help("Display errors on screen instead of exiting when refreshing periodically")
+        .req_flag(());
+    let element = long("error-panel-element")
+        .env("OIKOS_ERROR_PANEL_ELEMENT")
+        .help("Text element of the template used for error messages (default: error)")
+        .argument::<String>("ID")
+        .fallback(String::from("error"));
+
+    construct!(error_panel, element)
+        .map(|((), element)| ErrorPanel { element })
+        .group_help("Errors:")
+        .optional()
+}
+
+#[derive(Debug)]
+pub struct Fallback {
     pub template: PathBuf,
+    pub after: u32,
+}
+
+fn fallback() -> impl Parser<Option<Fallback>> {
+    let template = long("fallback-template")
+        .env("OIKOS_FALLBACK_TEMPLATE")
+        .help("SVG file displayed without script after repeated failures")
+        .argument::<PathBuf>("FILE");
+    let after = long("fallback-after")
+        .env("OIKOS_FALLBACK_AFTER")
+        .help("Display the fallback template after N consecutive failures (default: 3)")
+        .argument::<u32>("N")
+        .fallback(3)
+        .guard(|n| *n >= 1, "Number of failures must be at least 1");
+
+    construct!(Fallback { template, after })
+        .group_help("Fallback:")
+        .optional()
+}
+
+#[derive(Debug)]
+pub struct Retry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+fn retry() -> impl Parser<Option<Retry>> {
+    let attempts = long("retry")
+        .env("OIKOS_RETRY")
+        .help("Retry failed refreshes up to N times before giving up")
+        .argument::<u32>("N");
+    let backoff = long("retry-backoff")
+        .env("OIKOS_RETRY_BACKOFF")
+        .help("Delay before the first retry, doubled for each further retry (default: 5s)")
+        .argument::<String>("DURATION")
+        .parse(|s| humantime::parse_duration(&s))
+        .fallback(Duration::from_secs(5));
+
+    construct!(Retry { attempts, backoff }).group_help("Retry:").optional()
+}
+
+#[derive(Debug)]
+pub struct Logging {
+    pub file: Option<PathBuf>,
+    pub max_size: u64,
+    pub timestamps: Option<TimestampPrecision>,
+}
+
+fn logging() -> impl Parser<Logging> {
+    let file = long("log-file")
+        .env("OIKOS_LOG_FILE")
+        .help("Write log messages to this file instead of stderr. Set RUST_LOG to choose the level, e.g. RUST_LOG=info")
+        .argument::<PathBuf>("FILE")
+        .optional();
+    let max_size = long("log-max-size")
+        .env("OIKOS_LOG_MAX_SIZE")
+        .help("Rotate the log file once it exceeds this size, keeping three previous files (default: 1048576)")
+        .argument::<u64>("BYTES")
+        .guard(|n| *n > 0, "Maximum log size must be positive")
+        .fallback(1024 * 1024);
+    let timestamps = long("log-timestamps")
+        .env("OIKOS_LOG_TIMESTAMPS")
+        .help("Precision of timestamps in log messages: none, seconds, or millis (default: seconds)")
+        .argument::<String>("PRECISION")
+        .parse(|s| match s.as_str() {
+            "none" => Ok(None),
+            "seconds" => Ok(Some(TimestampPrecision::Seconds)),
+            "millis" => Ok(Some(TimestampPrecision::Millis)),
+            _ => Err(format!("Unknown timestamp precision {:?}", s)),
+        })
+        .fallback(Some(TimestampPrecision::Seconds));
+
+    construct!(Logging {
+        file,
+        max_size,
+        timestamps
+    })
+    .group_help("Logging:")
+}
+
+#[derive(Debug)]
+pub struct Scripting {
+    // either shared by all pages or one per page
+    pub scripts: Vec<PathBuf>,
+    pub args: Vec<(String, String)>,
+    pub state_dir: Option<PathBuf>,
+    pub allow_exec: bool,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    // Input templates, one per page
+    pub templates: Vec<PathBuf>,
     // Dynamic scripting
-    pub script: Option<PathBuf>,
+    pub scripting: Scripting,
     pub sleep: Option<Sleep>,
     pub wait_for_network: Option<WaitForNetwork>,
+    pub error_panel: Option<ErrorPanel>,
+    pub retry: Option<Retry>,
+    pub fallback: Option<Fallback>,
     // Resources for rendering
     pub resources_dir: Option<PathBuf>,
     pub fonts_dir: Option<PathBuf>,
     pub system_fonts: bool,
+    pub supersample: f32,
+    pub scale_mode: ScaleMode,
+    pub stylesheet: Option<PathBuf>,
+    pub watch: bool,
     // Output canvas
     pub output: Output,
+    pub device: Option<Preset>,
+    // Process management
+    pub daemon: bool,
+    pub pid_file: Option<PathBuf>,
+    // Diagnostics
+    pub logging: Logging,
+    pub timing_log: Option<PathBuf>,
+    pub error_json: bool,
+    pub iterations: Option<usize>,
 }
 
 fn options() -> impl Parser<Options> {
-    let template = positional("TEMPLATE").help("SVG file to be displayed");
-    let template_env = long("template")
+    let template = positional("TEMPLATE")
+        .help("SVG file to be displayed")
+        .complete_shell(ShellComp::File { mask: Some("*.svg") })
+        .map(|t| vec![t]);
+    let templates = long("template")
         .env("OIKOS_TEMPLATE")
+        .help("SVG file of a page to be displayed, may be repeated for multiple pages")
         .argument::<PathBuf>("TEMPLATE")
-        .hide();
-    let template = construct!([template_env, template]);
+        .complete_shell(ShellComp::File { mask: Some("*.svg") })
+        .some("No template provided");
+    let templates = construct!([templates, template]);
 
-    let output = construct!([image(), framebuffer()]).group_help("Output:");
+    let output = construct!([image(), framebuffer(), validate()]).group_help("Output:");
+    let device = long("device")
+        .env("OIKOS_DEVICE")
+        .help("Apply the screen size, rotation, e-ink refresh rate, color inversion, and dithering of a device: kindle4, kobo-clara, or waveshare-7in5 (rotated for portrait templates)")
+        .argument::<Preset>("DEVICE")
+        .optional();
 
-    let script = long("script")
+    let scripts = long("script")
         .env("OIKOS_SCRIPT")
-        .help("Script used to modify the template before rendering")
+        .help("Script used to modify the template before rendering. If repeated, each page uses the script at its position")
         .argument::<PathBuf>("FILE")
-        .group_help("Scripting:")
+        .complete_shell(ShellComp::File { mask: Some("*.rhai") })
+        .many();
+    let args = long("script-arg")
+        .env("OIKOS_SCRIPT_ARG")
+        .help("Pass an argument to the script, available as args.KEY, e.g. city=Zurich")
+        .argument::<String>("KEY=VALUE")
+        .parse(|s| match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(format!("Expected KEY=VALUE, got {:?}", s)),
+        })
+        .many();
+    let state_dir = long("state-dir")
+        .env("OIKOS_STATE_DIR")
+        .help("Directory where scripts keep data across runs, e.g. values set with store::set, responses of fetch for caching and conditional requests, and persistent cookies")
+        .argument::<PathBuf>("DIR")
+        .complete_shell(ShellComp::Dir { mask: None })
         .optional();
+    let allow_exec = long("allow-exec")
+        .env("OIKOS_ALLOW_EXEC")
+        .help("Allow scripts to run commands with exec, e.g. to read the battery level")
+        .switch();
+    let scripting = construct!(Scripting {
+        scripts,
+        args,
+        state_dir,
+        allow_exec
+    })
+    .group_help("Scripting:");
 
     let resources_dir = long("resources")
         .env("OIKOS_RESOURCES")
         .help("Directory used for resolving relative paths")
         .argument::<PathBuf>("DIR")
+        .complete_shell(ShellComp::Dir { mask: None })
         .optional();
     let fonts_dir = long("fonts")
         .env("OIKOS_FONTS")
         .help("Load fonts from this directory")
         .argument::<PathBuf>("DIR")
+        .complete_shell(ShellComp::Dir { mask: None })
         .optional();
     let system_fonts = long("system-fonts")
         .env("OIKOS_SYSTEM_FONTS")
         .help("Search for additional fonts in system directories")
         .switch();
+    let supersample = long("supersample")
+        .env("OIKOS_SUPERSAMPLE")
+        .help("Render at FACTOR times the resolution, up to 4, and downscale the result (default: 1)")
+        .argument::<f32>("FACTOR")
+        .fallback(1.)
+        .guard(
+            |f| (1. ..=4.).contains(f),
+            "Supersampling factor must be between 1 and 4",
+        );
+    let scale_mode = long("scale-mode")
+        .env("OIKOS_SCALE_MODE")
+        .help("Scale template to the screen size: fit, fill, stretch, center, or none (default: none)")
+        .argument::<String>("MODE")
+        .parse(|s| match s.as_str() {
+            "fit" => Ok(ScaleMode::Fit),
+            "fill" => Ok(ScaleMode::Fill),
+            "stretch" => Ok(ScaleMode::Stretch),
+            "center" => Ok(ScaleMode::Center),
+            "none" => Ok(ScaleMode::None),
+            _ => Err(format!("Unknown scale mode {:?}", s)),
+        })
+        .fallback(ScaleMode::None);
+
+    let stylesheet = long("stylesheet")
+        .env("OIKOS_STYLESHEET")
+        .help("Add CSS from this file to the template before rendering")
+        .argument::<PathBuf>("FILE")
+        .optional();
+
+    let watch = long("watch")
+        .env("OIKOS_WATCH")
+        .help("Refresh whenever the template, stylesheet, script, or resources change")
+        .switch();
+
+    let daemon = long("daemon")
+        .env("OIKOS_DAEMON")
+        .help("Detach from the terminal and run in the background. Use --log-file to keep log messages")
+        .switch();
+    let pid_file = long("pid-file")
+        .env("OIKOS_PID_FILE")
+        .help("Write the process ID to this file, e.g. for init scripts")
+        .argument::<PathBuf>("FILE")
+        .optional();
+
+    let timing_log = long("timing-log")
+        .env("OIKOS_TIMING_LOG")
+        .help("Append the duration of each render stage as a JSON line to this file")
+        .argument::<PathBuf>("FILE")
+        .optional();
+
+    let error_json = long("error-json")
+        .env("OIKOS_ERROR_JSON")
+        .help("Report fatal errors as a JSON object on stderr")
+        .switch();
+
+    let iterations = long("iterations")
+        .env("OIKOS_ITERATIONS")
+        .help("Exit after refreshing this many times")
+        .argument::<usize>("N")
+        .guard(|n| *n > 0, "Number of iterations must be positive")
+        .optional();
 
     construct!(Options {
         output,
-        script,
+        device,
+        scripting,
         sleep(),
         wait_for_network(),
+        error_panel(),
+        retry(),
+        fallback(),
         resources_dir,
         fonts_dir,
         system_fonts,
+        supersample,
+        scale_mode,
+        stylesheet,
+        watch,
+        daemon,
+        pid_file,
+        logging(),
+        timing_log,
+        error_json,
+        iterations,
         // positional argument at the end
-        template,
+        templates,
     })
+    .guard(
+        |o| o.scripting.scripts.len() <= 1 || o.scripting.scripts.len() == o.templates.len(),
+        "Either a single script or one script per template has to be provided",
+    )
+}
+
+// Diagnostic modes run instead of the dashboard
+#[derive(Debug)]
+pub enum Mode {
+    Run(Box<Options>),
+    ListInputDevices,
+    ListKeycodes,
+    IdentifyKeys,
+    Generate(Generate),
+}
+
+// Files which can be generated for packaging
+#[derive(Debug, Clone, Copy)]
+pub enum Generate {
+    Bash,
+    Zsh,
+    Fish,
+    Man,
+}
+
+fn mode() -> impl Parser<Mode> {
+    let list_input_devices = long("list-input-devices")
+        .help("List input devices with their supported event types and key codes, then exit")
+        .req_flag(())
+        .map(|()| Mode::ListInputDevices);
+
+    let list_keycodes = long("list-keycodes")
+        .help("List the names and numeric codes of all keys, then exit")
+        .req_flag(())
+        .map(|()| Mode::ListKeycodes);
+    let identify_keys = long("identify-keys")
+        .help("Print the name and code of each key pressed on any input device until interrupted")
+        .req_flag(())
+        .map(|()| Mode::IdentifyKeys);
+
+    let generate = long("generate")
+        .help("Print a shell completion script or the man page, then exit: bash, zsh, fish, or man")
+        .argument::<String>("TARGET")
+        .parse(|s| match s.as_str() {
+            "bash" => Ok(Generate::Bash),
+            "zsh" => Ok(Generate::Zsh),
+            "fish" => Ok(Generate::Fish),
+            "man" => Ok(Generate::Man),
+            _ => Err(format!("Unknown generation target {:?}", s)),
+        })
+        .complete_shell(ShellComp::Raw {
+            bash: "COMPREPLY=( $(compgen -W 'bash zsh fish man' -- \"${COMP_WORDS[COMP_CWORD]}\") )",
+            zsh: "compadd bash zsh fish man",
+            fish: "printf '%s\\n' bash zsh fish man",
+            elvish: "",
+        })
+        .map(Mode::Generate);
+
+    let run = options().map(|opts| Mode::Run(Box::new(opts)));
+    construct!([list_input_devices, list_keycodes, identify_keys, generate, run])
+}
+
+// Name of the executable, used in completion scripts and the man page
+const NAME: &str = env!("CARGO_PKG_NAME");
+
+fn parser() -> OptionParser<Mode> {
+    mode()
+        .to_options()
+        .descr("Renders SVG templates, optionally modified by scripts, to an image or an e-ink framebuffer")
+        .version(env!("CARGO_PKG_VERSION"))
+}
+
+pub fn parse() -> Mode {
+    parser().run()
 }
 
-pub fn parse() -> Options {
-    options().to_options().run()
+// Prints a shell completion script or the man page to stdout
+pub fn generate(target: Generate) {
+    let style = match target {
+        Generate::Bash => "--bpaf-complete-style-bash",
+        Generate::Zsh => "--bpaf-complete-style-zsh",
+        Generate::Fish => "--bpaf-complete-style-fish",
+        Generate::Man => {
+            let title = Some("Oikos Dashboard");
+            print!("{}", parser().render_manpage(NAME, Section::General, None, None, title));
+            return;
+        }
+    };
+    // bpaf prints the completion script when parsing this flag and exits. The
+    // script calls back into the program to complete the current command line
+    let _ = parser().run_inner(Args::from(&[style][..]).set_name(NAME));
 }
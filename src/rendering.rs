@@ -1,12 +1,23 @@
+use std::collections::HashSet;
+use std::mem;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
 
+use elementtree::Element;
 use log::debug;
+use tiny_skia::Color;
 use tiny_skia::IntSize;
 use tiny_skia::Pixmap;
 use usvg::fontdb;
-use usvg::{NodeExt, NodeKind, Transform, TreeParsing, TreeTextToPath};
+use usvg::{NodeExt, NodeKind, NonZeroPositiveF32, TextToPath, Transform, TreeParsing, TreeTextToPath};
 
-use crate::document::{self, Alignment, Arguments, Document, Horizontal, Orientation, Rotation, Vertical};
+use crate::document::{
+    self, Alignment, Arguments, Direction, Distribution, Document, ElementId, FitText, Horizontal, Orientation,
+    Overflow, QrCode, Rotation, Scale, Stack, Theme, Vertical, WrapText,
+};
+use crate::timing::Timings;
 
 fn align(target: &usvg::Node, alignment: Alignment, tree: &mut usvg::Tree) -> Option<Transform> {
     let anchor = match alignment.relative_to {
@@ -39,13 +50,15 @@ fn align(target: &usvg::Node, alignment: Alignment, tree: &mut usvg::Tree) -> Op
     }
 }
 
-fn rotate(target: &usvg::Node, rotation: Rotation, tree: &mut usvg::Tree) -> Option<Transform> {
-    let center = match rotation.center {
+// Returns the center of the `center` element (or the target itself) relative
+// to the target's coordinate system
+fn center_point(target: &usvg::Node, center: Option<ElementId>, tree: &mut usvg::Tree) -> Option<(f32, f32)> {
+    let center = match center {
         Some(center) => tree.node_by_id(&center)?,
         None => target.clone(),
     };
 
-    // rotation coordinates are absolute, correct for parent transformations:
+    // bounding box coordinates are absolute, correct for parent transformations:
     let abs = target.abs_transform();
     let (origin_x, origin_y) = (abs.tx, abs.ty);
 
@@ -53,9 +66,427 @@ fn rotate(target: &usvg::Node, rotation: Rotation, tree: &mut usvg::Tree) -> Opt
     let x = center.x() + center.width() / 2. - origin_x;
     let y = center.y() + center.height() / 2. - origin_y;
 
+    Some((x, y))
+}
+
+fn rotate(target: &usvg::Node, rotation: Rotation, tree: &mut usvg::Tree) -> Option<Transform> {
+    let (x, y) = center_point(target, rotation.center, tree)?;
     Some(Transform::from_rotate_at(rotation.angle as f32, x, y))
 }
 
+fn scale(target: &usvg::Node, scale: Scale, tree: &mut usvg::Tree) -> Option<Transform> {
+    let (x, y) = center_point(target, scale.center, tree)?;
+    let (sx, sy) = (scale.sx as f32, scale.sy as f32);
+    Some(Transform::from_row(sx, 0., 0., sy, x - sx * x, y - sy * y))
+}
+
+// Start and length of a bounding box along the given direction
+fn extent(direction: Direction, bbox: usvg::Rect) -> (f32, f32) {
+    match direction {
+        Direction::Horizontal => (bbox.x(), bbox.width()),
+        Direction::Vertical => (bbox.y(), bbox.height()),
+    }
+}
+
+// Extents of all elements which are still present in the tree
+fn extents<'a>(direction: Direction, elements: &'a [ElementId], tree: &usvg::Tree) -> Vec<(&'a ElementId, (f32, f32))> {
+    elements
+        .iter()
+        .filter_map(|id| {
+            let bbox = tree.node_by_id(id)?.calculate_bbox()?;
+            Some((id, extent(direction, bbox)))
+        })
+        .collect()
+}
+
+// Moves the target to `pos`, assuming the elements are placed one after
+// another starting at `pos`, separated by `gap`
+fn place_in_sequence(
+    target_id: &str,
+    direction: Direction,
+    elements: Vec<(&ElementId, (f32, f32))>,
+    mut pos: f32,
+    gap: f32,
+) -> Option<Transform> {
+    for (id, (start, len)) in elements {
+        if id == target_id {
+            let offset = pos - start;
+            return match direction {
+                Direction::Horizontal => Some(Transform::from_translate(offset, 0.)),
+                Direction::Vertical => Some(Transform::from_translate(0., offset)),
+            };
+        }
+        pos += len + gap;
+    }
+
+    None
+}
+
+// Spaces out all elements evenly within the anchor, keeping equal gaps between them
+fn distribute(target_id: &str, distribution: Distribution, tree: &mut usvg::Tree) -> Option<Transform> {
+    let anchor = match distribution.relative_to {
+        Some(relative_to) => tree.node_by_id(&relative_to)?,
+        None => tree.root.clone(),
+    };
+    let (anchor_start, anchor_len) = extent(distribution.direction, anchor.calculate_bbox()?);
+
+    let elements = extents(distribution.direction, &distribution.elements, tree);
+    let total_len: f32 = elements.iter().map(|(_, (_, len))| len).sum();
+    let (pos, gap) = match elements.len() {
+        0 => return None,
+        1 => (anchor_start + (anchor_len - total_len) / 2., 0.),
+        n => (anchor_start, (anchor_len - total_len) / (n - 1) as f32),
+    };
+
+    place_in_sequence(target_id, distribution.direction, elements, pos, gap)
+}
+
+// Places all elements one after another, starting at the position of the first
+fn stack(target_id: &str, stack: Stack, tree: &mut usvg::Tree) -> Option<Transform> {
+    let elements = extents(stack.direction, &stack.elements, tree);
+    let &(_, (pos, _)) = elements.first()?;
+
+    place_in_sequence(target_id, stack.direction, elements, pos, stack.gap as f32)
+}
+
+fn recolor(theme: &Theme, c: usvg::Color) -> usvg::Color {
+    match theme {
+        Theme::Invert => usvg::Color::new_rgb(255 - c.red, 255 - c.green, 255 - c.blue),
+        Theme::Grayscale => {
+            let luma = 0.299 * c.red as f32 + 0.587 * c.green as f32 + 0.114 * c.blue as f32;
+            let luma = luma.round().clamp(0., 255.) as u8;
+            usvg::Color::new_rgb(luma, luma, luma)
+        }
+        Theme::Palette(palette) => palette
+            .iter()
+            .find(|(from, _)| (from.red, from.green, from.blue) == (c.red, c.green, c.blue))
+            .map(|(_, to)| usvg::Color::new_rgb(to.red, to.green, to.blue))
+            .unwrap_or(c),
+    }
+}
+
+fn recolor_paint(theme: &Theme, paint: &mut usvg::Paint) {
+    let recolor_stops = |stops: &mut Vec<usvg::Stop>| {
+        for stop in stops {
+            stop.color = recolor(theme, stop.color);
+        }
+    };
+
+    match paint {
+        usvg::Paint::Color(c) => *c = recolor(theme, *c),
+        usvg::Paint::LinearGradient(g) => {
+            let mut gradient = (**g).clone();
+            recolor_stops(&mut gradient.base.stops);
+            *g = Rc::new(gradient);
+        }
+        usvg::Paint::RadialGradient(g) => {
+            let mut gradient = (**g).clone();
+            recolor_stops(&mut gradient.base.stops);
+            *g = Rc::new(gradient);
+        }
+        usvg::Paint::Pattern(_) => {}
+    }
+}
+
+// Recolors the fill and stroke of all paths within the target
+fn theme(target: &usvg::Node, theme: &Theme) {
+    for node in target.descendants() {
+        if let NodeKind::Path(ref mut path) = *node.borrow_mut() {
+            if let Some(fill) = path.fill.as_mut() {
+                recolor_paint(theme, &mut fill.paint);
+            }
+            if let Some(stroke) = path.stroke.as_mut() {
+                recolor_paint(theme, &mut stroke.paint);
+            }
+        }
+    }
+}
+
+// Adds the QR code as a square, centered within the bounding box of the target
+fn qrcode(target: &usvg::Node, qr: &QrCode) {
+    let Some(bbox) = target.calculate_bbox() else {
+        return;
+    };
+    if qr.width == 0 {
+        return;
+    }
+
+    let side = bbox.width().min(bbox.height());
+    let module = side / qr.width as f32;
+    let x0 = bbox.x() + (bbox.width() - side) / 2.;
+    let y0 = bbox.y() + (bbox.height() - side) / 2.;
+
+    // adjacent dark modules within a row are merged into a single rectangle
+    let mut builder = tiny_skia::PathBuilder::new();
+    for (y, row) in qr.modules.chunks(qr.width).enumerate() {
+        let mut x = 0;
+        while x < row.len() {
+            let run = row[x..].iter().take_while(|dark| **dark).count();
+            if run > 0 {
+                let rect = tiny_skia::Rect::from_xywh(
+                    x0 + x as f32 * module,
+                    y0 + y as f32 * module,
+                    run as f32 * module,
+                    module,
+                );
+                if let Some(rect) = rect {
+                    builder.push_rect(rect);
+                }
+            }
+            x += run.max(1);
+        }
+    }
+    let Some(data) = builder.finish() else {
+        return;
+    };
+
+    // the path is specified in absolute coordinates
+    let is_group = matches!(*target.borrow(), NodeKind::Group(_));
+    let parent_ts = match is_group {
+        true => target.abs_transform(),
+        false => target.parent().map(|p| p.abs_transform()).unwrap_or_default(),
+    };
+
+    let mut path = usvg::Path::new(Rc::new(data));
+    path.transform = parent_ts.invert().unwrap_or_default();
+    path.fill = Some(usvg::Fill::default());
+    path.rendering_mode = usvg::ShapeRendering::CrispEdges;
+
+    let node = usvg::Node::new(NodeKind::Path(path));
+    match is_group {
+        true => target.append(node),
+        false => target.insert_after(node),
+    }
+}
+
+// Returns the width of the text once converted to paths
+fn text_width(text: &usvg::Text, fonts: &fontdb::Database) -> Option<f32> {
+    let node = text.convert(fonts, Transform::default())?;
+    node.calculate_bbox().map(|bbox| bbox.width())
+}
+
+// Keeps the first `keep` characters of the text and appends an ellipsis
+fn truncate_text(text: &mut usvg::Text, keep: usize) {
+    let mut remaining = keep;
+    let Some(idx) = text.chunks.iter().position(|chunk| {
+        let count = chunk.text.chars().count();
+        let found = remaining <= count;
+        if !found {
+            remaining -= count;
+        }
+        found
+    }) else {
+        return;
+    };
+    text.chunks.truncate(idx + 1);
+
+    let chunk = &mut text.chunks[idx];
+    let cut = chunk
+        .text
+        .char_indices()
+        .nth(remaining)
+        .map(|(i, _)| i)
+        .unwrap_or(chunk.text.len());
+    let cut = chunk.text[..cut].trim_end().len();
+    chunk.text.truncate(cut);
+
+    let first = chunk.spans.first().cloned();
+    chunk.spans.retain(|span| span.start < cut);
+    for span in &mut chunk.spans {
+        span.end = span.end.min(cut);
+    }
+    if chunk.spans.is_empty() {
+        chunk
+            .spans
+            .extend(first.map(|span| usvg::TextSpan { start: 0, ..span }));
+    }
+
+    chunk.text.push('…');
+    if let Some(span) = chunk.spans.last_mut() {
+        span.end = chunk.text.len();
+    }
+}
+
+fn fit_text(text: &mut usvg::Text, fit: FitText, fonts: &fontdb::Database) {
+    let max_width = fit.max_width as f32;
+    let Some(width) = text_width(text, fonts) else {
+        return;
+    };
+    if width <= max_width {
+        return;
+    }
+
+    match fit.overflow {
+        Overflow::Shrink => {
+            // the width is roughly proportional to the font size, the loop
+            // accounts for any non-linear effects such as hinting or spacing
+            let mut factor = max_width / width;
+            for _ in 0..8 {
+                let mut shrunk = text.clone();
+                for span in shrunk.chunks.iter_mut().flat_map(|c| c.spans.iter_mut()) {
+                    if let Some(size) = NonZeroPositiveF32::new(span.font_size.get() * factor) {
+                        span.font_size = size;
+                    }
+                }
+
+                let fits = text_width(&shrunk, fonts).map(|w| w <= max_width).unwrap_or(true);
+                if fits {
+                    *text = shrunk;
+                    return;
+                }
+                factor *= 0.95;
+            }
+        }
+        Overflow::Ellipsis => {
+            // binary search for the longest prefix which still fits
+            let total = text.chunks.iter().map(|c| c.text.chars().count()).sum::<usize>();
+            let (mut lo, mut hi) = (0, total);
+            while lo + 1 < hi {
+                let mid = (lo + hi) / 2;
+                let mut truncated = text.clone();
+                truncate_text(&mut truncated, mid);
+                match text_width(&truncated, fonts) {
+                    Some(w) if w > max_width => hi = mid,
+                    _ => lo = mid,
+                }
+            }
+            truncate_text(text, lo);
+        }
+    }
+}
+
+// Returns the part of the chunk within the given byte range
+fn slice_chunk(chunk: &usvg::TextChunk, start: usize, end: usize) -> usvg::TextChunk {
+    let spans = chunk
+        .spans
+        .iter()
+        .filter(|span| span.start < end && span.end > start)
+        .map(|span| usvg::TextSpan {
+            start: span.start.max(start) - start,
+            end: span.end.min(end) - start,
+            ..span.clone()
+        })
+        .collect();
+
+    usvg::TextChunk {
+        text: chunk.text[start..end].to_string(),
+        spans,
+        ..chunk.clone()
+    }
+}
+
+// Breaks each line of the text into multiple lines at whitespace, such that
+// each line fits within the maximum width where possible
+fn wrap_text(text: &mut usvg::Text, wrap: WrapText, fonts: &fontdb::Database) {
+    let max_width = wrap.max_width as f32;
+
+    // used to measure individual lines
+    let mut line = text.clone();
+    line.positions.clear();
+    line.rotate.clear();
+    let mut width = |chunk: usvg::TextChunk| {
+        line.chunks = vec![chunk];
+        text_width(&line, fonts).unwrap_or(0.)
+    };
+
+    // character positions are indexed across all chunks and need to follow
+    // the whitespace dropped at line breaks
+    let (old_positions, old_rotate) = (mem::take(&mut text.positions), mem::take(&mut text.rotate));
+    let (mut positions, mut rotate) = (Vec::new(), Vec::new());
+    let mut keep_chars = |range: Range<usize>| {
+        positions.extend_from_slice(old_positions.get(range.clone()).unwrap_or_default());
+        rotate.extend_from_slice(old_rotate.get(range).unwrap_or_default());
+    };
+
+    let mut chunks = Vec::new();
+    let mut offset = 0.;
+    // baseline of the previous line and index of the first character of the
+    // chunk, to position chunks continuing the previous one without their own `y`
+    let (mut last_y, mut first_char) = (0., 0);
+    for chunk in text.chunks.drain(..) {
+        let dy = old_positions.get(first_char).and_then(|p| p.dy).unwrap_or(0.);
+        let chunk_y = chunk.y.map(|y| y + offset).unwrap_or(last_y + dy);
+        let chunk_start = first_char;
+        first_char += chunk.text.chars().count();
+
+        let mut words = Vec::<(usize, usize)>::new();
+        let mut start = None;
+        for (idx, c) in chunk.text.char_indices().chain([(chunk.text.len(), ' ')]) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(idx),
+                (Some(s), true) => {
+                    words.push((s, idx));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        let linear = matches!(chunk.text_flow, usvg::TextFlow::Linear);
+        let Some(&(first_start, first_end)) = words.first().filter(|_| linear) else {
+            last_y = chunk_y;
+            keep_chars(chunk_start..first_char);
+            chunks.push(chunk);
+            continue;
+        };
+
+        let mut lines = vec![];
+        let (mut line_start, mut line_end) = (first_start, first_end);
+        for &(word_start, word_end) in &words[1..] {
+            if width(slice_chunk(&chunk, line_start, word_end)) <= max_width {
+                line_end = word_end;
+            } else {
+                lines.push((line_start, line_end));
+                (line_start, line_end) = (word_start, word_end);
+            }
+        }
+        lines.push((line_start, line_end));
+
+        let font_size = chunk.spans.first().map(|s| s.font_size.get()).unwrap_or(0.);
+        let line_height = wrap.line_height as f32 * font_size;
+        for (n, &(start, end)) in lines.iter().enumerate() {
+            let char_index = |idx: usize| chunk_start + chunk.text[..idx].chars().count();
+            keep_chars(char_index(start)..char_index(end));
+
+            let mut line = slice_chunk(&chunk, start, end);
+            line.y = match n {
+                // keeps a relative position, including the `dy` of its first character
+                0 => chunk.y.map(|y| y + offset),
+                _ => Some(chunk_y + n as f32 * line_height),
+            };
+            chunks.push(line);
+        }
+        offset += (lines.len() - 1) as f32 * line_height;
+        last_y = chunk_y + (lines.len() - 1) as f32 * line_height;
+    }
+    text.chunks = chunks;
+    text.positions = positions;
+    text.rotate = rotate;
+}
+
+// Performs operations which modify the text itself. These need to be applied
+// before the text is converted to paths.
+fn perform_text(op: &document::Operation, tree: &usvg::Tree, fonts: &fontdb::Database) {
+    let Some(target) = tree.node_by_id(&op.target) else {
+        return;
+    };
+
+    let mut target = target.borrow_mut();
+    let NodeKind::Text(ref mut text) = *target else {
+        return;
+    };
+
+    match &op.args {
+        Arguments::FitText(fit) => fit_text(text, fit.clone(), fonts),
+        Arguments::WrapText(wrap) => wrap_text(text, wrap.clone(), fonts),
+        _ => {}
+    }
+}
+
+fn is_text_operation(op: &document::Operation) -> bool {
+    matches!(op.args, Arguments::FitText(_) | Arguments::WrapText(_))
+}
+
 pub fn perform(op: document::Operation, tree: &mut usvg::Tree) {
     let Some(target) = tree.node_by_id(&op.target) else {
         return;
@@ -64,6 +495,20 @@ pub fn perform(op: document::Operation, tree: &mut usvg::Tree) {
     let transform = match op.args {
         Arguments::Alignment(a) => align(&target, a, tree),
         Arguments::Rotation(r) => rotate(&target, r, tree),
+        Arguments::Scale(s) => scale(&target, s, tree),
+        Arguments::Translate(t) => Some(Transform::from_translate(t.dx as f32, t.dy as f32)),
+        Arguments::Distribution(d) => distribute(&op.target, d, tree),
+        Arguments::Stack(s) => stack(&op.target, s, tree),
+        // already applied before text conversion
+        Arguments::FitText(_) | Arguments::WrapText(_) => None,
+        Arguments::Theme(t) => {
+            theme(&target, &t);
+            None
+        }
+        Arguments::QrCode(q) => {
+            qrcode(&target, &q);
+            None
+        }
     };
 
     if let Some(transform) = transform {
@@ -78,6 +523,69 @@ pub fn perform(op: document::Operation, tree: &mut usvg::Tree) {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ScaleMode {
+    // Scale uniformly so that the whole document is visible
+    Fit,
+    // Scale uniformly so that the whole screen is covered
+    Fill,
+    // Scale non-uniformly to the screen size
+    Stretch,
+    // Center the document without scaling it
+    Center,
+    // Render the document at its intrinsic size in the top left corner
+    #[default]
+    None,
+}
+
+impl ScaleMode {
+    fn transform(&self, doc: usvg::Size, screen: IntSize) -> Transform {
+        let (width, height) = (screen.width() as f32, screen.height() as f32);
+        let sx = width / doc.width();
+        let sy = height / doc.height();
+
+        let scale = match self {
+            ScaleMode::Fit => sx.min(sy),
+            ScaleMode::Fill => sx.max(sy),
+            ScaleMode::Stretch => return Transform::from_scale(sx, sy),
+            ScaleMode::Center => 1.,
+            ScaleMode::None => return Transform::default(),
+        };
+
+        let tx = (width - doc.width() * scale) / 2.;
+        let ty = (height - doc.height() * scale) / 2.;
+        Transform::from_row(scale, 0., 0., scale, tx, ty)
+    }
+}
+
+// Rotation of the document on the screen, e.g. to show a portrait document on a
+// landscape panel
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ScreenRotation {
+    #[default]
+    None,
+    // by 90 degrees
+    Clockwise,
+}
+
+impl ScreenRotation {
+    // Size of the document area for the given screen size
+    fn document_size(&self, screen: IntSize) -> IntSize {
+        match self {
+            ScreenRotation::None => screen,
+            ScreenRotation::Clockwise => IntSize::from_wh(screen.height(), screen.width()).expect("size is not zero"),
+        }
+    }
+
+    // Maps the document area onto the screen of the given size
+    fn transform(&self, screen: IntSize) -> Transform {
+        match self {
+            ScreenRotation::None => Transform::default(),
+            ScreenRotation::Clockwise => Transform::from_row(0., 1., -1., 0., screen.width() as f32, 0.),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Configuration {
     pub base_dir: Option<PathBuf>,
@@ -85,12 +593,305 @@ pub struct Configuration {
     pub fonts_dir: Option<PathBuf>,
     pub system_fonts: bool,
     pub screen_size: Option<(u32, u32)>,
+    pub supersample: f32,
+    pub scale_mode: ScaleMode,
+    pub rotation: ScreenRotation,
+}
+
+// Computes for each destination pixel the covered source pixels and their
+// weight, i.e. the fraction of the destination pixel covered by them.
+fn box_filter_weights(src_len: u32, dst_len: u32) -> Vec<Vec<(usize, f32)>> {
+    let scale = src_len as f32 / dst_len as f32;
+    (0..dst_len)
+        .map(|d| {
+            let start = d as f32 * scale;
+            let end = start + scale;
+            let mut weights = Vec::new();
+            let mut s = start.floor() as u32;
+            while (s as f32) < end && s < src_len {
+                let overlap = end.min(s as f32 + 1.) - start.max(s as f32);
+                if overlap > 0. {
+                    weights.push((s as usize, overlap / scale));
+                }
+                s += 1;
+            }
+            weights
+        })
+        .collect()
+}
+
+// Downscales the pixmap into `dst` using a box filter. Each destination pixel
+// becomes the area-weighted average of the source pixels it covers. The
+// `horizontal` buffer holds the intermediate result and is reused across calls.
+fn downscale(src: &Pixmap, dst: &mut Pixmap, horizontal: &mut Vec<f32>) {
+    const CHANNELS: usize = 4;
+
+    let (src_width, src_height) = (src.width() as usize, src.height() as usize);
+    let dst_width = dst.width() as usize;
+    let columns = box_filter_weights(src.width(), dst.width());
+    let rows = box_filter_weights(src.height(), dst.height());
+
+    // horizontal pass: src_width x src_height -> dst_width x src_height
+    let data = src.data();
+    horizontal.clear();
+    horizontal.resize(dst_width * src_height * CHANNELS, 0.);
+    for y in 0..src_height {
+        for (x, weights) in columns.iter().enumerate() {
+            let dst = &mut horizontal[(y * dst_width + x) * CHANNELS..][..CHANNELS];
+            for &(sx, weight) in weights {
+                let src = &data[(y * src_width + sx) * CHANNELS..][..CHANNELS];
+                for c in 0..CHANNELS {
+                    dst[c] += src[c] as f32 * weight;
+                }
+            }
+        }
+    }
+
+    // vertical pass: dst_width x src_height -> dst_width x dst_height
+    let data = dst.data_mut();
+    for (y, weights) in rows.iter().enumerate() {
+        for x in 0..dst_width {
+            let mut acc = [0f32; CHANNELS];
+            for &(sy, weight) in weights {
+                let src = &horizontal[(sy * dst_width + x) * CHANNELS..][..CHANNELS];
+                for c in 0..CHANNELS {
+                    acc[c] += src[c] * weight;
+                }
+            }
+            let dst = &mut data[(y * dst_width + x) * CHANNELS..][..CHANNELS];
+            for c in 0..CHANNELS {
+                dst[c] = acc[c].round().clamp(0., u8::MAX as f32) as u8;
+            }
+        }
+    }
+}
+
+// Returns the previously allocated pixmap cleared to transparency if it has
+// the requested size. Otherwise, a new pixmap is allocated in its place,
+// which fails if the size in bytes does not fit into memory.
+fn reuse_pixmap(pixmap: &mut Option<Pixmap>, size: IntSize) -> Result<&mut Pixmap, anyhow::Error> {
+    let reusable = matches!(pixmap, Some(p) if p.width() == size.width() && p.height() == size.height());
+    if !reusable {
+        // the old pixmap is freed first on devices with little memory
+        *pixmap = None;
+        *pixmap = Some(
+            Pixmap::new(size.width(), size.height())
+                .ok_or_else(|| anyhow::format_err!("Bitmap of {}x{} is too large", size.width(), size.height()))?,
+        );
+    }
+
+    let pixmap = pixmap.as_mut().expect("pixmap has been allocated");
+    if reusable {
+        pixmap.fill(Color::TRANSPARENT);
+    }
+    Ok(pixmap)
+}
+
+// Render state of the previously rendered document. It allows us to skip
+// parsing and text conversion if the document itself did not change, and
+// to skip building the render tree if no operations changed either.
+struct Cache {
+    root: Element,
+    svg_data: Vec<u8>,
+    text_operations: Vec<document::Operation>,
+    // Parsed tree with converted text, before any other operations are applied
+    tree: usvg::Tree,
+    operations: Vec<document::Operation>,
+    // Tree with all operations applied, used to look up element positions
+    processed: usvg::Tree,
+    rtree: resvg::Tree,
+}
+
+impl Cache {
+    fn build(
+        root: Element,
+        svg_data: Vec<u8>,
+        text_operations: Vec<document::Operation>,
+        tree: usvg::Tree,
+        operations: Vec<document::Operation>,
+    ) -> Self {
+        let mut copy = usvg::Tree {
+            size: tree.size,
+            view_box: tree.view_box,
+            root: tree.root.make_deep_copy(),
+        };
+
+        for op in operations.iter().cloned() {
+            perform(op, &mut copy);
+        }
+
+        let rtree = resvg::Tree::from_usvg(&copy);
+        Cache {
+            root,
+            svg_data,
+            text_operations,
+            tree,
+            operations,
+            processed: copy,
+            rtree,
+        }
+    }
+}
+
+// Elements which can be parsed on their own and replaced in the cached tree
+const REPLACEABLE: &[&str] = &[
+    "g", "text", "path", "rect", "circle", "ellipse", "line", "polyline", "polygon", "image", "use",
+];
+
+// CSS selectors depending on siblings would match differently in isolation
+const SIBLING_SELECTORS: &[&str] = &["+", "~", ":first", ":last", ":nth", ":only", ":empty"];
+
+fn same_element(a: &Element, b: &Element) -> bool {
+    a.tag() == b.tag()
+        && a.text() == b.text()
+        && a.tail() == b.tail()
+        && a.attr_count() == b.attr_count()
+        && a.attrs().all(|(name, value)| b.get_attr(name) == Some(value))
+}
+
+fn descendants(root: &Element) -> Vec<&Element> {
+    let mut result = vec![];
+    let mut queue = vec![root];
+    while let Some(elem) = queue.pop() {
+        result.push(elem);
+        queue.extend(elem.children());
+    }
+    result
+}
+
+// Returns the ids referenced by attributes, e.g. `url(#gradient)` or `#symbol`
+// in links. Other values starting with `#` are colors.
+fn referenced_ids<'a>(elems: impl IntoIterator<Item = &'a Element>) -> HashSet<&'a str> {
+    let mut ids = HashSet::new();
+    for elem in elems {
+        for (name, value) in elem.attrs() {
+            if let Some(id) = value.strip_prefix('#').filter(|_| name.name() == "href") {
+                ids.insert(id);
+            }
+            let mut rest = value;
+            while let Some(start) = rest.find("url(#") {
+                rest = &rest[start + 5..];
+                let end = rest.find(')').unwrap_or(rest.len());
+                ids.insert(rest[..end].trim_matches(['"', '\'', ' ']));
+            }
+        }
+    }
+    ids
+}
+
+// Collects the outermost elements which differ between the two trees, each
+// with its ancestors. Returns false if the structure itself changed.
+fn changed_elements<'a>(
+    old: &Element,
+    new: &'a Element,
+    ancestors: &mut Vec<&'a Element>,
+    changed: &mut Vec<Vec<&'a Element>>,
+) -> bool {
+    // replaced elements are looked up by id in the cached tree
+    if old.get_attr("id") != new.get_attr("id") {
+        return false;
+    }
+    ancestors.push(new);
+    let same_children =
+        old.child_count() == new.child_count() && old.children().zip(new.children()).all(|(a, b)| a.tag() == b.tag());
+    if !same_element(old, new) || !same_children {
+        // the root itself cannot be replaced
+        changed.push(ancestors.clone());
+        ancestors.pop();
+        return !ancestors.is_empty();
+    }
+    for (a, b) in old.children().zip(new.children()) {
+        if !changed_elements(a, b, ancestors, changed) {
+            return false;
+        }
+    }
+    ancestors.pop();
+    true
+}
+
+// Chooses the element to replace for a change, i.e. the nearest ancestor with
+// an id, or the enclosing text element for changes within text. Returns None
+// if the element cannot be parsed in isolation.
+fn replacement_target<'a>(mut path: Vec<&'a Element>, referenced: &HashSet<&str>) -> Option<Vec<&'a Element>> {
+    if let Some(text) = path.iter().position(|e| e.tag().name() == "text") {
+        path.truncate(text + 1);
+    }
+    while path.last()?.get_attr("id").is_none() {
+        path.pop();
+    }
+    let (target, ancestors) = path.split_last()?;
+    let ancestors_are_groups = ancestors.iter().skip(1).all(|e| e.tag().name() == "g");
+    let is_referenced = descendants(target)
+        .iter()
+        .filter_map(|e| e.get_attr("id"))
+        .any(|id| referenced.contains(id));
+    match ancestors.is_empty() || !ancestors_are_groups || is_referenced {
+        true => None,
+        false => REPLACEABLE.contains(&target.tag().name()).then_some(path),
+    }
+}
+
+// Builds a standalone document with just the target element, its ancestors
+// without any other children, and all styles and top-level definitions
+fn isolate(root: &Element, path: &[&Element]) -> Option<Element> {
+    let (target, ancestors) = path.split_last()?;
+    let styles = descendants(root)
+        .into_iter()
+        .filter(|e| e.tag().name() == "style")
+        .collect::<Vec<_>>();
+    if styles
+        .iter()
+        .any(|s| SIBLING_SELECTORS.iter().any(|sel| s.text().contains(sel)))
+    {
+        return None;
+    }
+    let defs = root.children().filter(|e| e.tag().name() == "defs").collect::<Vec<_>>();
+
+    // all references must resolve within the isolated document
+    let defined = defs
+        .iter()
+        .chain([target])
+        .flat_map(|e| descendants(e))
+        .filter_map(|e| e.get_attr("id"))
+        .collect::<HashSet<_>>();
+    let references = referenced_ids(descendants(target).into_iter().chain(ancestors.iter().copied()));
+    if !references.is_subset(&defined) {
+        return None;
+    }
+
+    let shell = |e: &Element| {
+        let mut e = e.clone();
+        e.retain_children(|_| false);
+        e.set_text("");
+        e
+    };
+    let mut elem = (*target).clone();
+    elem.set_tail("");
+    for ancestor in ancestors.iter().skip(1).rev() {
+        let mut parent = shell(ancestor);
+        parent.append_child(elem);
+        elem = parent;
+    }
+    let mut doc = shell(ancestors[0]);
+    for e in styles.into_iter().chain(defs) {
+        doc.append_child(e.clone());
+    }
+    doc.append_child(elem);
+    Some(doc)
 }
 
 pub struct Renderer {
     opts: usvg::Options,
     fonts: fontdb::Database,
     screen_size: Option<IntSize>,
+    supersample: f32,
+    scale_mode: ScaleMode,
+    rotation: ScreenRotation,
+    cache: Option<Cache>,
+    // Buffers reused across renders to avoid repeated allocations
+    pixmap: Option<Pixmap>,
+    supersampled: Option<Pixmap>,
+    scratch: Vec<f32>,
 }
 
 impl Renderer {
@@ -105,6 +906,22 @@ impl Renderer {
             fonts.load_fonts_dir(fonts_dir);
         }
 
+        // Resolve generic sans-serif text, e.g. of the built-in error panel, to
+        // any available font if the default family is not installed
+        let query = fontdb::Query {
+            families: &[fontdb::Family::SansSerif],
+            ..Default::default()
+        };
+        if fonts.query(&query).is_none() {
+            let family = fonts
+                .faces()
+                .find_map(|f| f.families.first())
+                .map(|(name, _)| name.clone());
+            if let Some(family) = family {
+                fonts.set_sans_serif_family(family);
+            }
+        }
+
         let opts = usvg::Options {
             resources_dir: c.resources_dir.or(c.base_dir),
             ..usvg::Options::default()
@@ -114,30 +931,334 @@ impl Renderer {
             opts,
             fonts,
             screen_size,
+            supersample: c.supersample.max(1.),
+            scale_mode: c.scale_mode,
+            rotation: c.rotation,
+            cache: None,
+            pixmap: None,
+            supersampled: None,
+            scratch: Vec::new(),
         }
     }
 
-    pub fn render(&self, doc: Document) -> Result<Pixmap, anyhow::Error> {
-        let rtree = {
-            let (svg_data, operations) = doc.prepare()?;
-            debug!("Rendering document with {} queued operations", operations.len());
-            let mut tree = usvg::Tree::from_data(&svg_data, &self.opts)?;
+    // Returns the most recently rendered bitmap
+    pub fn last_render(&self) -> Option<&Pixmap> {
+        self.pixmap.as_ref()
+    }
 
-            tree.convert_text(&self.fonts);
+    // Discards the cached render tree, e.g. because referenced images changed
+    pub fn invalidate(&mut self) {
+        self.cache = None;
+    }
 
-            for op in operations {
-                perform(op, &mut tree);
-            }
+    // Size of the rendered pixmap, once a document has been rendered
+    pub fn output_size(&self) -> Option<(u32, u32)> {
+        let size = self.pixmap_size()?;
+        Some((size.width(), size.height()))
+    }
+
+    // Size of the area the document would be rendered to, before rotating it
+    // onto the screen. The document is parsed if neither the screen size nor a
+    // previous render determine it.
+    pub fn document_size(&self, doc: &Document) -> Result<(u32, u32), anyhow::Error> {
+        if let Some(size) = self.pixmap_size() {
+            let size = self.rotation.document_size(size);
+            return Ok((size.width(), size.height()));
+        }
+
+        let (root, _) = doc.prepare();
+        let tree = usvg::Tree::from_data(&document::serialize(&root)?, &self.opts)?;
+        let size = tree.size.to_int_size();
+        Ok((size.width(), size.height()))
+    }
+
+    fn pixmap_size(&self) -> Option<IntSize> {
+        match (self.screen_size, &self.cache) {
+            (Some(size), _) => Some(size),
+            // rotating the other way around maps the document size to the screen
+            (None, Some(cache)) => Some(self.rotation.document_size(cache.rtree.size.to_int_size())),
+            (None, None) => None,
+        }
+    }
+
+    // Returns the bounding box of the element in pixels of the last rendered
+    // document, or None if there is no such element
+    pub fn element_bounds(&self, id: &str) -> Option<usvg::Rect> {
+        let tree = &self.cache.as_ref()?.processed;
+        let bbox = tree.node_by_id(id)?.calculate_bbox()?;
+        let view_box = usvg::utils::view_box_to_transform(tree.view_box.rect, tree.view_box.aspect, tree.size);
+        let transform = self.transform(tree.size, self.pixmap_size()?);
+        bbox.transform(transform.pre_concat(view_box))
+    }
+
+    // Maps the document onto the pixmap, scaling it into the rotated screen area
+    fn transform(&self, doc: usvg::Size, pixmap_size: IntSize) -> Transform {
+        let area = self.rotation.document_size(pixmap_size);
+        self.scale_mode
+            .transform(doc, area)
+            .post_concat(self.rotation.transform(pixmap_size))
+    }
+
+    // Replaces the elements which changed since the cached document in the
+    // cached tree, instead of parsing the whole document again. Returns false
+    // without modifying the tree if this is not possible.
+    fn patch(&self, tree: &usvg::Tree, old: &Element, new: &Element, text_operations: &[document::Operation]) -> bool {
+        let mut changed = vec![];
+        if !changed_elements(old, new, &mut vec![], &mut changed) {
+            return false;
+        }
 
-            resvg::Tree::from_usvg(&tree)
+        let unique_ids = |root| {
+            let mut ids = HashSet::new();
+            descendants(root)
+                .iter()
+                .filter_map(|e| e.get_attr("id"))
+                .all(|id| ids.insert(id))
         };
-        let pixmap_size = match self.screen_size {
-            Some(size) => size,
-            None => rtree.size.to_int_size(),
+        if !unique_ids(old) || !unique_ids(new) {
+            return false;
+        }
+        let referenced = referenced_ids(descendants(new));
+
+        let mut targets = vec![];
+        for path in changed {
+            let Some(target) = replacement_target(path, &referenced) else {
+                return false;
+            };
+            targets.push(target);
+        }
+        // changes within an element which is replaced anyway are dropped
+        let last = |path: &[&Element]| path[path.len() - 1] as *const Element;
+        targets.dedup_by(|a, b| last(a) == last(b));
+        let contains = |outer: &[&Element], inner: &[&Element]| {
+            inner[..inner.len() - 1].iter().any(|e| std::ptr::eq(*e, last(outer)))
+        };
+        let targets = targets
+            .iter()
+            .filter(|t| !targets.iter().any(|outer| contains(outer, t)))
+            .collect::<Vec<_>>();
+
+        let mut replacements = vec![];
+        for path in targets {
+            let Some(id) = path.last().and_then(|e| e.get_attr("id")) else {
+                return false;
+            };
+            let Some(doc) = isolate(new, path) else {
+                return false;
+            };
+            let Ok(data) = document::serialize(&doc) else {
+                return false;
+            };
+            let Ok(mut isolated) = usvg::Tree::from_data(&data, &self.opts) else {
+                return false;
+            };
+            if isolated.size != tree.size
+                || isolated.view_box.rect != tree.view_box.rect
+                || isolated.view_box.aspect != tree.view_box.aspect
+            {
+                return false;
+            }
+            for op in text_operations {
+                perform_text(op, &isolated, &self.fonts);
+            }
+            isolated.convert_text(&self.fonts);
+            // empty elements are removed by usvg
+            let (Some(old_node), Some(new_node)) = (tree.node_by_id(id), isolated.node_by_id(id)) else {
+                return false;
+            };
+            replacements.push((old_node, new_node.make_deep_copy()));
+        }
+
+        debug!("Replacing {} changed elements in the cached tree", replacements.len());
+        for (old_node, new_node) in replacements {
+            old_node.insert_after(new_node);
+            old_node.detach();
+        }
+        true
+    }
+
+    fn update_cache(&mut self, doc: Document, timings: &mut Timings) -> Result<(), anyhow::Error> {
+        let (root, operations) = doc.prepare();
+        let svg_data = document::serialize(&root)?;
+        debug!("Rendering document with {} queued operations", operations.len());
+        let (text_operations, operations): (Vec<_>, Vec<_>) = operations.into_iter().partition(is_text_operation);
+
+        let cache = match self.cache.take() {
+            Some(cache)
+                if cache.svg_data == svg_data
+                    && cache.text_operations == text_operations
+                    && cache.operations == operations =>
+            {
+                debug!("Document unchanged, reusing render tree");
+                cache
+            }
+            Some(cache) if cache.svg_data == svg_data && cache.text_operations == text_operations => {
+                debug!("Document unchanged, reusing parsed tree");
+                timings.measure("build", || {
+                    Cache::build(root, cache.svg_data, cache.text_operations, cache.tree, operations)
+                })
+            }
+            Some(cache)
+                if cache.text_operations == text_operations
+                    && timings.measure("patch", || {
+                        self.patch(&cache.tree, &cache.root, &root, &text_operations)
+                    }) =>
+            {
+                timings.measure("build", || {
+                    Cache::build(root, svg_data, cache.text_operations, cache.tree, operations)
+                })
+            }
+            _ => {
+                let mut tree = timings.measure("parse", || usvg::Tree::from_data(&svg_data, &self.opts))?;
+                timings.measure("text", || {
+                    for op in &text_operations {
+                        perform_text(op, &tree, &self.fonts);
+                    }
+                    tree.convert_text(&self.fonts)
+                });
+                timings.measure("build", || {
+                    Cache::build(root, svg_data, text_operations, tree, operations)
+                })
+            }
         };
-        let mut pixmap =
-            tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height()).expect("invalid bitmap size");
-        rtree.render(Transform::default(), &mut pixmap.as_mut());
+
+        self.cache = Some(cache);
+        Ok(())
+    }
+
+    // Renders the document into a pixmap owned by the renderer. The pixmap is
+    // reused and overwritten by the next call.
+    pub fn render(&mut self, doc: Document, timings: &mut Timings) -> Result<&Pixmap, anyhow::Error> {
+        self.update_cache(doc, timings)?;
+        let start = Instant::now();
+
+        let pixmap_size = self.pixmap_size().expect("render cache has been populated");
+        let rtree = &self.cache.as_ref().expect("render cache has been populated").rtree;
+        let transform = self.transform(rtree.size, pixmap_size);
+
+        if self.supersample == 1. {
+            let pixmap = reuse_pixmap(&mut self.pixmap, pixmap_size)?;
+            rtree.render(transform, &mut pixmap.as_mut());
+            timings.record("rasterize", start.elapsed());
+            return Ok(pixmap);
+        }
+
+        // Rasterize at a higher resolution and downscale to the target size
+        let scaled_size = pixmap_size
+            .scale_by(self.supersample)
+            .ok_or_else(|| anyhow::format_err!("Invalid supersampling factor {}", self.supersample))?;
+        debug!(
+            "Supersampling document at {}x{} pixels",
+            scaled_size.width(),
+            scaled_size.height()
+        );
+        let supersampled = reuse_pixmap(&mut self.supersampled, scaled_size)?;
+        rtree.render(
+            transform.post_scale(self.supersample, self.supersample),
+            &mut supersampled.as_mut(),
+        );
+
+        let pixmap = reuse_pixmap(&mut self.pixmap, pixmap_size)?;
+        downscale(supersampled, pixmap, &mut self.scratch);
+        timings.record("rasterize", start.elapsed());
         Ok(pixmap)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="200" height="120">
+  <style>.hot { fill: #c00 } g.panel rect { stroke: #000 }</style>
+  <defs><g id="icon"><circle id="dot" cx="5" cy="5" r="4"/></g></defs>
+  <rect id="bg" width="200" height="120" fill="#fff"/>
+  <g id="panel" class="panel" transform="translate(10 10)" opacity="0.8">
+    <rect id="box" width="50" height="30" fill="#888"/>
+    <text id="label" x="0" y="50" font-family="Barlow Semi Condensed" font-size="14">Hello</text>
+  </g>
+  <g id="list">
+    <rect id="row" y="80" width="30" height="10" class="hot"/>
+    <rect id="other" x="40" y="80" width="30" height="10"/>
+  </g>
+  <use id="ref" xlink:href="#icon" x="150" y="20"/>
+</svg>"##;
+
+    fn renderer() -> Renderer {
+        Renderer::from_config(Configuration {
+            fonts_dir: Some(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/example/fonts"))),
+            supersample: 1.,
+            ..Default::default()
+        })
+    }
+
+    // Renders the template, then again after applying visible changes, and
+    // checks that the result matches a fresh render. Returns whether the cached tree
+    // has been patched instead of parsing the changed document again.
+    fn render_changed(change: impl Fn(&Document) -> Result<(), document::Error>) -> bool {
+        let template = Document::from_bytes(TEMPLATE).unwrap();
+        let mut cached = renderer();
+        let original = cached
+            .render(template.deep_copy(), &mut Timings::new())
+            .unwrap()
+            .clone();
+
+        let doc = template.deep_copy();
+        change(&doc).unwrap();
+        let mut timings = Timings::new();
+        let patched = cached.render(doc.deep_copy(), &mut timings).unwrap().clone();
+        let fresh = renderer().render(doc, &mut Timings::new()).unwrap().clone();
+
+        assert!(patched.data() != original.data(), "change is not visible");
+        assert!(
+            patched.data() == fresh.data(),
+            "patched render differs from fresh render"
+        );
+        !timings.to_json().contains("\"parse_ms\"")
+    }
+
+    #[test]
+    fn patch_text() {
+        assert!(render_changed(|doc| doc.select_by_attr("id", "label")?.text("World")));
+    }
+
+    #[test]
+    fn patch_attributes() {
+        assert!(render_changed(|doc| {
+            doc.select_by_attr("id", "box")?.attr("fill", "#222")?;
+            doc.select_by_attr("id", "panel")?.attr("opacity", "0.5")
+        }));
+    }
+
+    #[test]
+    fn patch_css_selected() {
+        assert!(render_changed(|doc| doc
+            .select_by_attr("id", "box")?
+            .attr("class", "hot")));
+    }
+
+    #[test]
+    fn patch_removed() {
+        assert!(render_changed(|doc| doc.select_by_attr("id", "row")?.remove()));
+        // usvg drops the then empty group, which is parsed again instead
+        assert!(!render_changed(|doc| doc
+            .select_by_attr("id", "list")?
+            .children()?
+            .remove()));
+    }
+
+    #[test]
+    fn patch_cloned() {
+        assert!(render_changed(|doc| {
+            let row = doc.select_by_attr("id", "row")?.clone_element()?;
+            row.attr("y", "100")
+        }));
+    }
+
+    #[test]
+    fn patch_used() {
+        // referenced elements may be rendered more than once
+        assert!(!render_changed(|doc| doc.select_by_attr("id", "dot")?.attr("r", "8")));
+        assert!(render_changed(|doc| doc.select_by_attr("id", "ref")?.attr("x", "100")));
+    }
+}
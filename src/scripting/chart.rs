@@ -0,0 +1,414 @@
+use rhai::plugin::*;
+use rhai::{Array, Map};
+
+use super::datetime::days_in_month;
+
+// Helpers for reading optional chart settings from a Rhai object map
+
+fn opt_f64(opts: &Map, key: &str, default: f64) -> f64 {
+    opts.get(key).and_then(as_f64).unwrap_or(default)
+}
+
+fn opt_bool(opts: &Map, key: &str, default: bool) -> bool {
+    opts.get(key).and_then(|v| v.as_bool().ok()).unwrap_or(default)
+}
+
+fn opt_str(opts: &Map, key: &str, default: &str) -> String {
+    opts.get(key)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| default.to_string())
+}
+
+fn as_f64(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|i| i as f64))
+}
+
+// Escapes text for use in XML attribute values and text content
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Formats a number with at most two decimal places
+fn num(value: f64) -> String {
+    let s = format!("{:.2}", value);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// Returns the minimum and maximum of the given values, or of the `min` and `max`
+// options if specified
+fn value_range(values: &[Option<f64>], opts: &Map) -> (f64, f64) {
+    let present = values.iter().flatten().copied();
+    let min = present.clone().fold(f64::INFINITY, f64::min);
+    let max = present.fold(f64::NEG_INFINITY, f64::max);
+    (opt_f64(opts, "min", min), opt_f64(opts, "max", max))
+}
+
+// Returns the color for the series with the given index. The `fill` option may
+// either be a single color or an array of colors, one per series.
+fn series_fill(opts: &Map, series: usize) -> String {
+    let fill = match opts.get("fill") {
+        Some(fill) if fill.is_array() => fill
+            .read_lock::<Array>()
+            .and_then(|fills| fills.get(series % fills.len().max(1)).map(|f| f.to_string())),
+        Some(fill) => Some(fill.to_string()),
+        None => None,
+    };
+    escape(&fill.unwrap_or_else(|| "black".to_string()))
+}
+
+// Returns path data for a circular arc around (`cx`, `cy`), with angles in
+// degrees measured clockwise from the positive x-axis
+fn arc(cx: f64, cy: f64, r: f64, start: f64, sweep: f64) -> String {
+    // a full circle cannot be expressed as a single arc
+    let sweep = sweep.clamp(0., 359.99);
+    let point = |angle: f64| {
+        let rad = angle.to_radians();
+        (cx + r * rad.cos(), cy + r * rad.sin())
+    };
+
+    let (x0, y0) = point(start);
+    let (x1, y1) = point(start + sweep);
+    format!(
+        "M{},{} A{},{} 0 {} 1 {},{}",
+        num(x0),
+        num(y0),
+        num(r),
+        num(r),
+        (sweep > 180.) as u8,
+        num(x1),
+        num(y1)
+    )
+}
+
+#[export_module]
+pub mod chart {
+    use tz::DateTime;
+
+    // Returns a line chart of the values as an SVG fragment of the given size.
+    // Missing values (`()`) interrupt the line.
+    pub fn sparkline(values: Array, width: f64, height: f64) -> String {
+        sparkline_with_options(values, width, height, Map::new())
+    }
+
+    // Supported options: `stroke`, `stroke_width`, `min`, `max`, and `labels`
+    // together with `font_size` for annotating the minimum and maximum values
+    #[rhai_fn(name = "sparkline")]
+    pub fn sparkline_with_options(values: Array, width: f64, height: f64, opts: Map) -> String {
+        let values = values.iter().map(super::as_f64).collect::<Vec<_>>();
+        let (min, max) = super::value_range(&values, &opts);
+
+        let step = match values.len() {
+            0 | 1 => 0.,
+            n => width / (n - 1) as f64,
+        };
+        let point = |idx: usize, value: f64| {
+            let y = match max - min {
+                range if range > 0. => height - (value - min) / range * height,
+                _ => height / 2.,
+            };
+            (idx as f64 * step, y)
+        };
+
+        let mut d = String::new();
+        let mut pen_down = false;
+        for (idx, value) in values.iter().enumerate() {
+            match value {
+                Some(value) => {
+                    let (x, y) = point(idx, *value);
+                    let cmd = if pen_down { 'L' } else { 'M' };
+                    d.push_str(&format!("{}{},{} ", cmd, super::num(x), super::num(y)));
+                    pen_down = true;
+                }
+                None => pen_down = false,
+            }
+        }
+
+        let mut svg = format!(
+            r#"<g class="sparkline"><path d="{}" fill="none" stroke="{}" stroke-width="{}" stroke-linejoin="round" />"#,
+            d.trim_end(),
+            super::escape(&super::opt_str(&opts, "stroke", "black")),
+            super::num(super::opt_f64(&opts, "stroke_width", 2.)),
+        );
+
+        if super::opt_bool(&opts, "labels", false) {
+            let font_size = super::opt_f64(&opts, "font_size", 12.);
+            let extremes = [
+                values
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| Some((i, (*v)?)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1)),
+                values
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| Some((i, (*v)?)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1)),
+            ];
+            // the maximum is labeled above and the minimum below the line
+            let offsets = [-font_size / 2., font_size * 1.25];
+            for (extreme, offset) in extremes.into_iter().zip(offsets) {
+                let Some((idx, value)) = extreme else {
+                    continue;
+                };
+                let (x, y) = point(idx, value);
+                let y = y + offset;
+                let anchor = match idx {
+                    0 => "start",
+                    i if i + 1 == values.len() => "end",
+                    _ => "middle",
+                };
+                svg.push_str(&format!(
+                    r#"<text x="{}" y="{}" font-size="{}" text-anchor="{}">{}</text>"#,
+                    super::num(x),
+                    super::num(y),
+                    super::num(font_size),
+                    anchor,
+                    super::num(value),
+                ));
+            }
+        }
+
+        svg.push_str("</g>");
+        svg
+    }
+
+    // Returns a bar chart of the values as an SVG fragment. Each value may be
+    // either a number or an array of numbers for grouped bars.
+    //
+    // Supported options: `width`, `height`, `gap` between groups, `fill` (a color
+    // or an array of colors per series), `min`, `max`, `ticks` for the number of
+    // y-axis intervals, `labels` for value labels, and `font_size`.
+    pub fn bars(values: Array, opts: Map) -> String {
+        let groups = values
+            .iter()
+            .map(|v| match v.read_lock::<Array>() {
+                Some(group) => group.iter().map(super::as_f64).collect::<Vec<_>>(),
+                None => vec![super::as_f64(v)],
+            })
+            .collect::<Vec<_>>();
+
+        let width = super::opt_f64(&opts, "width", 300.);
+        let height = super::opt_f64(&opts, "height", 100.);
+        let gap = super::opt_f64(&opts, "gap", 4.);
+        let font_size = super::opt_f64(&opts, "font_size", 12.);
+
+        // bars always start at zero, even if all values are positive
+        let all = groups.iter().flatten().copied().collect::<Vec<_>>();
+        let (min, max) = super::value_range(&all, &opts);
+        let (min, max) = (min.min(0.), max.max(0.));
+        let y = |value: f64| match max - min {
+            range if range > 0. => height - (value - min) / range * height,
+            _ => height,
+        };
+
+        let mut svg = String::from(r#"<g class="bars">"#);
+
+        let ticks = super::opt_f64(&opts, "ticks", 0.).max(0.) as usize;
+        if ticks > 0 {
+            svg.push_str(&format!(
+                r#"<path d="M0,0 V{}" fill="none" stroke="black" stroke-width="1" />"#,
+                super::num(height)
+            ));
+            for i in 0..=ticks {
+                let value = min + (max - min) * i as f64 / ticks as f64;
+                let ty = super::num(y(value));
+                svg.push_str(&format!(
+                    r#"<path d="M-4,{ty} H0" fill="none" stroke="black" stroke-width="1" /><text x="-6" y="{ty}" font-size="{}" text-anchor="end" dominant-baseline="middle">{}</text>"#,
+                    super::num(font_size),
+                    super::num(value),
+                ));
+            }
+        }
+
+        let group_width = match groups.len() {
+            0 => 0.,
+            n => ((width - gap * (n - 1) as f64) / n as f64).max(0.),
+        };
+        for (g, group) in groups.iter().enumerate() {
+            let bar_width = group_width / group.len().max(1) as f64;
+            for (series, value) in group.iter().enumerate() {
+                let Some(value) = value else {
+                    continue;
+                };
+
+                let x = g as f64 * (group_width + gap) + series as f64 * bar_width;
+                let (top, bottom) = (y(value.max(0.)), y(value.min(0.)));
+                svg.push_str(&format!(
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" />"#,
+                    super::num(x),
+                    super::num(top),
+                    super::num(bar_width),
+                    super::num(bottom - top),
+                    super::series_fill(&opts, series),
+                ));
+
+                if super::opt_bool(&opts, "labels", false) {
+                    let label_y = match *value < 0. {
+                        true => bottom + font_size,
+                        false => top - font_size / 4.,
+                    };
+                    svg.push_str(&format!(
+                        r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle">{}</text>"#,
+                        super::num(x + bar_width / 2.),
+                        super::num(label_y),
+                        super::num(font_size),
+                        super::num(*value),
+                    ));
+                }
+            }
+        }
+
+        svg.push_str("</g>");
+        svg
+    }
+
+    // Returns a dial showing the value within the range as an SVG fragment
+    #[rhai_fn(return_raw)]
+    pub fn gauge(value: Dynamic, min: Dynamic, max: Dynamic) -> Result<String, Box<EvalAltResult>> {
+        gauge_with_options(value, min, max, Map::new())
+    }
+
+    // Supported options: `radius`, `stroke_width`, `sweep` in degrees (default:
+    // 270, open at the bottom), `fill` and `track` colors, `labels` for showing
+    // the value in the center, and `font_size`. The dial is centered within a
+    // square of twice the radius.
+    #[rhai_fn(name = "gauge", return_raw)]
+    pub fn gauge_with_options(
+        value: Dynamic,
+        min: Dynamic,
+        max: Dynamic,
+        opts: Map,
+    ) -> Result<String, Box<EvalAltResult>> {
+        let number = |v: Dynamic| super::as_f64(&v).ok_or_else(|| format!("Expected a number, got {}", v.type_name()));
+        let (value, min, max) = (number(value)?, number(min)?, number(max)?);
+
+        let r = super::opt_f64(&opts, "radius", 50.);
+        let stroke_width = super::opt_f64(&opts, "stroke_width", 10.);
+        let sweep = super::opt_f64(&opts, "sweep", 270.).clamp(0., 360.);
+        let start = 90. + (360. - sweep) / 2.;
+        let fraction = match max - min {
+            range if range > 0. => ((value - min) / range).clamp(0., 1.),
+            _ => 0.,
+        };
+
+        // the stroke is drawn within the square
+        let radius = (r - stroke_width / 2.).max(0.);
+        let stroke = |color: String, sweep: f64| {
+            format!(
+                r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}" />"#,
+                super::arc(r, r, radius, start, sweep),
+                super::escape(&color),
+                super::num(stroke_width),
+            )
+        };
+
+        let mut svg = String::from(r#"<g class="gauge">"#);
+        svg.push_str(&stroke(super::opt_str(&opts, "track", "#dddddd"), sweep));
+        if fraction > 0. {
+            svg.push_str(&stroke(super::opt_str(&opts, "fill", "black"), sweep * fraction));
+        }
+
+        if super::opt_bool(&opts, "labels", false) {
+            svg.push_str(&format!(
+                r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle" dominant-baseline="middle">{}</text>"#,
+                super::num(r),
+                super::num(r),
+                super::num(super::opt_f64(&opts, "font_size", r / 2.)),
+                super::num(value),
+            ));
+        }
+
+        svg.push_str("</g>");
+        Ok(svg)
+    }
+
+    // Returns a grid of the month containing the date as an SVG fragment, with
+    // the day of the date highlighted
+    pub fn calendar(date: DateTime) -> String {
+        calendar_with_options(date, Map::new())
+    }
+
+    // Supported options: `cell_width`, `cell_height`, `font_size`, `week_start`
+    // (0 = Sunday, default: 1 = Monday), `weekdays` for the seven header labels
+    // starting with Sunday, `headers` to toggle the header row, and `highlight`
+    // and `highlight_text` colors for the current day
+    #[rhai_fn(name = "calendar")]
+    pub fn calendar_with_options(date: DateTime, opts: Map) -> String {
+        let cell_width = super::opt_f64(&opts, "cell_width", 40.);
+        let cell_height = super::opt_f64(&opts, "cell_height", 30.);
+        let font_size = super::opt_f64(&opts, "font_size", cell_height / 2.);
+        let week_start = super::opt_f64(&opts, "week_start", 1.).rem_euclid(7.) as usize;
+
+        // column of the first day of the month
+        let first_week_day = (date.week_day() as i64 - (date.month_day() as i64 - 1)).rem_euclid(7) as usize;
+        let offset = (first_week_day + 7 - week_start) % 7;
+
+        let mut svg = String::from(r#"<g class="calendar">"#);
+        let text = |svg: &mut String, class: &str, col: usize, row: usize, fill: Option<&str>, label: &str| {
+            svg.push_str(&format!(
+                r#"<text class="{}" x="{}" y="{}" font-size="{}" text-anchor="middle" dominant-baseline="central"{}>{}</text>"#,
+                class,
+                super::num((col as f64 + 0.5) * cell_width),
+                super::num((row as f64 + 0.5) * cell_height),
+                super::num(font_size),
+                fill.map(|f| format!(r#" fill="{}""#, super::escape(f))).unwrap_or_default(),
+                super::escape(label),
+            ));
+        };
+
+        let mut row = 0;
+        if super::opt_bool(&opts, "headers", true) {
+            let labels = match opts
+                .get("weekdays")
+                .and_then(|w| w.read_lock::<Array>().map(|w| w.clone()))
+            {
+                Some(labels) if labels.len() == 7 => labels.iter().map(|l| l.to_string()).collect::<Vec<_>>(),
+                _ => ["Su", "Mo", "Tu", "We", "Th", "Fr", "Sa"].map(String::from).to_vec(),
+            };
+            for col in 0..7 {
+                text(
+                    &mut svg,
+                    "calendar-weekday",
+                    col,
+                    row,
+                    None,
+                    &labels[(col + week_start) % 7],
+                );
+            }
+            row += 1;
+        }
+
+        let highlight = super::opt_str(&opts, "highlight", "black");
+        let highlight_text = super::opt_str(&opts, "highlight_text", "white");
+        for day in 1..=super::days_in_month(date.year() as i64, date.month() as i64) as u8 {
+            let cell = offset + day as usize - 1;
+            let (col, row) = (cell % 7, row + cell / 7);
+            if day == date.month_day() {
+                svg.push_str(&format!(
+                    r#"<rect class="calendar-today" x="{}" y="{}" width="{}" height="{}" fill="{}" />"#,
+                    super::num(col as f64 * cell_width),
+                    super::num(row as f64 * cell_height),
+                    super::num(cell_width),
+                    super::num(cell_height),
+                    super::escape(&highlight),
+                ));
+                text(
+                    &mut svg,
+                    "calendar-day",
+                    col,
+                    row,
+                    Some(&highlight_text),
+                    &day.to_string(),
+                );
+            } else {
+                text(&mut svg, "calendar-day", col, row, None, &day.to_string());
+            }
+        }
+
+        svg.push_str("</g>");
+        svg
+    }
+}
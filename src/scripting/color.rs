@@ -0,0 +1,116 @@
+use rhai::plugin::*;
+use rhai::{EvalAltResult, Map, INT};
+
+use crate::document::Color;
+
+// Accepts any SVG color, e.g. `#f80`, `#ff8800`, `rgb(255, 136, 0)` or `orange`
+fn parse(color: &str) -> Result<Color, Box<EvalAltResult>> {
+    color
+        .parse::<Color>()
+        .map_err(|_| format!("invalid color: {:?}", color).into())
+}
+
+fn hex(c: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue)
+}
+
+// Interpolates between the two colors, a weight of 0 returns the first one
+fn interpolate(a: Color, b: Color, weight: f64) -> Color {
+    let weight = weight.clamp(0., 1.);
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * weight).round() as u8;
+    Color::new_rgb(
+        channel(a.red, b.red),
+        channel(a.green, b.green),
+        channel(a.blue, b.blue),
+    )
+}
+
+// Relative luminance as defined by WCAG 2
+fn luminance(c: Color) -> f64 {
+    let linear = |v: u8| {
+        let v = v as f64 / 255.;
+        match v <= 0.03928 {
+            true => v / 12.92,
+            false => ((v + 0.055) / 1.055).powf(2.4),
+        }
+    };
+    0.2126 * linear(c.red) + 0.7152 * linear(c.green) + 0.0722 * linear(c.blue)
+}
+
+fn contrast(a: Color, b: Color) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+// Functions return colors as lowercase `#rrggbb` strings
+#[export_module]
+pub mod color {
+    // Returns the color as a map with `r`, `g` and `b` components from 0 to 255
+    #[rhai_fn(return_raw)]
+    pub fn hex_to_rgb(color: &str) -> Result<Map, Box<EvalAltResult>> {
+        let c = super::parse(color)?;
+        let mut rgb = Map::new();
+        rgb.insert("r".into(), Dynamic::from_int(c.red as INT));
+        rgb.insert("g".into(), Dynamic::from_int(c.green as INT));
+        rgb.insert("b".into(), Dynamic::from_int(c.blue as INT));
+        Ok(rgb)
+    }
+
+    // Components out of range are clamped
+    pub fn rgb_to_hex(r: INT, g: INT, b: INT) -> String {
+        let channel = |v: INT| v.clamp(0, 255) as u8;
+        super::hex(Color::new_rgb(channel(r), channel(g), channel(b)))
+    }
+
+    // Mixes the color with white, an amount of 1 results in white
+    #[rhai_fn(return_raw)]
+    pub fn lighten(color: &str, amount: f64) -> Result<String, Box<EvalAltResult>> {
+        Ok(super::hex(super::interpolate(
+            super::parse(color)?,
+            Color::white(),
+            amount,
+        )))
+    }
+
+    // Mixes the color with black, an amount of 1 results in black
+    #[rhai_fn(return_raw)]
+    pub fn darken(color: &str, amount: f64) -> Result<String, Box<EvalAltResult>> {
+        Ok(super::hex(super::interpolate(
+            super::parse(color)?,
+            Color::black(),
+            amount,
+        )))
+    }
+
+    #[rhai_fn(return_raw)]
+    pub fn mix(a: &str, b: &str) -> Result<String, Box<EvalAltResult>> {
+        mix_with_weight(a, b, 0.5)
+    }
+
+    // Mixes the two colors, a weight of 0 returns `a` and 1 returns `b`
+    #[rhai_fn(name = "mix", return_raw)]
+    pub fn mix_with_weight(a: &str, b: &str, weight: f64) -> Result<String, Box<EvalAltResult>> {
+        Ok(super::hex(super::interpolate(
+            super::parse(a)?,
+            super::parse(b)?,
+            weight,
+        )))
+    }
+
+    // Returns black or white, whichever is more readable on the background
+    #[rhai_fn(return_raw)]
+    pub fn contrast_on(background: &str) -> Result<String, Box<EvalAltResult>> {
+        contrast_on_with(background, "#000000", "#ffffff")
+    }
+
+    // Returns the more readable one of the two colors on the background
+    #[rhai_fn(name = "contrast_on", return_raw)]
+    pub fn contrast_on_with(background: &str, dark: &str, light: &str) -> Result<String, Box<EvalAltResult>> {
+        let background = super::parse(background)?;
+        let (d, l) = (super::parse(dark)?, super::parse(light)?);
+        match super::contrast(background, d) >= super::contrast(background, l) {
+            true => Ok(dark.to_string()),
+            false => Ok(light.to_string()),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::time::Duration;
 
 use rhai::plugin::*;
 use tz;
@@ -11,14 +12,33 @@ impl TimeDelta {
     pub fn as_nanoseconds(&self) -> i128 {
         self.0
     }
+
+    // Returns None for negative or too large deltas
+    pub fn to_duration(self) -> Option<Duration> {
+        u64::try_from(self.0).ok().map(Duration::from_nanos)
+    }
 }
 
-fn local_tz() -> tz::TimeZoneRef<'static> {
+pub fn local_tz() -> tz::TimeZoneRef<'static> {
     tzdb::local_tz()
         .or_else(|| tzdb::tz_by_name(env::var("TZ").unwrap_or_default()))
         .unwrap_or(tzdb::time_zone::UTC)
 }
 
+// Converts seconds since the Unix epoch to a datetime in the local time zone
+pub fn from_unix_time(unix_time: i64) -> Option<tz::DateTime> {
+    tz::DateTime::from_timespec(unix_time, 0, local_tz()).ok()
+}
+
+pub fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
 #[export_module]
 pub mod datetime {
     pub type DateTime = tz::DateTime;
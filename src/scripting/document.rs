@@ -1,6 +1,6 @@
 use rhai::plugin::*;
 
-use crate::document::{Document, Horizontal, Orientation, Vertical};
+use crate::document::{Direction, Document, Horizontal, Orientation, Overflow, Theme, Vertical};
 
 #[export_module]
 pub mod alignment {
@@ -12,6 +12,18 @@ pub mod alignment {
     pub const BOTTOM: Vertical = Vertical::Bottom;
 }
 
+#[export_module]
+pub mod direction {
+    pub const HORIZONTAL: Direction = Direction::Horizontal;
+    pub const VERTICAL: Direction = Direction::Vertical;
+}
+
+#[export_module]
+pub mod overflow {
+    pub const ELLIPSIS: Overflow = Overflow::Ellipsis;
+    pub const SHRINK: Overflow = Overflow::Shrink;
+}
+
 #[export_module]
 pub mod globals {
     pub type Document = super::Document;
@@ -26,12 +38,147 @@ pub mod globals {
         doc.select_by_attr("class", class).map_err(|e| e.to_string().into())
     }
 
+    #[rhai_fn(return_raw, global)]
+    pub fn tag(doc: &mut Document, tag: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.select_by_tag(tag).map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn attr_starts_with(doc: &mut Document, key: &str, prefix: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.select_by_attr_prefix(key, prefix).map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn select(doc: &mut Document, selector: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.select(selector).map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn parent(doc: &mut Document) -> Result<Document, Box<EvalAltResult>> {
+        doc.parent().map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn children(doc: &mut Document) -> Result<Document, Box<EvalAltResult>> {
+        doc.children().map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(global)]
+    pub fn first(doc: &mut Document) -> Document {
+        doc.first()
+    }
+
+    #[rhai_fn(global)]
+    pub fn last(doc: &mut Document) -> Document {
+        doc.last()
+    }
+
+    // Negative indices result in an empty selection
+    #[rhai_fn(global)]
+    pub fn nth(doc: &mut Document, index: i64) -> Document {
+        doc.nth(usize::try_from(index).unwrap_or(usize::MAX))
+    }
+
+    #[rhai_fn(name = "clone", return_raw, global)]
+    pub fn clone_element(doc: &mut Document) -> Result<Document, Box<EvalAltResult>> {
+        doc.clone_element().map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn remove(doc: &mut Document) -> Result<(), Box<EvalAltResult>> {
+        doc.remove().map_err(|e| e.to_string().into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn append_svg(doc: &mut Document, snippet: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.append_svg(snippet).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn inject_css(doc: &mut Document, css: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.inject_css(css).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
     #[rhai_fn(return_raw, global)]
     pub fn text(doc: &mut Document, text: &str) -> Result<Document, Box<EvalAltResult>> {
         doc.text(text).map_err(|e| e.to_string())?;
         Ok(doc.clone())
     }
 
+    #[rhai_fn(name = "text_lines", return_raw, global)]
+    pub fn text_lines_default(doc: &mut Document, lines: rhai::Array) -> Result<Document, Box<EvalAltResult>> {
+        text_lines(doc, lines, 1.25)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn text_lines(
+        doc: &mut Document,
+        lines: rhai::Array,
+        line_height: f64,
+    ) -> Result<Document, Box<EvalAltResult>> {
+        let lines = lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+        doc.text_lines(&lines, line_height).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "fit_text", return_raw, global)]
+    pub fn fit_text_ellipsis(doc: &mut Document, max_width: f64) -> Result<Document, Box<EvalAltResult>> {
+        fit_text(doc, max_width, Overflow::Ellipsis)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn fit_text(doc: &mut Document, max_width: f64, overflow: Overflow) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_fit_text(max_width, overflow).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "wrap_text", return_raw, global)]
+    pub fn wrap_text_default(doc: &mut Document, max_width: f64) -> Result<Document, Box<EvalAltResult>> {
+        wrap_text(doc, max_width, 1.25)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn wrap_text(doc: &mut Document, max_width: f64, line_height: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_wrap_text(max_width, line_height).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    // Resolves `data-bind` paths such as `weather.today.temperature` in the map.
+    // Arrays can be indexed by number, e.g. `forecast.0.summary`.
+    #[rhai_fn(return_raw, global)]
+    pub fn bind(doc: &mut Document, data: rhai::Map) -> Result<Document, Box<EvalAltResult>> {
+        // values are borrowed from the data while locked, hence the recursion
+        fn resolve<'a>(value: &Dynamic, mut keys: impl Iterator<Item = &'a str>) -> Option<String> {
+            let Some(key) = keys.next() else {
+                return match value.is_unit() {
+                    true => None,
+                    false => Some(value.to_string()),
+                };
+            };
+
+            if let Some(map) = value.read_lock::<rhai::Map>() {
+                resolve(map.get(key)?, keys)
+            } else if let Some(array) = value.read_lock::<rhai::Array>() {
+                resolve(array.get(key.parse::<usize>().ok()?)?, keys)
+            } else {
+                None
+            }
+        }
+
+        let data = Dynamic::from_map(data);
+        let lookup = |path: &str| resolve(&data, path.split('.'));
+
+        doc.bind(lookup).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn get_text(doc: &mut Document) -> Result<String, Box<EvalAltResult>> {
+        doc.get_text().map_err(|e| e.to_string().into())
+    }
+
     #[rhai_fn(return_raw, global)]
     pub fn visible(doc: &mut Document, visible: bool) -> Result<Document, Box<EvalAltResult>> {
         doc.attr(
@@ -45,6 +192,96 @@ pub mod globals {
         Ok(doc.clone())
     }
 
+    #[rhai_fn(return_raw, global)]
+    pub fn get_attr(doc: &mut Document, name: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        match doc.get_attr(name).map_err(|e| e.to_string())? {
+            Some(value) => Ok(value.into()),
+            None => Ok(Dynamic::UNIT),
+        }
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn set_attr(doc: &mut Document, name: &str, value: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.attr(name, value).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn opacity(doc: &mut Document, opacity: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.presentation_attr("opacity", &opacity.clamp(0., 1.).to_string())
+            .map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn fill(doc: &mut Document, color: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.presentation_attr("fill", color).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn stroke(doc: &mut Document, color: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.presentation_attr("stroke", color).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "progress", return_raw, global)]
+    pub fn progress_horizontal(doc: &mut Document, fraction: f64) -> Result<Document, Box<EvalAltResult>> {
+        progress(doc, fraction, Direction::Horizontal)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn progress(doc: &mut Document, fraction: f64, direction: Direction) -> Result<Document, Box<EvalAltResult>> {
+        doc.progress(None, fraction, direction).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "progress_with", return_raw, global)]
+    pub fn progress_with_horizontal(
+        doc: &mut Document,
+        track: Document,
+        fraction: f64,
+    ) -> Result<Document, Box<EvalAltResult>> {
+        progress_with(doc, track, fraction, Direction::Horizontal)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn progress_with(
+        doc: &mut Document,
+        track: Document,
+        fraction: f64,
+        direction: Direction,
+    ) -> Result<Document, Box<EvalAltResult>> {
+        doc.progress(Some(&track), fraction, direction)
+            .map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn qrcode(doc: &mut Document, text: &str) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_qrcode(text).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "theme", return_raw, global)]
+    pub fn theme_by_name(doc: &mut Document, name: &str) -> Result<Document, Box<EvalAltResult>> {
+        let theme = Theme::from_name(name).map_err(|e| e.to_string())?;
+        doc.push_theme(theme).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "theme", return_raw, global)]
+    pub fn theme_palette(doc: &mut Document, palette: rhai::Map) -> Result<Document, Box<EvalAltResult>> {
+        let colors = palette
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect::<Vec<_>>();
+        let theme =
+            Theme::palette(colors.iter().map(|(from, to)| (from.as_str(), to.as_str()))).map_err(|e| e.to_string())?;
+        doc.push_theme(theme).map_err(|e| e.to_string())?;
+        Ok(doc.clone())
+    }
+
     #[rhai_fn(name = "align", return_raw, global)]
     pub fn align_horizontal(doc: &mut Document, horizontal: Horizontal) -> Result<Document, Box<EvalAltResult>> {
         doc.push_alignment(Orientation::Horizontal(horizontal), None)
@@ -127,4 +364,73 @@ pub mod globals {
 
         Ok(doc.clone())
     }
+
+    #[rhai_fn(name = "scale", return_raw, global)]
+    pub fn scale_uniform(doc: &mut Document, factor: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_scale(factor, factor, None).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn scale(doc: &mut Document, sx: f64, sy: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_scale(sx, sy, None).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(name = "scale_at", return_raw, global)]
+    pub fn scale_at_uniform(doc: &mut Document, center: Document, factor: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_scale(factor, factor, Some(&center))
+            .map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn scale_at(doc: &mut Document, center: Document, sx: f64, sy: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_scale(sx, sy, Some(&center)).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn move_by(doc: &mut Document, dx: f64, dy: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_translation(dx, dy).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn distribute(doc: &mut Document, direction: Direction) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_distribution(direction, None).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn distribute_with(
+        doc: &mut Document,
+        relative_to: Document,
+        direction: Direction,
+    ) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_distribution(direction, Some(&relative_to))
+            .map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn stack_horizontal(doc: &mut Document, gap: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_stack(Direction::Horizontal, gap).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn stack_vertical(doc: &mut Document, gap: f64) -> Result<Document, Box<EvalAltResult>> {
+        doc.push_stack(Direction::Vertical, gap).map_err(|e| e.to_string())?;
+
+        Ok(doc.clone())
+    }
 }
@@ -0,0 +1,94 @@
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use md5::Md5;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rhai::plugin::*;
+use rhai::{Blob, EvalAltResult};
+use sha2::{Digest, Sha256};
+
+// Everything but the unreserved characters of RFC 3986, as required for
+// request signatures like AWS SigV4
+const URL_ENCODE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+// Decoding accepts both padded and unpadded input
+const DECODE_CONFIG: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const DECODE_STANDARD: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, DECODE_CONFIG);
+const DECODE_URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, DECODE_CONFIG);
+
+fn base64_decode(data: &str) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    // the URL-safe alphabet is used by e.g. JSON web tokens
+    let engine = match data.contains(['-', '_']) {
+        true => &DECODE_URL_SAFE,
+        false => &DECODE_STANDARD,
+    };
+    let data: String = data.split_whitespace().collect();
+    engine
+        .decode(data)
+        .map_err(|err| format!("invalid base64: {}", err).into())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[export_module]
+pub mod globals {
+    #[rhai_fn(global)]
+    pub fn base64_encode(data: &str) -> String {
+        STANDARD.encode(data)
+    }
+
+    #[rhai_fn(name = "base64_encode", global)]
+    pub fn base64_encode_blob(data: Blob) -> String {
+        STANDARD.encode(data)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn base64_decode(data: &str) -> Result<String, Box<EvalAltResult>> {
+        String::from_utf8(super::base64_decode(data)?)
+            .map_err(|_| "decoded data is not valid UTF-8, use base64_decode_blob".into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn base64_decode_blob(data: &str) -> Result<Blob, Box<EvalAltResult>> {
+        super::base64_decode(data)
+    }
+
+    // Percent-encodes all but unreserved characters, e.g. for query parameters
+    #[rhai_fn(global)]
+    pub fn url_encode(s: &str) -> String {
+        utf8_percent_encode(s, super::URL_ENCODE).to_string()
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn url_decode(s: &str) -> Result<String, Box<EvalAltResult>> {
+        percent_decode_str(s)
+            .decode_utf8()
+            .map(|s| s.into_owned())
+            .map_err(|_| "decoded data is not valid UTF-8".into())
+    }
+
+    // Hashes are returned as lowercase hex strings
+    #[rhai_fn(global)]
+    pub fn sha256(data: &str) -> String {
+        super::hex(&Sha256::digest(data))
+    }
+
+    #[rhai_fn(name = "sha256", global)]
+    pub fn sha256_blob(data: Blob) -> String {
+        super::hex(&Sha256::digest(data))
+    }
+
+    #[rhai_fn(global)]
+    pub fn md5(data: &str) -> String {
+        super::hex(&Md5::digest(data))
+    }
+
+    #[rhai_fn(name = "md5", global)]
+    pub fn md5_blob(data: Blob) -> String {
+        super::hex(&Md5::digest(data))
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::Cell;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use rhai::plugin::*;
+use rhai::{Array, Dynamic, EvalAltResult};
+
+use super::datetime::TimeDelta;
+
+thread_local! {
+    // Commands are only run if enabled with --allow-exec
+    static ALLOWED: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn allow_exec() {
+    ALLOWED.with(|allowed| allowed.set(true));
+}
+
+// Commands still running after this long are killed, so that a hanging
+// command does not block the refresh
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Output beyond this size is discarded
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+// Output read so far, and the thread reading it
+type Output = (Arc<Mutex<Vec<u8>>>, JoinHandle<()>);
+
+fn drain(mut reader: impl Read + Send + 'static) -> Output {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let shared = output.clone();
+    let reader = thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        // reading continues beyond the limit, as a full pipe blocks the command
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let mut output = shared.lock().unwrap_or_else(PoisonError::into_inner);
+            let keep = n.min(MAX_OUTPUT - output.len());
+            output.extend(&buf[..keep]);
+        }
+    });
+    (output, reader)
+}
+
+fn finished(output: &Option<Output>) -> bool {
+    output.as_ref().is_none_or(|(_, reader)| reader.is_finished())
+}
+
+fn text(output: Option<Output>) -> String {
+    output
+        .map(|(data, _)| String::from_utf8_lossy(&data.lock().unwrap_or_else(PoisonError::into_inner)).into_owned())
+        .unwrap_or_default()
+}
+
+fn run(cmd: &str, args: Array, timeout: Duration) -> Result<rhai::Map, Box<EvalAltResult>> {
+    if !ALLOWED.with(Cell::get) {
+        return Err("exec is disabled, see --allow-exec".into());
+    }
+    let args = args
+        .into_iter()
+        .map(|arg| {
+            arg.into_string()
+                .map_err(|t| format!("arguments must be strings, not {}", t))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    debug!("Executing {:?} {:?}", cmd, args);
+    let mut child = Command::new(cmd)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // in its own process group, so that processes started by it are
+        // killed along with it
+        .process_group(0)
+        .spawn()
+        .map_err(|err| format!("failed to execute {:?}: {}", cmd, err))?;
+
+    // both pipes are drained concurrently, as a full pipe blocks the command
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+
+    let started = Instant::now();
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = child
+                .try_wait()
+                .map_err(|err| format!("failed to wait for {:?}: {}", cmd, err))?;
+        }
+        if status.is_some() && finished(&stdout) && finished(&stderr) {
+            break;
+        }
+        if started.elapsed() >= timeout {
+            warn!("Killing {:?} after {:?}", cmd, timeout);
+            let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    // the readers are not joined, as processes left behind may keep the
+    // pipes open, e.g. if they changed their process group
+    let status = match status {
+        Some(status) => status,
+        None => child
+            .wait()
+            .map_err(|err| format!("failed to wait for {:?}: {}", cmd, err))?,
+    };
+
+    let mut result = rhai::Map::new();
+    result.insert("stdout".into(), text(stdout).into());
+    result.insert("stderr".into(), text(stderr).into());
+    // commands terminated by a signal have no exit code
+    let code = status.code().map(|c| Dynamic::from_int(c as i64));
+    result.insert("code".into(), code.unwrap_or(Dynamic::UNIT));
+    Ok(result)
+}
+
+#[export_module]
+pub mod globals {
+    // Runs the command and returns its stdout, stderr, and exit code
+    #[rhai_fn(return_raw, global)]
+    pub fn exec(cmd: &str) -> Result<rhai::Map, Box<EvalAltResult>> {
+        super::run(cmd, Array::new(), super::DEFAULT_TIMEOUT)
+    }
+
+    #[rhai_fn(name = "exec", return_raw, global)]
+    pub fn exec_with_args(cmd: &str, args: Array) -> Result<rhai::Map, Box<EvalAltResult>> {
+        super::run(cmd, args, super::DEFAULT_TIMEOUT)
+    }
+
+    #[rhai_fn(name = "exec", return_raw, global)]
+    pub fn exec_with_timeout(cmd: &str, args: Array, timeout: TimeDelta) -> Result<rhai::Map, Box<EvalAltResult>> {
+        let timeout = timeout.to_duration().ok_or("timeout out of range")?;
+        super::run(cmd, args, timeout)
+    }
+}
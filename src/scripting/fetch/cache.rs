@@ -0,0 +1,134 @@
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, warn};
+use rhai::{Dynamic, EvalAltResult, NativeCallContext};
+
+use super::Response;
+use crate::scripting::json;
+use crate::scripting::store::{state_dir, unix_time, write_file};
+
+// Response stored on disk, keyed by a hash of the request
+pub struct Entry {
+    // seconds since the Unix epoch
+    pub fetched: i64,
+    pub response: Response,
+}
+
+// 64-bit FNV-1a, which unlike DefaultHasher is stable across Rust versions
+fn hash(key: &str) -> u64 {
+    key.bytes()
+        .fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// Entries not written for this long are removed, as the cache would grow with
+// every distinct request otherwise. Entries are rewritten when they are
+// fetched again, so this only affects a cache_ttl longer than the age.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 3600);
+// Seconds between scans of the cache directory
+const PRUNE_INTERVAL: i64 = 24 * 3600;
+
+thread_local! {
+    // Time of the last scan in seconds since the Unix epoch
+    static PRUNED: Cell<Option<i64>> = const { Cell::new(None) };
+}
+
+fn dir() -> Result<PathBuf, Box<EvalAltResult>> {
+    Ok(state_dir()?.join("cache"))
+}
+
+fn path(key: &str) -> Result<PathBuf, Box<EvalAltResult>> {
+    Ok(dir()?.join(format!("{:016x}.json", hash(key))))
+}
+
+fn prune(dir: &Path) {
+    let now = unix_time();
+    if PRUNED
+        .with(Cell::get)
+        .is_some_and(|pruned| now - pruned < PRUNE_INTERVAL)
+    {
+        return;
+    }
+    PRUNED.with(|pruned| pruned.set(Some(now)));
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok());
+        if age.is_some_and(|age| age > MAX_AGE) {
+            debug!("Removing stale cached response {:?}", entry.path());
+            if let Err(err) = fs::remove_file(entry.path()) {
+                warn!("Failed to remove cached response {:?}: {}", entry.path(), err);
+            }
+        }
+    }
+}
+
+// Returns the cached response, regardless of its age. Unreadable entries are
+// treated as missing, so that a corrupt file does not break the script
+pub fn load(context: &NativeCallContext, key: &str) -> Result<Option<Entry>, Box<EvalAltResult>> {
+    let path = path(key)?;
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            warn!("Failed to read cached response {:?}: {}", path, err);
+            return Ok(None);
+        }
+    };
+    let entry = context.engine().parse_json(json, true).ok().and_then(|mut map| {
+        let mut take = |name: &str| map.remove(name).unwrap_or(Dynamic::UNIT);
+        Some(Entry {
+            fetched: take("fetched").as_int().ok()?,
+            response: Response {
+                status: take("status").as_int().ok()?,
+                status_text: take("status_text").into_string().ok()?,
+                headers: take("headers")
+                    .try_cast::<rhai::Map>()?
+                    .into_iter()
+                    .map(|(name, value)| Some((name.to_string(), value.into_string().ok()?)))
+                    .collect::<Option<_>>()?,
+                body: match take("body_base64").into_string() {
+                    Ok(body) => STANDARD.decode(body).ok()?,
+                    Err(_) => take("body").into_string().ok()?.into_bytes(),
+                },
+            },
+        })
+    });
+    if entry.is_none() {
+        warn!("Ignoring invalid cached response {:?}", path);
+    }
+    Ok(entry)
+}
+
+pub fn save(key: &str, response: &Response) -> Result<(), Box<EvalAltResult>> {
+    let mut map = rhai::Map::new();
+    map.insert("fetched".into(), Dynamic::from_int(unix_time()));
+    map.insert("status".into(), Dynamic::from_int(response.status));
+    map.insert("status_text".into(), response.status_text.clone().into());
+    // binary bodies cannot be represented as JSON strings
+    match std::str::from_utf8(&response.body) {
+        Ok(body) => map.insert("body".into(), body.into()),
+        Err(_) => map.insert("body_base64".into(), STANDARD.encode(&response.body).into()),
+    };
+    let headers: rhai::Map = response
+        .headers
+        .iter()
+        .map(|(name, value)| (name.into(), value.clone().into()))
+        .collect();
+    map.insert("headers".into(), headers.into());
+    let json = json::to_json(&Dynamic::from_map(map))?;
+    write_file(&path(key)?, json.as_bytes())?;
+    prune(&dir()?);
+    Ok(())
+}
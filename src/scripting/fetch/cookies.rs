@@ -0,0 +1,93 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use cookie_store::CookieStore;
+use log::{debug, warn};
+use rhai::EvalAltResult;
+
+use super::tls::Tls;
+use crate::scripting::store::{state_dir, write_file};
+
+thread_local! {
+    // Agent of the current run along with its proxy and TLS settings, the
+    // agent keeps the cookies received so far
+    static AGENT: RefCell<Option<(Option<String>, Tls, ureq::Agent)>> = const { RefCell::new(None) };
+}
+
+const FILE: &str = "cookies.json";
+
+// Cookies are only persisted if a state directory is configured
+fn path() -> Option<PathBuf> {
+    state_dir().ok().map(|dir| dir.join(FILE))
+}
+
+// Starts with an empty jar, apart from persisted cookies of previous runs
+pub fn reset() {
+    AGENT.with(|agent| agent.borrow_mut().take());
+}
+
+// Returns the agent for requests through the given proxy and with the given
+// TLS settings. Switching to other settings replaces the agent, but carries
+// over its cookies
+pub fn agent(proxy: Option<&str>, tls: &Tls) -> Result<ureq::Agent, Box<EvalAltResult>> {
+    AGENT.with(|agent| {
+        let mut agent = agent.borrow_mut();
+        if let Some((current_proxy, current_tls, agent)) = agent.as_ref() {
+            if current_proxy.as_deref() == proxy && current_tls == tls {
+                return Ok(agent.clone());
+            }
+        }
+
+        let store = match agent.as_ref() {
+            Some((_, _, agent)) => agent.cookie_store().clone(),
+            None => load(),
+        };
+        let mut builder = ureq::AgentBuilder::new().cookie_store(store);
+        if let Some(config) = tls.client_config()? {
+            builder = builder.tls_config(config);
+        }
+        if let Some(proxy) = proxy {
+            debug!("Using proxy {:?}", proxy);
+            let proxy = ureq::Proxy::new(proxy).map_err(|e| format!("invalid proxy {:?}: {}", proxy, e))?;
+            builder = builder.proxy(proxy);
+        }
+        let (_, _, new) = agent.insert((proxy.map(str::to_string), tls.clone(), builder.build()));
+        Ok(new.clone())
+    })
+}
+
+// Unreadable files are treated as empty, so that the script can log in again
+fn load() -> CookieStore {
+    let Some(path) = path() else {
+        return CookieStore::default();
+    };
+    let store = File::open(&path).and_then(|file| {
+        CookieStore::load_json(BufReader::new(file)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    });
+    match store {
+        Ok(store) => {
+            debug!("Loaded cookies from {:?}", path);
+            store
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => CookieStore::default(),
+        Err(err) => {
+            warn!("Ignoring invalid cookies {:?}: {}", path, err);
+            CookieStore::default()
+        }
+    }
+}
+
+// Writes the cookies which outlive the run, session cookies are discarded
+pub fn save(agent: &ureq::Agent) -> Result<(), Box<EvalAltResult>> {
+    let Some(path) = path() else {
+        return Ok(());
+    };
+    let mut json = Vec::new();
+    agent
+        .cookie_store()
+        .save_json(&mut json)
+        .map_err(|e| format!("failed to serialize cookies: {}", e))?;
+    write_file(&path, &json)
+}
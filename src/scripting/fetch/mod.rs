@@ -0,0 +1,531 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use log::{debug, warn};
+use rhai::plugin::*;
+use rhai::{self, Dynamic, EvalAltResult, ImmutableString, NativeCallContext};
+
+use super::datetime::TimeDelta;
+use super::json;
+use super::store::{state_dir, unix_time};
+
+pub use self::cookies::reset as reset_cookies;
+
+mod cache;
+mod cookies;
+mod proxy;
+mod ratelimit;
+mod tls;
+
+thread_local! {
+    // Files returned instead of fetching the given URLs, e.g. for validation
+    static MOCKS: RefCell<HashMap<String, PathBuf>> = RefCell::new(HashMap::new());
+}
+
+// Makes fetch return the contents of the file instead of requesting the URL
+pub fn mock(url: String, file: PathBuf) {
+    MOCKS.with(|mocks| mocks.borrow_mut().insert(url, file));
+}
+
+fn mocked(url: &str) -> Option<PathBuf> {
+    MOCKS.with(|mocks| mocks.borrow().get(url).cloned())
+}
+
+trait Extract {
+    fn extract<T: Any>(&mut self, key: &str) -> Result<Option<T>, Box<EvalAltResult>>;
+}
+
+impl Extract for rhai::Map {
+    fn extract<T: Any>(&mut self, key: &str) -> Result<Option<T>, Box<EvalAltResult>> {
+        if let Some(val) = self.remove(key) {
+            let val_type = val.type_name();
+            match val.try_cast() {
+                Some(s) => return Ok(Some(s)),
+                None => {
+                    return Err(Box::new(EvalAltResult::ErrorMismatchDataType(
+                        std::any::type_name::<T>().to_string(),
+                        val_type.to_string(),
+                        Position::NONE,
+                    )))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub struct Response {
+    pub status: i64,
+    pub status_text: String,
+    // lowercase names, with the values of repeated headers joined by commas
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn text(self) -> Result<String, Box<EvalAltResult>> {
+        String::from_utf8(self.body).map_err(|_| "response is not valid UTF-8, use response_type bytes".into())
+    }
+
+    fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(&name.to_ascii_lowercase())
+    }
+
+    fn into_map(self) -> Result<rhai::Map, Box<EvalAltResult>> {
+        let headers: rhai::Map = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.into(), value.clone().into()))
+            .collect();
+        let mut map = rhai::Map::new();
+        map.insert("status".into(), Dynamic::from_int(self.status));
+        map.insert("status_text".into(), self.status_text.clone().into());
+        map.insert("headers".into(), headers.into());
+        map.insert("data".into(), self.text()?.into());
+        Ok(map)
+    }
+}
+
+enum Error {
+    // invalid options, reported to the script as is
+    Script(Box<EvalAltResult>),
+    // the request failed, which a cached response can make up for
+    Request(String),
+}
+
+impl From<Box<EvalAltResult>> for Error {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        Error::Script(err)
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Error::Script(msg.into())
+    }
+}
+
+impl From<Error> for Box<EvalAltResult> {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Script(err) => err,
+            Error::Request(msg) => msg.into(),
+        }
+    }
+}
+
+// Stalled requests, e.g. right after resuming from suspend, fail after this
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// Delay before the first retry, doubled for each further retry
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+// Longest delay before a single retry, and in total, so that retries do not
+// hold up the refresh for long
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const MAX_TOTAL_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+// Network errors, server errors, and rate limiting are worth another try
+fn retryable(err: &ureq::Error) -> bool {
+    match err {
+        ureq::Error::Status(status, _) => *status >= 500 || *status == 429,
+        ureq::Error::Transport(_) => true,
+    }
+}
+
+struct Body {
+    // used unless the script sets the Content-Type header
+    content_type: Option<&'static str>,
+    data: String,
+}
+
+// Percent-encodes a form field name or value, with spaces as '+'
+fn form_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => out.push(b as char),
+            b' ' => out.push('+'),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+// Encodes the map as application/x-www-form-urlencoded. Arrays are encoded as
+// repeated fields, e.g. #{id: [1, 2]} as id=1&id=2
+fn form_encode(form: rhai::Map) -> Result<String, Box<EvalAltResult>> {
+    let mut fields = Vec::new();
+    for (name, value) in form {
+        let values = match value.is_array() {
+            true => value.cast::<rhai::Array>(),
+            false => vec![value],
+        };
+        for value in values {
+            if value.is_unit() || value.is_map() || value.is_array() {
+                return Err(format!("form field {} cannot be {}", name, value.type_name()).into());
+            }
+            fields.push(format!("{}={}", form_escape(&name), form_escape(&value.to_string())));
+        }
+    }
+    Ok(fields.join("&"))
+}
+
+// Extracts the request body, given either as a string in data, as a value
+// serialized to JSON in json, or as a map of form fields in form
+fn body(opts: &mut rhai::Map) -> Result<Option<Body>, Box<EvalAltResult>> {
+    let data = opts.extract::<ImmutableString>("data")?.map(|data| Body {
+        content_type: None,
+        data: data.to_string(),
+    });
+    let json = match opts.extract::<Dynamic>("json")? {
+        Some(value) => Some(Body {
+            content_type: Some("application/json"),
+            data: json::to_json(&value)?,
+        }),
+        None => None,
+    };
+    let form = match opts.extract::<rhai::Map>("form")? {
+        Some(form) => Some(Body {
+            content_type: Some("application/x-www-form-urlencoded"),
+            data: form_encode(form)?,
+        }),
+        None => None,
+    };
+
+    let mut bodies: Vec<Body> = [data, json, form].into_iter().flatten().collect();
+    match bodies.len() {
+        0 | 1 => Ok(bodies.pop()),
+        _ => Err("only one of data, json, and form may be set".into()),
+    }
+}
+
+// Returns the value of the Authorization header, given either as a user name
+// and password in basic_auth, or as a token in bearer
+fn authorization(opts: &mut rhai::Map) -> Result<Option<String>, Box<EvalAltResult>> {
+    let basic = match opts.extract::<rhai::Array>("basic_auth")? {
+        Some(credentials) => {
+            const USAGE: &str = "basic_auth must be an array of user name and password";
+            let [user, password] = <[Dynamic; 2]>::try_from(credentials).map_err(|_| USAGE)?;
+            let user = user.into_string().map_err(|_| USAGE)?;
+            let password = password.into_string().map_err(|_| USAGE)?;
+            let credentials = format!("{}:{}", user, password);
+            Some(format!("Basic {}", STANDARD.encode(credentials)))
+        }
+        None => None,
+    };
+    let bearer = opts
+        .extract::<ImmutableString>("bearer")?
+        .map(|token| format!("Bearer {}", token));
+
+    match (basic, bearer) {
+        (Some(_), Some(_)) => Err("only one of basic_auth and bearer may be set".into()),
+        (basic, bearer) => Ok(basic.or(bearer)),
+    }
+}
+
+// Sends the request, made conditional on the validators of the cached
+// response, if any. The server then responds with 304 if it is unchanged.
+// Error statuses are returned as responses with `with_status`, instead of
+// failing the request, so that scripts can inspect them.
+fn request(
+    context: &NativeCallContext,
+    path: &str,
+    opts: &mut rhai::Map,
+    body: Option<&Body>,
+    cached: Option<&Response>,
+    min_interval: Option<Duration>,
+    with_status: bool,
+) -> Result<Response, Error> {
+    // an empty proxy option disables the proxy configured in the environment
+    let proxy = match opts.extract::<ImmutableString>("proxy")? {
+        Some(proxy) => Some(proxy.to_string()).filter(|p| !p.is_empty()),
+        None => proxy::from_env(path),
+    };
+    let tls = tls::Tls::from_opts(opts)?;
+    let agent = cookies::agent(proxy.as_deref(), &tls)?;
+    let mut req = match opts.extract::<ImmutableString>("method")? {
+        Some(method) => agent.request(&method, path),
+        None => agent.get(path),
+    };
+
+    // ureq decompresses gzip itself, deflate is decoded in read_body
+    req = req.set("Accept-Encoding", "gzip, deflate");
+    if let Some(content_type) = body.and_then(|b| b.content_type) {
+        req = req.set("Content-Type", content_type);
+    }
+    if let Some(authorization) = authorization(opts)? {
+        req = req.set("Authorization", &authorization);
+    }
+
+    if let Some(etag) = cached.and_then(|r| r.header("ETag")) {
+        req = req.set("If-None-Match", etag);
+    }
+    if let Some(last_modified) = cached.and_then(|r| r.header("Last-Modified")) {
+        req = req.set("If-Modified-Since", last_modified);
+    }
+
+    if let Some(headers) = opts.extract::<rhai::Map>("headers")? {
+        for (k, v) in headers.into_iter() {
+            let v = v
+                .into_immutable_string()
+                .map_err(|t| format!("header {} must be a string, not {}", k, t))?;
+            req = req.set(&k, &v);
+        }
+    }
+
+    let mut duration = |name: &str, default: Duration| -> Result<Duration, Box<EvalAltResult>> {
+        match opts.extract::<TimeDelta>(name)? {
+            Some(delta) => delta
+                .to_duration()
+                .ok_or_else(|| format!("{} out of range", name).into()),
+            None => Ok(default),
+        }
+    };
+    let timeout = duration("timeout", DEFAULT_TIMEOUT)?;
+    let retry_backoff = duration("retry_backoff", DEFAULT_RETRY_BACKOFF)?;
+    let retries = match opts.extract::<i64>("retries")? {
+        Some(n) => u32::try_from(n).map_err(|_| "retries must not be negative".to_string())?,
+        None => 0,
+    };
+    req = req.timeout(timeout);
+
+    if let Some((host, interval)) = ratelimit::host(path).zip(min_interval) {
+        ratelimit::acquire(context, &host, interval, timeout)?;
+    }
+
+    let mut attempt = 0;
+    let mut waited = Duration::ZERO;
+    let resp = loop {
+        debug!("Fetching {:?}", path);
+        let result = match body {
+            Some(body) => req.clone().send_string(&body.data),
+            None => req.clone().call(),
+        };
+        match result {
+            Err(err) if attempt < retries && retryable(&err) && waited < MAX_TOTAL_RETRY_DELAY => {
+                let delay = retry_backoff
+                    .saturating_mul(2u32.saturating_pow(attempt))
+                    .min(MAX_RETRY_DELAY)
+                    .min(MAX_TOTAL_RETRY_DELAY - waited);
+                warn!("Failed to fetch {:?}, retrying in {:?}: {}", path, delay, err);
+                thread::sleep(delay);
+                waited += delay;
+                attempt += 1;
+            }
+            Err(ureq::Error::Status(_, resp)) if with_status => break resp,
+            result => break result.map_err(|e| Error::Request(e.to_string()))?,
+        }
+    };
+    if resp.has("Set-Cookie") {
+        cookies::save(&agent)?;
+    }
+
+    let headers = resp
+        .headers_names()
+        .into_iter()
+        .map(|name| {
+            let value = resp.all(&name).join(", ");
+            (name, value)
+        })
+        .collect();
+    Ok(Response {
+        status: resp.status() as i64,
+        status_text: resp.status_text().to_string(),
+        headers,
+        body: read_body(resp).map_err(|e| Error::Request(e.to_string()))?,
+    })
+}
+
+// Larger responses are rejected, as they would not fit in memory on e-readers
+const MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
+
+fn read_body(resp: ureq::Response) -> io::Result<Vec<u8>> {
+    let deflate = resp
+        .header("Content-Encoding")
+        .is_some_and(|e| e.trim().eq_ignore_ascii_case("deflate"));
+    let reader: Box<dyn Read> = match deflate {
+        true => Box::new(ZlibDecoder::new(resp.into_reader())),
+        false => Box::new(resp.into_reader()),
+    };
+
+    let mut body = Vec::new();
+    reader.take(MAX_BODY_SIZE + 1).read_to_end(&mut body)?;
+    match body.len() as u64 > MAX_BODY_SIZE {
+        true => Err(io::Error::new(io::ErrorKind::InvalidData, "response too large")),
+        false => Ok(body),
+    }
+}
+
+// Responses are cached if requested with cache_ttl, or if they can be
+// revalidated with a conditional request the next time they are fetched
+fn cacheable(opts: &rhai::Map, body: Option<&Body>, ttl: Option<TimeDelta>) -> bool {
+    let method = opts.get("method").map_or_else(|| "GET".to_string(), |m| m.to_string());
+    let get = method.eq_ignore_ascii_case("GET") && body.is_none();
+    ttl.is_some() || (get && state_dir().is_ok())
+}
+
+// Serves the response from the cache until it is older than the time-to-live.
+// Expired responses are revalidated with the server, and still used if the
+// request fails, e.g. while the network is down, or if the host must not be
+// requested again yet
+fn cached_request(
+    context: &NativeCallContext,
+    path: &str,
+    opts: &mut rhai::Map,
+    body: Option<&Body>,
+    ttl: Option<TimeDelta>,
+    min_interval: Option<Duration>,
+    with_status: bool,
+) -> Result<Response, Box<EvalAltResult>> {
+    const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+    // credentials and headers may select different responses, e.g. per user
+    let key = {
+        let option = |name: &str| opts.get(name).map(|v| v.to_string()).unwrap_or_default();
+        let headers = opts
+            .get("headers")
+            .and_then(|h| h.read_lock::<rhai::Map>())
+            .map(|h| {
+                h.iter()
+                    .map(|(k, v)| format!("{}: {}\n", k.to_ascii_lowercase(), v))
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+        format!(
+            "{} {} {}\n{}\n{}\n{}",
+            option("method"),
+            path,
+            body.map_or("", |b| &b.data),
+            option("bearer"),
+            option("basic_auth"),
+            headers
+        )
+    };
+    let fresh =
+        |fetched: i64| ttl.is_some_and(|ttl| ((unix_time() - fetched) as i128) * NANOS_PER_SEC < ttl.as_nanoseconds());
+    let cached = match cache::load(context, &key)? {
+        Some(entry) if fresh(entry.fetched) => {
+            debug!("Fetching {:?} from cache", path);
+            return Ok(entry.response);
+        }
+        cached => cached.map(|entry| entry.response),
+    };
+
+    let limited =
+        |interval| ratelimit::host(path).is_some_and(|host| !ratelimit::remaining(context, &host, interval).is_zero());
+    let cached = match cached {
+        Some(cached) if min_interval.is_some_and(limited) => {
+            debug!("Rate limit for {:?} exceeded, using cached response", path);
+            return Ok(cached);
+        }
+        cached => cached,
+    };
+
+    match (
+        request(context, path, opts, body, cached.as_ref(), min_interval, with_status),
+        cached,
+    ) {
+        (Ok(response), Some(mut cached)) if response.status == 304 => {
+            debug!("Response for {:?} not modified, using cached response", path);
+            // servers may send updated validators along
+            for name in ["etag", "last-modified"] {
+                if let Some(value) = response.header(name) {
+                    cached.headers.insert(name.to_string(), value.clone());
+                }
+            }
+            cache::save(&key, &cached)?;
+            Ok(cached)
+        }
+        // error statuses are only returned with `with_status` and never cached
+        (Ok(response), _) if response.status >= 400 => Ok(response),
+        (Ok(response), _) => {
+            if ttl.is_some() || response.header("ETag").is_some() || response.header("Last-Modified").is_some() {
+                cache::save(&key, &response)?;
+            }
+            Ok(response)
+        }
+        (Err(Error::Request(err)), Some(cached)) if ttl.is_some() => {
+            warn!("Failed to fetch {:?}, using cached response: {}", path, err);
+            Ok(cached)
+        }
+        (Err(err), _) => Err(err.into()),
+    }
+}
+
+// Fetches the URL with the given options, see the fetch function of scripts
+pub fn fetch(context: &NativeCallContext, path: &str, mut opts: rhai::Map) -> Result<Dynamic, Box<EvalAltResult>> {
+    let cache_ttl = opts.extract::<TimeDelta>("cache_ttl")?;
+    let min_interval = match opts.extract::<TimeDelta>("min_interval")? {
+        Some(delta) => Some(delta.to_duration().ok_or("min_interval out of range")?),
+        None => None,
+    };
+    let body = body(&mut opts)?;
+    let response_type = opts.extract::<ImmutableString>("response_type")?;
+    let response_type = response_type.as_ref().map(|s| s.as_str());
+    // only these response types expose the status to the script
+    let with_status = matches!(response_type, Some("status" | "full"));
+    let response = match (mocked(path), cache_ttl) {
+        (Some(file), _) => {
+            debug!("Fetching {:?} from mock {:?}", path, file);
+            let body = fs::read(&file).map_err(|e| format!("Failed to read mock {:?}: {}", file, e))?;
+            Response {
+                status: 200,
+                status_text: "OK".to_string(),
+                headers: BTreeMap::new(),
+                body,
+            }
+        }
+        (None, ttl) if cacheable(&opts, body.as_ref(), ttl) => {
+            cached_request(context, path, &mut opts, body.as_ref(), ttl, min_interval, with_status)?
+        }
+        (None, _) => request(context, path, &mut opts, body.as_ref(), None, min_interval, with_status)?,
+    };
+    match response_type {
+        Some("json") | None => context.engine().parse_json(response.text()?, true).map(Dynamic::from),
+        Some("string") => Ok(Dynamic::from(response.text()?)),
+        Some("bytes") => Ok(Dynamic::from_blob(response.body)),
+        // e.g. for data URIs of images
+        Some("base64") => Ok(Dynamic::from(STANDARD.encode(response.body))),
+        Some("status") => {
+            let mut result = rhai::Map::new();
+            result.insert("status".into(), Dynamic::from_int(response.status));
+            result.insert("status_text".into(), response.status_text.clone().into());
+            result.insert("data".into(), Dynamic::from(response.text()?));
+            Ok(Dynamic::from_map(result))
+        }
+        // like status, but with the response headers
+        Some("full") => Ok(Dynamic::from_map(response.into_map()?)),
+        Some(r) => Err(format!("response_type not supported: {}", r).into()),
+    }
+}
+
+#[export_module]
+pub mod globals {
+    #[rhai_fn(name = "fetch", return_raw, global)]
+    pub fn fetch(context: NativeCallContext, path: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        fetch_with_opts(context, path, rhai::Map::new())
+    }
+
+    #[rhai_fn(name = "fetch", return_raw, global)]
+    pub fn fetch_with_opts(
+        context: NativeCallContext,
+        path: &str,
+        opts: rhai::Map,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::fetch(&context, path, opts)
+    }
+
+    // Parses a JSON object, e.g. the data of a full response
+    #[rhai_fn(name = "parse_json", return_raw, global)]
+    pub fn parse_json(context: NativeCallContext, json: &str) -> Result<rhai::Map, Box<EvalAltResult>> {
+        context.engine().parse_json(json, true)
+    }
+}
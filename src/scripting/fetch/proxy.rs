@@ -0,0 +1,31 @@
+use std::env;
+
+use url::Url;
+
+fn var(name: &str) -> Option<String> {
+    // like curl, ignore HTTP_PROXY which CGI scripts set from the Proxy request
+    // header (httpoxy)
+    let upper = match name {
+        "http_proxy" => None,
+        _ => env::var(name.to_uppercase()).ok(),
+    };
+    env::var(name).ok().or(upper).filter(|v| !v.is_empty())
+}
+
+// Returns the proxy configured for the URL in the environment, following the
+// conventions of curl: http_proxy, https_proxy, or all_proxy, unless the host
+// is listed in no_proxy. Only the lowercase http_proxy is supported.
+pub fn from_env(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+    if let Some(no_proxy) = var("no_proxy") {
+        let excluded = no_proxy.split(',').map(str::trim).any(|pattern| {
+            let domain = pattern.trim_start_matches('.');
+            pattern == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        });
+        if excluded {
+            return None;
+        }
+    }
+    var(&format!("{}_proxy", url.scheme())).or_else(|| var("all_proxy"))
+}
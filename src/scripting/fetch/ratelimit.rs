@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use rhai::{Dynamic, EvalAltResult, NativeCallContext};
+use url::Url;
+
+use super::Error;
+use crate::scripting::json;
+use crate::scripting::store::{state_dir, write_file};
+
+thread_local! {
+    // Time of the last request per host, used if no state directory is
+    // configured and thus only kept while the dashboard is running
+    static LAST_REQUESTS: RefCell<HashMap<String, i64>> = RefCell::new(HashMap::new());
+}
+
+// Maps hosts to the time of their last request in milliseconds since the
+// Unix epoch, so that the limit also holds across restarts
+const FILE: &str = "ratelimit.json";
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+pub fn host(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+// Unreadable files are treated as empty, the worst case is an early request
+fn load(context: &NativeCallContext) -> Option<rhai::Map> {
+    let path = state_dir().ok()?.join(FILE);
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Some(rhai::Map::new()),
+        Err(err) => {
+            warn!("Failed to read {:?}: {}", path, err);
+            return Some(rhai::Map::new());
+        }
+    };
+    match context.engine().parse_json(json, true) {
+        Ok(map) => Some(map),
+        Err(err) => {
+            warn!("Ignoring invalid {:?}: {}", path, err);
+            Some(rhai::Map::new())
+        }
+    }
+}
+
+fn last_request(context: &NativeCallContext, host: &str) -> Option<i64> {
+    match load(context) {
+        Some(map) => map.get(host).and_then(|t| t.as_int().ok()),
+        None => LAST_REQUESTS.with(|last| last.borrow().get(host).copied()),
+    }
+}
+
+fn record(context: &NativeCallContext, host: &str) -> Result<(), Box<EvalAltResult>> {
+    let now = unix_millis();
+    match load(context) {
+        Some(mut map) => {
+            map.insert(host.into(), Dynamic::from_int(now));
+            let json = json::to_json(&Dynamic::from_map(map))?;
+            write_file(&state_dir()?.join(FILE), json.as_bytes())
+        }
+        None => {
+            LAST_REQUESTS.with(|last| last.borrow_mut().insert(host.to_string(), now));
+            Ok(())
+        }
+    }
+}
+
+// Returns how long requests to the host must be held back
+pub fn remaining(context: &NativeCallContext, host: &str, interval: Duration) -> Duration {
+    let Some(last) = last_request(context, host) else {
+        return Duration::ZERO;
+    };
+    let elapsed = Duration::from_millis(unix_millis().saturating_sub(last).max(0) as u64);
+    interval.saturating_sub(elapsed)
+}
+
+// Waits until the minimum interval since the last request to the host has
+// passed, unless that takes longer than the timeout, and records the request
+pub fn acquire(context: &NativeCallContext, host: &str, interval: Duration, timeout: Duration) -> Result<(), Error> {
+    let wait = remaining(context, host, interval);
+    if wait > timeout {
+        return Err(Error::Request(format!(
+            "rate limit for {} exceeded, next request allowed in {}s",
+            host,
+            wait.as_secs_f64().ceil()
+        )));
+    }
+    if !wait.is_zero() {
+        debug!("Waiting {:?} before requesting {:?}", wait, host);
+        thread::sleep(wait);
+    }
+    record(context, host)?;
+    Ok(())
+}
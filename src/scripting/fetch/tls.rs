@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rhai::{EvalAltResult, ImmutableString};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+
+use super::Extract;
+
+// TLS settings from the tls option of fetch, e.g. for local services with
+// self-signed certificates
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tls {
+    // skips verification of the server certificate entirely
+    insecure: bool,
+    // PEM file with additional CA certificates to trust
+    ca_file: Option<PathBuf>,
+}
+
+impl Tls {
+    pub fn from_opts(opts: &mut rhai::Map) -> Result<Self, Box<EvalAltResult>> {
+        let Some(mut tls) = opts.extract::<rhai::Map>("tls")? else {
+            return Ok(Tls::default());
+        };
+        Ok(Tls {
+            insecure: tls.extract::<bool>("insecure")?.unwrap_or(false),
+            ca_file: tls
+                .extract::<ImmutableString>("ca_file")?
+                .map(|f| PathBuf::from(f.as_str())),
+        })
+    }
+
+    // Returns the client config, or None if the defaults of ureq apply
+    pub fn client_config(&self) -> Result<Option<Arc<ClientConfig>>, Box<EvalAltResult>> {
+        if *self == Tls::default() {
+            return Ok(None);
+        }
+
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(
+            webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+            }),
+        );
+        if let Some(path) = &self.ca_file {
+            let certs = File::open(path)
+                .and_then(|file| rustls_pemfile::certs(&mut BufReader::new(file)))
+                .map_err(|err| format!("failed to read CA file {:?}: {}", path, err))?;
+            if certs.is_empty() {
+                return Err(format!("no certificates found in CA file {:?}", path).into());
+            }
+            for cert in certs {
+                roots
+                    .add(&Certificate(cert))
+                    .map_err(|err| format!("invalid certificate in CA file {:?}: {}", path, err))?;
+            }
+        }
+
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        if self.insecure {
+            config.dangerous().set_certificate_verifier(Arc::new(NoVerification));
+        }
+        Ok(Some(Arc::new(config)))
+    }
+}
+
+// Accepts any server certificate, the handshake signatures are still checked
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use log::debug;
+use rhai::plugin::*;
+use rhai::{Blob, EvalAltResult};
+
+use super::store::{self, state_dir};
+
+thread_local! {
+    // Directory for resolving relative paths, the same as for templates
+    static RESOURCES_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+pub fn set_resources_dir(dir: PathBuf) {
+    RESOURCES_DIR.with(|resources_dir| *resources_dir.borrow_mut() = Some(dir));
+}
+
+// Resolves the path relative to the resources directory. Parent components
+// are rejected, so that paths cannot leave the directory lexically.
+fn resolve(path: &str) -> Result<PathBuf, Box<EvalAltResult>> {
+    let path = Path::new(path);
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(format!("path {:?} must not contain ..", path).into());
+    }
+    let resources_dir = RESOURCES_DIR
+        .with(|dir| dir.borrow().clone())
+        .ok_or("no resources directory configured, see --resources")?;
+    Ok(resources_dir.join(path))
+}
+
+// Ensures that the existing path does not lead outside of the resources and
+// state directories, e.g. via symbolic links. Errors refer to the target,
+// which may be inside the checked path.
+fn check(path: &Path, target: &Path) -> Result<(), Box<EvalAltResult>> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| format!("failed to access {:?}: {}", path, err))?;
+    let roots = [RESOURCES_DIR.with(|dir| dir.borrow().clone()), state_dir().ok()];
+    let allowed = roots
+        .into_iter()
+        .flatten()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| canonical.starts_with(root));
+    match allowed {
+        true => Ok(()),
+        false => Err(format!("{:?} is outside of the resources and state directories", target).into()),
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, Box<EvalAltResult>> {
+    let path = resolve(path)?;
+    check(&path, &path)?;
+    debug!("Reading file {:?}", path);
+    fs::read(&path).map_err(|err| format!("failed to read {:?}: {}", path, err).into())
+}
+
+fn write(path: &str, data: &[u8]) -> Result<(), Box<EvalAltResult>> {
+    let path = resolve(path)?;
+    // missing parent directories are created, as long as they are inside
+    let existing = path.ancestors().skip(1).find(|p| p.exists()).ok_or("invalid path")?;
+    check(existing, &path)?;
+    if path.is_symlink() || path.exists() {
+        check(&path, &path)?;
+    }
+    debug!("Writing file {:?}", path);
+    store::write_file(&path, data)
+}
+
+#[export_module]
+pub mod globals {
+    // Reads a text file from the resources or state directory, relative
+    // paths are resolved against the resources directory
+    #[rhai_fn(return_raw, global)]
+    pub fn read_file(path: &str) -> Result<String, Box<EvalAltResult>> {
+        String::from_utf8(super::read(path)?).map_err(|_| format!("{} is not valid UTF-8, use read_blob", path).into())
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn read_blob(path: &str) -> Result<Blob, Box<EvalAltResult>> {
+        super::read(path)
+    }
+
+    // Writes the file atomically, e.g. icons for the template to reference
+    #[rhai_fn(return_raw, global)]
+    pub fn write_file(path: &str, data: &str) -> Result<(), Box<EvalAltResult>> {
+        super::write(path, data.as_bytes())
+    }
+
+    #[rhai_fn(name = "write_file", return_raw, global)]
+    pub fn write_blob(path: &str, data: Blob) -> Result<(), Box<EvalAltResult>> {
+        super::write(path, &data)
+    }
+}
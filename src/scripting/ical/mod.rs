@@ -0,0 +1,371 @@
+use std::collections::HashSet;
+
+use rhai::plugin::*;
+use rhai::{Array, Dynamic, EvalAltResult, NativeCallContext};
+use tz::{DateTime, TimeZoneRef, UtcDateTime};
+
+use self::rrule::{Date, Rule};
+use super::datetime::{from_unix_time, local_tz};
+use super::fetch;
+
+mod rrule;
+
+// Recurring events are not expanded beyond this many occurrences, which
+// protects against rules that hardly ever match
+const MAX_OCCURRENCES: usize = 100_000;
+
+// Content line of the form NAME;PARAM=VALUE:VALUE
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        // parameter values may contain colons if they are quoted
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                quoted = !quoted;
+                None
+            }
+            ':' if !quoted => Some(i),
+            _ => None,
+        })?;
+        let mut parts = line[..colon].split(';');
+        let name = parts.next()?.to_ascii_uppercase();
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+            .collect();
+        Some(Property {
+            name,
+            params,
+            value: line[colon + 1..].to_string(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::new();
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            match (c, c == '\\') {
+                (_, true) => match chars.next() {
+                    Some('n' | 'N') => text.push('\n'),
+                    Some(c) => text.push(c),
+                    None => (),
+                },
+                (c, false) => text.push(c),
+            }
+        }
+        text
+    }
+}
+
+// Returns the content lines of the components with the given name, e.g.
+// VEVENT. Long lines are folded by inserting a line break and a space.
+fn components(ics: &str, name: &str) -> Vec<Vec<Property>> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut components = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    let mut depth = 0;
+    for property in lines.iter().filter_map(|l| Property::parse(l)) {
+        match (property.name.as_str(), property.value.eq_ignore_ascii_case(name)) {
+            ("BEGIN", true) if current.is_none() => current = Some(Vec::new()),
+            ("END", true) if depth == 0 => components.extend(current.take()),
+            // properties of nested components like alarms are ignored
+            ("BEGIN", _) if current.is_some() => depth += 1,
+            ("END", _) if current.is_some() => depth -= 1,
+            _ if depth == 0 => {
+                if let Some(current) = &mut current {
+                    current.push(property);
+                }
+            }
+            _ => (),
+        }
+    }
+    components
+}
+
+#[derive(Clone, Copy)]
+enum Zone {
+    Utc,
+    Tz(TimeZoneRef<'static>),
+}
+
+// Value of DTSTART and similar properties, either a date for all-day events
+// or a date with time. Times without zone are in the local time zone.
+#[derive(Clone, Copy)]
+struct Time {
+    date: Date,
+    time: Option<(i64, i64, i64)>,
+    zone: Zone,
+}
+
+impl Time {
+    fn parse(value: &str, tzid: Option<&str>) -> Option<Self> {
+        let value = value.trim();
+        let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<i64>().ok();
+        let date = Date::new(number(0..4)?, number(4..6)?, number(6..8)?)?;
+        let zone = match (value.ends_with(['Z', 'z']), tzid) {
+            (true, _) => Zone::Utc,
+            // unknown names, e.g. of Windows time zones, fall back to local time
+            (false, Some(tzid)) => Zone::Tz(tzdb::tz_by_name(tzid.trim_start_matches('/')).unwrap_or(local_tz())),
+            (false, None) => Zone::Tz(local_tz()),
+        };
+        let time = match value.get(8..9) {
+            Some("T" | "t") => Some((number(9..11)?, number(11..13)?, number(13..15)?)),
+            _ => None,
+        };
+        Some(Time { date, time, zone })
+    }
+
+    fn from_property(property: &Property) -> Option<Self> {
+        Time::parse(&property.value, property.param("TZID"))
+    }
+
+    fn with_date(self, date: Date) -> Self {
+        Time { date, ..self }
+    }
+
+    // Seconds since the Unix epoch, all-day events start at local midnight
+    fn unix(&self) -> Option<i64> {
+        let (hour, minute, second) = self.time.unwrap_or((0, 0, 0));
+        let (year, month, day) = (
+            self.date.year.try_into().ok()?,
+            self.date.month as u8,
+            self.date.day as u8,
+        );
+        let (hour, minute, second) = (hour.try_into().ok()?, minute.try_into().ok()?, second.try_into().ok()?);
+        match self.zone {
+            Zone::Utc => UtcDateTime::new(year, month, day, hour, minute, second, 0)
+                .ok()
+                .map(|t| t.unix_time()),
+            Zone::Tz(tz) => DateTime::find(year, month, day, hour, minute, second, 0, tz)
+                .ok()?
+                .earliest()
+                .map(|t| t.unix_time()),
+        }
+    }
+}
+
+// Parses durations like P1D, PT1H30M, or -PT15M into seconds
+fn parse_duration(s: &str) -> Option<i64> {
+    let (sign, s) = match s.trim().strip_prefix('-') {
+        Some(s) => (-1, s),
+        None => (1, s.trim().trim_start_matches('+')),
+    };
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in s.strip_prefix('P')?.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'T' => continue,
+            'W' => 7 * 86400,
+            'D' => 86400,
+            'H' => 3600,
+            'M' => 60,
+            'S' => 1,
+            _ => return None,
+        };
+        seconds += number.parse::<i64>().ok()? * unit;
+        number.clear();
+    }
+    Some(sign * seconds)
+}
+
+struct Event {
+    properties: Vec<Property>,
+    start: Time,
+    // for all-day events in days, otherwise in seconds
+    length: i64,
+}
+
+impl Event {
+    fn parse(properties: Vec<Property>) -> Option<Self> {
+        let property = |name: &str| properties.iter().find(|p| p.name == name);
+        let start = Time::from_property(property("DTSTART")?)?;
+        let all_day = start.time.is_none();
+        let end = property("DTEND").and_then(Time::from_property);
+        let length = match (end, property("DURATION").and_then(|d| parse_duration(&d.value))) {
+            (Some(end), _) if all_day => end.date.days() - start.date.days(),
+            (Some(end), _) => end.unix()? - start.unix()?,
+            (None, Some(seconds)) if all_day => seconds / 86400,
+            (None, Some(seconds)) => seconds,
+            (None, None) if all_day => 1,
+            (None, None) => 0,
+        };
+        Some(Event {
+            properties,
+            start,
+            length: length.max(0),
+        })
+    }
+
+    fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    fn text(&self, name: &str) -> Dynamic {
+        self.property(name)
+            .map(|p| Dynamic::from(p.text()))
+            .unwrap_or(Dynamic::UNIT)
+    }
+
+    fn uid(&self) -> String {
+        self.property("UID").map(|p| p.value.clone()).unwrap_or_default()
+    }
+
+    // Start times of the instances listed by EXDATE or RDATE properties
+    fn dates(&self, name: &str) -> Vec<Time> {
+        self.properties
+            .iter()
+            .filter(|p| p.name == name)
+            .flat_map(|p| p.value.split(',').filter_map(|v| Time::parse(v, p.param("TZID"))))
+            .collect()
+    }
+
+    fn end(&self, start: Time) -> Option<i64> {
+        match start.time {
+            None => start.with_date(start.date.add_days(self.length)).unix(),
+            Some(_) => Some(start.unix()? + self.length),
+        }
+    }
+
+    // Returns the start times of all instances up to the last date
+    fn instances(&self, last: Date) -> Result<Vec<Time>, String> {
+        let Some(rule) = self.property("RRULE") else {
+            return Ok(vec![self.start]);
+        };
+        let rule = Rule::parse(&rule.value)?;
+        // UNTIL is inclusive, and in UTC unless the event is all-day or floating
+        let until = match &rule.until {
+            Some(until) => {
+                let until = Time::parse(until, None).ok_or_else(|| format!("invalid UNTIL {:?}", until))?;
+                match until.time {
+                    Some(_) => until.unix(),
+                    None => until.with_date(until.date.add_days(1)).unix().map(|t| t - 1),
+                }
+            }
+            None => None,
+        };
+
+        let excluded = self
+            .dates("EXDATE")
+            .iter()
+            .filter_map(Time::unix)
+            .collect::<HashSet<_>>();
+        let mut instances = Vec::new();
+        for (i, date) in rule.dates(self.start.date, last).into_iter().enumerate() {
+            let instance = self.start.with_date(date);
+            let unix = instance.unix().unwrap_or_default();
+            if rule.count.is_some_and(|count| i as i64 >= count) || until.is_some_and(|until| unix > until) {
+                break;
+            }
+            if i >= MAX_OCCURRENCES {
+                break;
+            }
+            if !excluded.contains(&unix) {
+                instances.push(instance);
+            }
+        }
+        instances.extend(self.dates("RDATE"));
+        Ok(instances)
+    }
+}
+
+// Returns the events of the calendar overlapping the time window, with
+// recurring events expanded into their instances
+fn events(ics: &str, from: &DateTime, to: &DateTime) -> Result<Array, Box<EvalAltResult>> {
+    let (from, to) = (from.unix_time(), to.unix_time());
+    // dates are compared before time zones are applied
+    let last = Date::from_days(to.div_euclid(86400) + 2);
+
+    let events = components(ics, "VEVENT")
+        .into_iter()
+        .filter_map(Event::parse)
+        .filter(|e| {
+            !e.property("STATUS")
+                .is_some_and(|s| s.value.eq_ignore_ascii_case("CANCELLED"))
+        })
+        .collect::<Vec<_>>();
+    // modified instances of recurring events replace the original ones
+    let modified = events
+        .iter()
+        .filter_map(|e| {
+            let recurrence_id = Time::from_property(e.property("RECURRENCE-ID")?)?.unix()?;
+            Some((e.uid(), recurrence_id))
+        })
+        .collect::<HashSet<_>>();
+
+    let mut instances = Vec::new();
+    for event in &events {
+        let uid = event.uid();
+        let is_modification = event.property("RECURRENCE-ID").is_some();
+        for start in event.instances(last)? {
+            let Some((start_unix, end_unix)) = start.unix().zip(event.end(start)) else {
+                continue;
+            };
+            if !is_modification && modified.contains(&(uid.clone(), start_unix)) {
+                continue;
+            }
+            let overlaps = start_unix < to && (end_unix > from || start_unix >= from);
+            if !overlaps {
+                continue;
+            }
+
+            let mut map = rhai::Map::new();
+            map.insert("summary".into(), event.text("SUMMARY"));
+            map.insert("description".into(), event.text("DESCRIPTION"));
+            map.insert("location".into(), event.text("LOCATION"));
+            map.insert("uid".into(), uid.clone().into());
+            map.insert("all_day".into(), start.time.is_none().into());
+            let datetime = |t| from_unix_time(t).map(Dynamic::from).unwrap_or(Dynamic::UNIT);
+            map.insert("start".into(), datetime(start_unix));
+            map.insert("end".into(), datetime(end_unix));
+            instances.push((start_unix, map));
+        }
+    }
+    instances.sort_by_key(|(start, _)| *start);
+    Ok(instances.into_iter().map(|(_, map)| map.into()).collect())
+}
+
+#[export_module]
+pub mod ical {
+    use tz::DateTime;
+
+    // Returns the events between from and to, sorted by start, as maps with
+    // summary, description, location, uid, start, end, and all_day
+    #[rhai_fn(return_raw)]
+    pub fn parse(ics: &str, from: DateTime, to: DateTime) -> Result<Array, Box<EvalAltResult>> {
+        super::events(ics, &from, &to)
+    }
+
+    #[rhai_fn(return_raw)]
+    pub fn fetch(
+        context: NativeCallContext,
+        url: &str,
+        from: DateTime,
+        to: DateTime,
+    ) -> Result<Array, Box<EvalAltResult>> {
+        let mut opts = rhai::Map::new();
+        opts.insert("response_type".into(), "string".into());
+        let ics = fetch::fetch(&context, url, opts)?.into_string()?;
+        super::events(&ics, &from, &to)
+    }
+}
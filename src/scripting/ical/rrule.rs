@@ -0,0 +1,459 @@
+// Recurrence rules of RFC 5545, as far as calendars exported by common
+// services use them. Occurrences are calculated on calendar dates, the time of
+// day is taken from the start of the event.
+
+use crate::scripting::datetime::days_in_month;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+}
+
+impl Date {
+    pub fn new(year: i64, month: i64, day: i64) -> Option<Self> {
+        let valid = (1..=12).contains(&month) && day >= 1 && day <= days_in_month(year, month);
+        valid.then_some(Date { year, month, day })
+    }
+
+    // Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    pub fn days(self) -> i64 {
+        let year = if self.month <= 2 { self.year - 1 } else { self.year };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((self.month + 9) % 12) + 2) / 5 + self.day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    pub fn from_days(days: i64) -> Self {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        Date { year, month, day }
+    }
+
+    pub fn add_days(self, days: i64) -> Self {
+        Date::from_days(self.days() + days)
+    }
+
+    // 0 for Monday to 6 for Sunday
+    pub fn weekday(self) -> i64 {
+        (self.days() + 3).rem_euclid(7)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    frequency: Frequency,
+    interval: i64,
+    pub count: Option<i64>,
+    // raw value, as its interpretation depends on the start of the event
+    pub until: Option<String>,
+    // weekdays with an optional ordinal, e.g. -1 for the last one in the month
+    by_day: Vec<(Option<i64>, i64)>,
+    by_month_day: Vec<i64>,
+    by_month: Vec<i64>,
+    by_set_pos: Vec<i64>,
+    week_start: i64,
+}
+
+fn weekday(s: &str) -> Option<i64> {
+    ["MO", "TU", "WE", "TH", "FR", "SA", "SU"]
+        .iter()
+        .position(|d| *d == s)
+        .map(|d| d as i64)
+}
+
+fn numbers(s: &str) -> Result<Vec<i64>, String> {
+    s.split(',')
+        .map(|n| {
+            n.trim_start_matches('+')
+                .parse()
+                .map_err(|_| format!("invalid number {:?}", n))
+        })
+        .collect()
+}
+
+impl Rule {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: 0,
+        };
+        let mut frequency = None;
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule part {:?}", part))?;
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        f => return Err(format!("unsupported frequency {}", f)),
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse().map_err(|_| format!("invalid interval {:?}", value))?,
+                "COUNT" => rule.count = Some(value.parse().map_err(|_| format!("invalid count {:?}", value))?),
+                "UNTIL" => rule.until = Some(value.to_string()),
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let day = day.trim().to_ascii_uppercase();
+                        if !day.is_ascii() {
+                            return Err(format!("invalid weekday {:?}", day));
+                        }
+                        let (ordinal, name) = day.split_at(day.len().saturating_sub(2));
+                        let ordinal = match ordinal.trim_start_matches('+') {
+                            "" => None,
+                            n => Some(n.parse().map_err(|_| format!("invalid weekday {:?}", day))?),
+                        };
+                        let weekday = weekday(name).ok_or_else(|| format!("invalid weekday {:?}", day))?;
+                        rule.by_day.push((ordinal, weekday));
+                    }
+                }
+                "BYMONTHDAY" => rule.by_month_day = numbers(value)?,
+                "BYMONTH" => rule.by_month = numbers(value)?,
+                "BYSETPOS" => rule.by_set_pos = numbers(value)?,
+                "WKST" => rule.week_start = weekday(value).ok_or_else(|| format!("invalid weekday {:?}", value))?,
+                _ => (),
+            }
+        }
+        rule.frequency = frequency.ok_or("rule without frequency")?;
+        if rule.interval < 1 {
+            return Err(format!("invalid interval {}", rule.interval));
+        }
+        Ok(rule)
+    }
+
+    // Returns the dates of occurrences from the start up to the last date, in
+    // ascending order. The start is always the first occurrence.
+    pub fn dates(&self, start: Date, last: Date) -> Vec<Date> {
+        let mut dates = vec![start];
+        for n in 0.. {
+            let (period_start, candidates) = self.period(start, n);
+            if period_start > last || period_start.year > 9999 {
+                break;
+            }
+            dates.extend(candidates.into_iter().filter(|d| *d > start && *d <= last));
+        }
+        dates
+    }
+
+    // Returns the first day and the sorted occurrences of the n-th period
+    fn period(&self, start: Date, n: i64) -> (Date, Vec<Date>) {
+        let step = n * self.interval;
+        let (period_start, mut candidates) = match self.frequency {
+            Frequency::Daily => {
+                let date = start.add_days(step);
+                let matches_day = self.by_day.is_empty() || self.by_day.iter().any(|(_, wd)| *wd == date.weekday());
+                let matches_month_day = self.by_month_day.is_empty() || self.matches_month_day(date);
+                (
+                    date,
+                    if matches_day && matches_month_day {
+                        vec![date]
+                    } else {
+                        vec![]
+                    },
+                )
+            }
+            Frequency::Weekly => {
+                let offset = (start.weekday() - self.week_start).rem_euclid(7);
+                let week = start.add_days(step * 7 - offset);
+                let dates = match self.by_day.is_empty() {
+                    true => vec![week.add_days(offset)],
+                    false => self
+                        .by_day
+                        .iter()
+                        .map(|(_, wd)| week.add_days((wd - self.week_start).rem_euclid(7)))
+                        .collect(),
+                };
+                (week, dates)
+            }
+            Frequency::Monthly => {
+                let months = start.year * 12 + start.month - 1 + step;
+                let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+                (Date { year, month, day: 1 }, self.in_month(start, year, month))
+            }
+            Frequency::Yearly => {
+                let year = start.year + step;
+                let first = Date { year, month: 1, day: 1 };
+                let last = Date {
+                    year,
+                    month: 12,
+                    day: 31,
+                };
+                let dates = if self.by_month.is_empty() && self.by_month_day.is_empty() && !self.by_day.is_empty() {
+                    // weekdays of the whole year, e.g. the 20th Monday
+                    self.by_day_in(first, last)
+                } else if self.by_month.is_empty() {
+                    self.in_month(start, year, start.month)
+                } else {
+                    self.by_month
+                        .iter()
+                        .flat_map(|m| self.in_month(start, year, *m))
+                        .collect()
+                };
+                (first, dates)
+            }
+        };
+
+        if !self.by_month.is_empty() {
+            candidates.retain(|d| self.by_month.contains(&d.month));
+        }
+        candidates.sort();
+        candidates.dedup();
+        if !self.by_set_pos.is_empty() {
+            let len = candidates.len() as i64;
+            let mut selected = self
+                .by_set_pos
+                .iter()
+                .filter_map(|pos| match *pos {
+                    pos if pos > 0 && pos <= len => Some(candidates[pos as usize - 1]),
+                    pos if pos < 0 && -pos <= len => Some(candidates[(len + pos) as usize]),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            selected.sort();
+            candidates = selected;
+        }
+        (period_start, candidates)
+    }
+
+    fn matches_month_day(&self, date: Date) -> bool {
+        let len = days_in_month(date.year, date.month);
+        self.by_month_day
+            .iter()
+            .any(|d| *d == date.day || (*d < 0 && len + d + 1 == date.day))
+    }
+
+    fn in_month(&self, start: Date, year: i64, month: i64) -> Vec<Date> {
+        let (Some(first), Some(last)) = (
+            Date::new(year, month, 1),
+            Date::new(year, month, days_in_month(year, month)),
+        ) else {
+            return vec![];
+        };
+        if !self.by_month_day.is_empty() {
+            let len = days_in_month(year, month);
+            self.by_month_day
+                .iter()
+                .filter_map(|d| Date::new(year, month, if *d < 0 { len + d + 1 } else { *d }))
+                .filter(|d| self.by_day.is_empty() || self.by_day.iter().any(|(_, wd)| *wd == d.weekday()))
+                .collect()
+        } else if !self.by_day.is_empty() {
+            self.by_day_in(first, last)
+        } else {
+            // months without the day of the start are skipped
+            Date::new(year, month, start.day).into_iter().collect()
+        }
+    }
+
+    // Expands the weekdays within the range, where ordinals count from the
+    // beginning or the end of the range
+    fn by_day_in(&self, first: Date, last: Date) -> Vec<Date> {
+        let mut dates = Vec::new();
+        for (ordinal, weekday) in &self.by_day {
+            let first_match = first.add_days((weekday - first.weekday()).rem_euclid(7));
+            let all = (0..)
+                .map(|w| first_match.add_days(w * 7))
+                .take_while(|d| *d <= last)
+                .collect::<Vec<_>>();
+            match *ordinal {
+                None => dates.extend(all),
+                Some(n) if n > 0 => dates.extend(all.get(n as usize - 1)),
+                Some(n) if n < 0 => dates.extend(all.len().checked_sub(n.unsigned_abs() as usize).map(|i| all[i])),
+                Some(_) => (),
+            }
+        }
+        dates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i64, month: i64, day: i64) -> Date {
+        Date::new(year, month, day).unwrap()
+    }
+
+    // Occurrences of the rule starting on the date, as "YYYY-MM-DD"
+    fn dates(rule: &str, start: Date, last: Date) -> Vec<String> {
+        Rule::parse(rule)
+            .unwrap()
+            .dates(start, last)
+            .iter()
+            .map(|d| format!("{:04}-{:02}-{:02}", d.year, d.month, d.day))
+            .collect()
+    }
+
+    #[test]
+    fn date_conversion() {
+        assert_eq!(date(1970, 1, 1).days(), 0);
+        assert_eq!(Date::from_days(19723), date(2024, 1, 1));
+        assert_eq!(date(2024, 2, 28).add_days(1), date(2024, 2, 29));
+        assert_eq!(date(2024, 1, 1).weekday(), 0);
+        assert_eq!(Date::new(2023, 2, 29), None);
+    }
+
+    #[test]
+    fn daily() {
+        assert_eq!(
+            dates("FREQ=DAILY;INTERVAL=2", date(2024, 2, 27), date(2024, 3, 4)),
+            ["2024-02-27", "2024-02-29", "2024-03-02", "2024-03-04"]
+        );
+        // weekdays only, as created by Outlook
+        assert_eq!(
+            dates("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR", date(2024, 3, 7), date(2024, 3, 12)),
+            ["2024-03-07", "2024-03-08", "2024-03-11", "2024-03-12"]
+        );
+    }
+
+    #[test]
+    fn weekly() {
+        assert_eq!(
+            dates("FREQ=WEEKLY;BYDAY=MO,WE,FR", date(2024, 3, 6), date(2024, 3, 13)),
+            ["2024-03-06", "2024-03-08", "2024-03-11", "2024-03-13"]
+        );
+        // every other week, counted from the week of the start
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,SU;WKST=MO",
+                date(2024, 3, 5),
+                date(2024, 3, 31)
+            ),
+            ["2024-03-05", "2024-03-10", "2024-03-19", "2024-03-24"]
+        );
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,SU;WKST=SU",
+                date(2024, 3, 5),
+                date(2024, 3, 31)
+            ),
+            ["2024-03-05", "2024-03-17", "2024-03-19", "2024-03-31"]
+        );
+    }
+
+    #[test]
+    fn monthly() {
+        // the 31st only exists in some months
+        assert_eq!(
+            dates("FREQ=MONTHLY", date(2024, 1, 31), date(2024, 6, 1)),
+            ["2024-01-31", "2024-03-31", "2024-05-31"]
+        );
+        assert_eq!(
+            dates("FREQ=MONTHLY;BYMONTHDAY=-1", date(2024, 1, 31), date(2024, 4, 30)),
+            ["2024-01-31", "2024-02-29", "2024-03-31", "2024-04-30"]
+        );
+        // the second Tuesday and the last Friday
+        assert_eq!(
+            dates("FREQ=MONTHLY;BYDAY=2TU", date(2024, 1, 9), date(2024, 3, 31)),
+            ["2024-01-09", "2024-02-13", "2024-03-12"]
+        );
+        assert_eq!(
+            dates("FREQ=MONTHLY;BYDAY=-1FR", date(2024, 1, 26), date(2024, 3, 31)),
+            ["2024-01-26", "2024-02-23", "2024-03-29"]
+        );
+        // Friday the 13th
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=FR;BYMONTHDAY=13",
+                date(2024, 9, 13),
+                date(2025, 12, 31)
+            ),
+            ["2024-09-13", "2024-12-13", "2025-06-13"]
+        );
+    }
+
+    #[test]
+    fn by_set_pos() {
+        // last weekday of the month, as created by Outlook
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+                date(2024, 1, 31),
+                date(2024, 4, 30)
+            ),
+            ["2024-01-31", "2024-02-29", "2024-03-29", "2024-04-30"]
+        );
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=SA,SU;BYSETPOS=1,2",
+                date(2024, 6, 1),
+                date(2024, 7, 31)
+            ),
+            ["2024-06-01", "2024-06-02", "2024-07-06", "2024-07-07"]
+        );
+    }
+
+    #[test]
+    fn yearly() {
+        assert_eq!(
+            dates("FREQ=YEARLY", date(2024, 2, 29), date(2032, 12, 31)),
+            ["2024-02-29", "2028-02-29", "2032-02-29"]
+        );
+        // Thanksgiving, as exported by Google
+        assert_eq!(
+            dates(
+                "FREQ=YEARLY;BYMONTH=11;BYDAY=4TH",
+                date(2023, 11, 23),
+                date(2025, 12, 31)
+            ),
+            ["2023-11-23", "2024-11-28", "2025-11-27"]
+        );
+        // the 20th Monday of the year
+        assert_eq!(
+            dates("FREQ=YEARLY;BYDAY=20MO", date(2024, 5, 13), date(2025, 12, 31)),
+            ["2024-05-13", "2025-05-19"]
+        );
+    }
+
+    #[test]
+    fn by_day_in() {
+        let rule = Rule::parse("FREQ=MONTHLY;BYDAY=1MO,-2MO,+5MO").unwrap();
+        assert_eq!(
+            rule.by_day_in(date(2024, 4, 1), date(2024, 4, 30)),
+            [date(2024, 4, 1), date(2024, 4, 22), date(2024, 4, 29)]
+        );
+        assert_eq!(
+            rule.by_day_in(date(2024, 5, 1), date(2024, 5, 31)),
+            [date(2024, 5, 6), date(2024, 5, 20)]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Rule::parse("BYDAY=MO").is_err());
+        assert!(Rule::parse("FREQ=HOURLY").is_err());
+        assert!(Rule::parse("FREQ=DAILY;INTERVAL=0").is_err());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=XX").is_err());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=ÉA").is_err());
+        assert!(Rule::parse("FREQ=WEEKLY;BYDAY=1ÉA").is_err());
+        assert!(Rule::parse("freq=weekly;byday=mo").is_ok());
+    }
+}
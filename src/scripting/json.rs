@@ -0,0 +1,55 @@
+use std::fmt::Write;
+
+use rhai::{Dynamic, EvalAltResult};
+
+use crate::exit::json_string;
+
+// Serializes unit, booleans, numbers, strings, arrays, and maps as JSON.
+// Rhai's own to_json() formats nested arrays and strings in Rust syntax.
+pub fn to_json(value: &Dynamic) -> Result<String, Box<EvalAltResult>> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Dynamic) -> Result<(), Box<EvalAltResult>> {
+    if value.is_unit() {
+        out.push_str("null");
+    } else if let Ok(b) = value.as_bool() {
+        write!(out, "{}", b).unwrap();
+    } else if let Ok(i) = value.as_int() {
+        write!(out, "{}", i).unwrap();
+    } else if let Ok(f) = value.as_float() {
+        if !f.is_finite() {
+            return Err(format!("{} cannot be represented in JSON", f).into());
+        }
+        write!(out, "{:?}", f).unwrap();
+    } else if let Ok(c) = value.as_char() {
+        out.push_str(&json_string(&c.to_string()));
+    } else if let Some(s) = value.read_lock::<rhai::ImmutableString>() {
+        out.push_str(&json_string(&s));
+    } else if let Some(array) = value.read_lock::<rhai::Array>() {
+        out.push('[');
+        for (i, item) in array.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_value(out, item)?;
+        }
+        out.push(']');
+    } else if let Some(map) = value.read_lock::<rhai::Map>() {
+        out.push('{');
+        for (i, (key, item)) in map.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(&json_string(key));
+            out.push(':');
+            write_value(out, item)?;
+        }
+        out.push('}');
+    } else {
+        return Err(format!("{} cannot be represented in JSON", value.type_name()).into());
+    }
+    Ok(())
+}
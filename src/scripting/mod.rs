@@ -1,15 +1,41 @@
+use std::cell::Cell;
 use std::env::VarError;
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
 
 use log::debug;
 use rhai::plugin::*;
-use rhai::{EvalAltResult, Scope};
+use rhai::{Dynamic, EvalAltResult, Scope};
 
 use crate::document::Document;
 
+use self::datetime::TimeDelta;
+
+pub use self::datetime::local_tz;
+pub use self::exec::allow_exec;
+pub use self::fetch::mock as mock_fetch;
+pub use self::files::set_resources_dir;
+pub use self::store::set_state_dir;
+
+mod chart;
+mod color;
 mod datetime;
 mod document;
+mod encoding;
+mod exec;
 mod fetch;
+mod files;
+mod ical;
+mod json;
+mod oauth;
+mod parse;
+mod query;
+mod rss;
+mod stats;
+mod store;
+mod strings;
+mod xml;
 
 fn env(s: &str) -> String {
     match std::env::var(s) {
@@ -19,9 +45,41 @@ fn env(s: &str) -> String {
     }
 }
 
+// Reason for the current run, exposed to scripts as the WAKEUP constant
+#[derive(Debug, Clone)]
+pub struct Wakeup {
+    // one of "start", "timer", "carousel", "key", "tap", "swipe", "network",
+    // "remote", "mqtt", or "reload"
+    pub reason: &'static str,
+    pub key: Option<String>,
+    pub action: Option<String>,
+    // payload of the received MQTT message
+    pub payload: Option<String>,
+}
+
+impl Wakeup {
+    pub fn new(reason: &'static str) -> Self {
+        Wakeup {
+            reason,
+            key: None,
+            action: None,
+            payload: None,
+        }
+    }
+}
+
+// Result of a script run
+pub struct Output {
+    pub document: Document,
+    // Overrides the duration until the next refresh, if set by the script
+    pub next_refresh: Option<Duration>,
+}
+
 pub struct Script {
     file: PathBuf,
     engine: rhai::Engine,
+    constants: Vec<(&'static str, Dynamic)>,
+    next_refresh: Rc<Cell<Option<Duration>>>,
 }
 
 impl Script {
@@ -31,28 +89,114 @@ impl Script {
         let datetime = exported_module!(datetime::datetime);
         let timedelta = exported_module!(datetime::timedelta);
         let alignment = exported_module!(document::alignment);
+        let direction = exported_module!(document::direction);
+        let overflow = exported_module!(document::overflow);
+        let chart = exported_module!(chart::chart);
+        let color = exported_module!(color::color);
+        let store = exported_module!(store::store);
+        let oauth = exported_module!(oauth::oauth);
+        let rss = exported_module!(rss::rss);
+        let ical = exported_module!(ical::ical);
         engine.register_static_module("datetime", datetime.into());
         engine.register_static_module("timedelta", timedelta.into());
         engine.register_static_module("alignment", alignment.into());
+        engine.register_static_module("direction", direction.into());
+        engine.register_static_module("overflow", overflow.into());
+        engine.register_static_module("chart", chart.into());
+        engine.register_static_module("color", color.into());
+        engine.register_static_module("store", store.into());
+        engine.register_static_module("oauth", oauth.into());
+        engine.register_static_module("rss", rss.into());
+        engine.register_static_module("ical", ical.into());
 
         let document = exported_module!(document::globals);
         let fetch = exported_module!(fetch::globals);
+        let xml = exported_module!(xml::globals);
+        let parse = exported_module!(parse::globals);
+        let query = exported_module!(query::globals);
+        let files = exported_module!(files::globals);
+        let exec = exported_module!(exec::globals);
+        let strings = exported_module!(strings::globals);
+        let encoding = exported_module!(encoding::globals);
+        let stats = exported_module!(stats::globals);
         engine.register_global_module(document.into());
         engine.register_global_module(fetch.into());
+        engine.register_global_module(xml.into());
+        engine.register_global_module(parse.into());
+        engine.register_global_module(query.into());
+        engine.register_global_module(files.into());
+        engine.register_global_module(exec.into());
+        engine.register_global_module(strings.into());
+        engine.register_global_module(encoding.into());
+        engine.register_global_module(stats.into());
         engine.register_fn("env", env);
 
-        Script { file, engine }
+        let next_refresh = Rc::new(Cell::new(None));
+        let next = next_refresh.clone();
+        engine.register_fn(
+            "set_next_refresh",
+            move |delta: TimeDelta| -> Result<(), Box<EvalAltResult>> {
+                let nanos = u64::try_from(delta.as_nanoseconds()).map_err(|_| "refresh interval out of range")?;
+                next.set(Some(Duration::from_nanos(nanos)));
+                Ok(())
+            },
+        );
+
+        Script {
+            file,
+            engine,
+            constants: Vec::new(),
+            next_refresh,
+        }
+    }
+
+    // Defines a constant visible to the script in subsequent runs
+    pub fn set_constant(&mut self, name: &'static str, value: impl Into<Dynamic>) {
+        let value = value.into();
+        match self.constants.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.constants.push((name, value)),
+        }
+    }
+
+    // Exposes user provided arguments to the script as the args map
+    pub fn set_args(&mut self, args: &[(String, String)]) {
+        let map: rhai::Map = args.iter().map(|(k, v)| (k.into(), v.clone().into())).collect();
+        self.set_constant("args", map);
+    }
+
+    // Lets the script know why it is being run
+    pub fn set_wakeup(&mut self, wakeup: &Wakeup) {
+        let optional = |s: &Option<String>| s.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT);
+        let mut map = rhai::Map::new();
+        map.insert("reason".into(), wakeup.reason.into());
+        map.insert("key".into(), optional(&wakeup.key));
+        map.insert("action".into(), optional(&wakeup.action));
+        self.set_constant("WAKEUP", map);
+
+        // the last MQTT message remains available until the next one arrives
+        if let Some(payload) = &wakeup.payload {
+            self.set_constant("mqtt_payload", payload.clone());
+        }
     }
 
-    pub fn run_with_document(&self, doc: Document) -> Result<Document, Box<EvalAltResult>> {
+    pub fn run_with_document(&self, doc: Document) -> Result<Output, Box<EvalAltResult>> {
         const NAME: &str = "document";
 
         debug!("Running script: {:?}", self.file.to_string_lossy());
 
         let mut scope = Scope::new();
+        for (name, value) in &self.constants {
+            scope.push_constant_dynamic(*name, value.clone());
+        }
         scope.push(NAME, doc);
+        self.next_refresh.set(None);
+        fetch::reset_cookies();
         self.engine.run_file_with_scope(&mut scope, self.file.to_path_buf())?;
 
-        scope.get_value(NAME).ok_or("document invalidated".into())
+        Ok(Output {
+            document: scope.get_value(NAME).ok_or("document invalidated")?,
+            next_refresh: self.next_refresh.take(),
+        })
     }
 }
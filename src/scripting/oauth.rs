@@ -0,0 +1,113 @@
+use log::debug;
+use rhai::plugin::*;
+use rhai::{Dynamic, EvalAltResult, ImmutableString, NativeCallContext};
+
+use super::fetch;
+use super::store::{self, unix_time};
+
+// Tokens are renewed this many seconds before they expire, so that they stay
+// valid for the requests of the current run
+const EXPIRY_MARGIN: i64 = 60;
+// Assumed lifetime of tokens if the server does not specify one
+const DEFAULT_LIFETIME: i64 = 3600;
+
+fn required(config: &rhai::Map, name: &str) -> Result<String, Box<EvalAltResult>> {
+    optional(config, name)?.ok_or_else(|| format!("oauth config is missing {}", name).into())
+}
+
+fn optional(config: &rhai::Map, name: &str) -> Result<Option<String>, Box<EvalAltResult>> {
+    match config.get(name) {
+        Some(value) => value
+            .clone()
+            .into_string()
+            .map(Some)
+            .map_err(|t| format!("oauth config {} must be a string, not {}", name, t).into()),
+        None => Ok(None),
+    }
+}
+
+#[export_module]
+pub mod oauth {
+    // Returns an access token for the given config, which is requested from
+    // token_url with the refresh_token grant if a refresh_token is provided,
+    // or with the client_credentials grant otherwise. Tokens are kept in the
+    // store until they expire, refresh tokens issued by the server replace
+    // the one in the config until the config changes.
+    #[rhai_fn(return_raw)]
+    pub fn token(context: NativeCallContext, config: rhai::Map) -> Result<ImmutableString, Box<EvalAltResult>> {
+        let token_url = required(&config, "token_url")?;
+        let client_id = required(&config, "client_id")?;
+        let key = match optional(&config, "key")? {
+            Some(key) => key,
+            None => format!("oauth:{}@{}", client_id, token_url),
+        };
+
+        // the refresh token in the config is preferred once it changes, e.g.
+        // after authorizing again, over the one previously issued by the server
+        let configured = optional(&config, "refresh_token")?;
+        let stored = store::get_value(&context, &key)?
+            .and_then(|v| v.try_cast::<rhai::Map>())
+            .filter(|s| s.get("configured").and_then(|t| t.clone().into_string().ok()) == configured);
+        let stored_field = |name: &str| stored.as_ref().and_then(|s| s.get(name)).cloned();
+        let expires = stored_field("expires").and_then(|e| e.as_int().ok());
+        if let (Some(token), Some(expires)) = (stored_field("access_token"), expires) {
+            if unix_time() + EXPIRY_MARGIN < expires {
+                return token.into_immutable_string().map_err(|t| t.into());
+            }
+        }
+
+        let refresh_token = stored_field("refresh_token")
+            .and_then(|t| t.into_string().ok())
+            .or_else(|| configured.clone());
+        let mut form = rhai::Map::new();
+        form.insert("client_id".into(), client_id.into());
+        if let Some(secret) = optional(&config, "client_secret")? {
+            form.insert("client_secret".into(), secret.into());
+        }
+        if let Some(scope) = optional(&config, "scope")? {
+            form.insert("scope".into(), scope.into());
+        }
+        match &refresh_token {
+            Some(token) => {
+                form.insert("grant_type".into(), "refresh_token".into());
+                form.insert("refresh_token".into(), token.into());
+            }
+            None => {
+                form.insert("grant_type".into(), "client_credentials".into());
+            }
+        }
+
+        debug!("Requesting OAuth token from {:?}", token_url);
+        let mut opts = rhai::Map::new();
+        opts.insert("method".into(), "POST".into());
+        opts.insert("form".into(), form.into());
+        let mut response = fetch::fetch(&context, &token_url, opts)?
+            .try_cast::<rhai::Map>()
+            .ok_or("unexpected token response")?;
+
+        let token = response
+            .remove("access_token")
+            .and_then(|t| t.into_immutable_string().ok())
+            .ok_or("token response does not contain an access_token")?;
+        let lifetime = response
+            .get("expires_in")
+            .and_then(|e| e.as_int().ok())
+            .unwrap_or(DEFAULT_LIFETIME);
+        let refresh_token = response
+            .remove("refresh_token")
+            .and_then(|t| t.into_string().ok())
+            .or(refresh_token);
+
+        let mut entry = rhai::Map::new();
+        entry.insert("access_token".into(), token.clone().into());
+        entry.insert("expires".into(), Dynamic::from_int(unix_time() + lifetime));
+        if let Some(refresh_token) = refresh_token {
+            entry.insert("refresh_token".into(), refresh_token.into());
+        }
+        if let Some(configured) = configured {
+            entry.insert("configured".into(), configured.into());
+        }
+        store::set_value(&context, &key, entry.into(), None)?;
+        Ok(token)
+    }
+}
@@ -0,0 +1,77 @@
+use rhai::plugin::*;
+use rhai::{Dynamic, EvalAltResult};
+
+fn from_yaml(value: serde_yaml::Value) -> Result<Dynamic, Box<EvalAltResult>> {
+    use serde_yaml::Value;
+
+    Ok(match value {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(b) => b.into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Dynamic::from_int(i),
+            None => Dynamic::from_float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => s.into(),
+        Value::Sequence(items) => items
+            .into_iter()
+            .map(from_yaml)
+            .collect::<Result<rhai::Array, _>>()?
+            .into(),
+        Value::Mapping(mapping) => {
+            let mut map = rhai::Map::new();
+            for (key, value) in mapping {
+                // scalar keys are common in YAML, e.g. years or port numbers
+                let key = match key {
+                    Value::String(s) => s,
+                    Value::Number(n) => n.to_string(),
+                    Value::Bool(b) => b.to_string(),
+                    key => return Err(format!("unsupported YAML key: {:?}", key).into()),
+                };
+                map.insert(key.into(), from_yaml(value)?);
+            }
+            map.into()
+        }
+        // tags like !!binary or custom ones are ignored
+        Value::Tagged(tagged) => from_yaml(tagged.value)?,
+    })
+}
+
+fn from_toml_table(table: toml::Table) -> rhai::Map {
+    table
+        .into_iter()
+        .map(|(key, value)| (key.into(), from_toml(value)))
+        .collect()
+}
+
+fn from_toml(value: toml::Value) -> Dynamic {
+    use toml::Value;
+
+    match value {
+        Value::String(s) => s.into(),
+        Value::Integer(i) => Dynamic::from_int(i),
+        Value::Float(f) => Dynamic::from_float(f),
+        Value::Boolean(b) => b.into(),
+        // kept as written, e.g. 1979-05-27T07:32:00Z
+        Value::Datetime(dt) => dt.to_string().into(),
+        Value::Array(items) => items.into_iter().map(from_toml).collect::<rhai::Array>().into(),
+        Value::Table(table) => from_toml_table(table).into(),
+    }
+}
+
+#[export_module]
+pub mod globals {
+    // Parses a YAML document, whose root may also be a list or a scalar
+    #[rhai_fn(return_raw, global)]
+    pub fn parse_yaml(yaml: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let value = serde_yaml::from_str(yaml).map_err(|e| format!("invalid YAML: {}", e))?;
+        super::from_yaml(value)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn parse_toml(toml: &str) -> Result<rhai::Map, Box<EvalAltResult>> {
+        let table = toml
+            .parse::<toml::Table>()
+            .map_err(|e| format!("invalid TOML: {}", e))?;
+        Ok(super::from_toml_table(table))
+    }
+}
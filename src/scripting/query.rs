@@ -0,0 +1,116 @@
+use rhai::plugin::*;
+use rhai::{Dynamic, EvalAltResult};
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    Key(String),
+    // negative indices count from the end
+    Index(i64),
+    Wildcard,
+}
+
+// Parses a JSON pointer like /hourly/temperature_2m/0, or a subset of
+// JSONPath like $.hourly.temperature_2m[0], $['key'], or $.items[*].name
+fn parse(path: &str) -> Result<Vec<Step>, String> {
+    if let Some(pointer) = path.strip_prefix('/') {
+        return Ok(pointer
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .map(|token| match token.parse::<i64>() {
+                Ok(index) if index >= 0 => Step::Index(index),
+                _ => Step::Key(token),
+            })
+            .collect());
+    }
+
+    let invalid = || format!("invalid path {:?}", path);
+    let mut steps = Vec::new();
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    // the leading dot may be omitted, e.g. hourly.time
+    if !rest.is_empty() && !rest.starts_with(['.', '[']) {
+        let end = rest.find(['.', '[']).unwrap_or(rest.len());
+        steps.push(Step::Key(rest[..end].to_string()));
+        rest = &rest[end..];
+    }
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('.') {
+            let end = r.find(['.', '[']).unwrap_or(r.len());
+            steps.push(match &r[..end] {
+                "" => return Err(invalid()),
+                "*" => Step::Wildcard,
+                key => Step::Key(key.to_string()),
+            });
+            rest = &r[end..];
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = match r.chars().next() {
+                Some(quote @ ('\'' | '"')) => r[1..].find(quote).map(|i| i + 2).ok_or_else(invalid)?,
+                _ => r.find(']').ok_or_else(invalid)?,
+            };
+            let selector = &r[..end];
+            steps.push(match selector {
+                "*" => Step::Wildcard,
+                s if s.len() >= 2 && s.starts_with(['\'', '"']) => Step::Key(s[1..s.len() - 1].to_string()),
+                s => Step::Index(s.trim().parse().map_err(|_| invalid())?),
+            });
+            rest = r[end..].strip_prefix(']').ok_or_else(invalid)?;
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(steps)
+}
+
+fn step(value: &Dynamic, step: &Step) -> Vec<Dynamic> {
+    if let Some(map) = value.read_lock::<rhai::Map>() {
+        return match step {
+            Step::Key(key) => map.get(key.as_str()).cloned().into_iter().collect(),
+            // pointers do not distinguish numeric keys from indices
+            Step::Index(index) => map.get(index.to_string().as_str()).cloned().into_iter().collect(),
+            Step::Wildcard => map.values().cloned().collect(),
+        };
+    }
+    if let Some(array) = value.read_lock::<rhai::Array>() {
+        return match step {
+            Step::Index(index) => {
+                let index = if *index < 0 { array.len() as i64 + index } else { *index };
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|i| array.get(i))
+                    .cloned()
+                    .into_iter()
+                    .collect()
+            }
+            Step::Wildcard => array.to_vec(),
+            Step::Key(_) => vec![],
+        };
+    }
+    vec![]
+}
+
+fn query(value: Dynamic, path: &str, default: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+    let steps = parse(path)?;
+    let mut values = vec![value];
+    for s in &steps {
+        values = values.iter().flat_map(|v| step(v, s)).collect();
+    }
+    // wildcards select a list of values, which may be empty
+    if steps.contains(&Step::Wildcard) {
+        return Ok(values.into());
+    }
+    Ok(values.pop().filter(|v| !v.is_unit()).unwrap_or(default))
+}
+
+#[export_module]
+pub mod globals {
+    // Returns the value at the path, or () if it does not exist
+    #[rhai_fn(return_raw, global)]
+    pub fn jq(value: Dynamic, path: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::query(value, path, Dynamic::UNIT)
+    }
+
+    // Returns the value at the path, or the default if it does not exist
+    #[rhai_fn(name = "jq", return_raw, global)]
+    pub fn jq_or(value: Dynamic, path: &str, default: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::query(value, path, default)
+    }
+}
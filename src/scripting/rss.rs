@@ -0,0 +1,184 @@
+use rhai::plugin::*;
+use rhai::{Array, Dynamic, EvalAltResult, NativeCallContext};
+
+use super::datetime::from_unix_time;
+use super::fetch;
+use super::xml::Node;
+
+// Converts a civil time with the offset from UTC in seconds to Unix time
+fn unix_time(date: (i64, i64, i64), time: (i64, i64, i64), offset: i64) -> Option<i64> {
+    let utc = tz::UtcDateTime::new(
+        date.0.try_into().ok()?,
+        date.1.try_into().ok()?,
+        date.2.try_into().ok()?,
+        time.0.try_into().ok()?,
+        time.1.try_into().ok()?,
+        time.2.try_into().ok()?,
+        0,
+    )
+    .ok()?;
+    Some(utc.unix_time() - offset)
+}
+
+fn parse_time(s: &str) -> Option<(i64, i64, i64)> {
+    let mut parts = s.splitn(3, ':').map(|p| p.parse::<i64>());
+    let hour = parts.next()?.ok()?;
+    let minute = parts.next()?.ok()?;
+    let second = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((hour, minute, second))
+}
+
+// Parses dates of RSS feeds, e.g. "Tue, 10 Jun 2003 04:00:00 GMT"
+fn parse_rfc2822(s: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    // the day of the week is optional and redundant
+    let s = s.split_once(',').map_or(s, |(_, rest)| rest);
+    let mut parts = s.split_whitespace();
+    let day = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?.get(..3)?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year = match parts.next()?.parse::<i64>().ok()? {
+        year @ 0..=49 => year + 2000,
+        year @ 50..=999 => year + 1900,
+        year => year,
+    };
+    let time = parse_time(parts.next()?)?;
+    let offset = match parts.next().unwrap_or("GMT") {
+        zone if zone.starts_with(['+', '-']) && zone.len() == 5 => {
+            let value = zone[1..].parse::<i64>().ok()?;
+            let offset = (value / 100) * 3600 + (value % 100) * 60;
+            if zone.starts_with('-') {
+                -offset
+            } else {
+                offset
+            }
+        }
+        "EDT" => -4 * 3600,
+        "EST" | "CDT" => -5 * 3600,
+        "CST" | "MDT" => -6 * 3600,
+        "MST" | "PDT" => -7 * 3600,
+        "PST" => -8 * 3600,
+        // GMT, UT, Z, and military zones, which are ambiguous in practice
+        _ => 0,
+    };
+    unix_time((year, month, day), time, offset)
+}
+
+// Parses dates of Atom feeds, e.g. "2003-12-13T18:30:02Z"
+fn parse_rfc3339(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, rest) = (s.get(..10)?, &s[10..]);
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>());
+    let date = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+    let rest = rest.trim_start_matches(['T', 't', ' ']);
+    if rest.is_empty() {
+        return unix_time(date, (0, 0, 0), 0);
+    }
+
+    let zone_start = rest.rfind(['Z', 'z', '+', '-']).unwrap_or(rest.len());
+    let (time, zone) = rest.split_at(zone_start);
+    // fractions of seconds are irrelevant for feeds
+    let time = parse_time(time.split('.').next()?)?;
+    let offset = match zone.split_once(':') {
+        Some((hours, minutes)) => {
+            let hours = hours.parse::<i64>().ok()?;
+            let minutes = minutes.parse::<i64>().ok()?;
+            hours * 3600 + hours.signum() * minutes * 60
+        }
+        None => 0,
+    };
+    unix_time(date, time, offset)
+}
+
+fn parse_date(s: &str) -> Option<i64> {
+    parse_rfc3339(s).or_else(|| parse_rfc2822(s))
+}
+
+fn text(node: &Node, path: &str) -> Option<String> {
+    node.find(path)
+        .map(|n| n.text().trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+// Atom entries may link to several representations, the alternate one is
+// the entry itself
+fn atom_link(entry: &Node) -> Option<String> {
+    entry
+        .select("link")
+        .into_iter()
+        .find(|link| matches!(link.attr("rel"), None | Some("alternate")))
+        .and_then(|link| link.attr("href").map(str::to_string))
+}
+
+fn entry(title: Option<String>, link: Option<String>, date: Option<String>) -> Dynamic {
+    let optional = |s: Option<String>| s.map(Dynamic::from).unwrap_or(Dynamic::UNIT);
+    let date = date
+        .as_deref()
+        .and_then(parse_date)
+        .and_then(from_unix_time)
+        .map(Dynamic::from)
+        .unwrap_or(Dynamic::UNIT);
+    let mut map = rhai::Map::new();
+    map.insert("title".into(), optional(title));
+    map.insert("link".into(), optional(link));
+    map.insert("date".into(), date);
+    map.into()
+}
+
+// Returns the entries of an RSS 2.0, RSS 1.0, or Atom feed in document order
+fn entries(feed: &Node) -> Result<Vec<Dynamic>, Box<EvalAltResult>> {
+    let entries = match feed.name() {
+        "rss" => feed
+            .select("channel/item")
+            .iter()
+            .map(|item| {
+                let date = text(item, "pubDate").or_else(|| text(item, "date"));
+                entry(text(item, "title"), text(item, "link"), date)
+            })
+            .collect(),
+        "RDF" => feed
+            .select("item")
+            .iter()
+            .map(|item| entry(text(item, "title"), text(item, "link"), text(item, "date")))
+            .collect(),
+        "feed" => feed
+            .select("entry")
+            .iter()
+            .map(|e| {
+                let date = text(e, "published").or_else(|| text(e, "updated"));
+                entry(text(e, "title"), atom_link(e), date)
+            })
+            .collect(),
+        name => return Err(format!("unsupported feed format: {}", name).into()),
+    };
+    Ok(entries)
+}
+
+#[export_module]
+pub mod rss {
+    // Fetches the feed and returns its entries as maps with title, link, and
+    // date, which are () if missing
+    #[rhai_fn(return_raw)]
+    pub fn fetch(context: NativeCallContext, url: &str) -> Result<Array, Box<EvalAltResult>> {
+        fetch_max_items(context, url, i64::MAX)
+    }
+
+    #[rhai_fn(return_raw, name = "fetch")]
+    pub fn fetch_max_items(context: NativeCallContext, url: &str, max_items: i64) -> Result<Array, Box<EvalAltResult>> {
+        let mut opts = rhai::Map::new();
+        opts.insert("response_type".into(), "string".into());
+        let xml = fetch::fetch(&context, url, opts)?.into_string()?;
+        let mut items = parse(&xml)?;
+        items.truncate(max_items.try_into().unwrap_or(0));
+        Ok(items)
+    }
+
+    // Returns the entries of a feed which has already been fetched
+    #[rhai_fn(return_raw)]
+    pub fn parse(xml: &str) -> Result<Array, Box<EvalAltResult>> {
+        entries(&Node::parse(xml)?)
+    }
+}
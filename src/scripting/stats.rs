@@ -0,0 +1,107 @@
+use rhai::plugin::*;
+use rhai::{Array, Dynamic, EvalAltResult, FLOAT, INT};
+
+// Extracts the numbers from the array, missing values given as () are skipped
+fn numbers(values: &Array) -> Result<Vec<FLOAT>, Box<EvalAltResult>> {
+    values
+        .iter()
+        .filter(|v| !v.is_unit())
+        .map(|v| match v.as_int() {
+            Ok(i) => Ok(i as FLOAT),
+            Err(_) => v
+                .as_float()
+                .map_err(|t| format!("values must be numbers, not {}", t).into()),
+        })
+        .collect()
+}
+
+fn sorted(values: &Array) -> Result<Vec<FLOAT>, Box<EvalAltResult>> {
+    let mut numbers = numbers(values)?;
+    numbers.sort_by(FLOAT::total_cmp);
+    Ok(numbers)
+}
+
+// Returns the element with the smallest or largest value, keeping its type
+fn extreme(values: Array, largest: bool) -> Result<Dynamic, Box<EvalAltResult>> {
+    let numbers = numbers(&values)?;
+    let present = values.into_iter().filter(|v| !v.is_unit());
+    let better = |a: FLOAT, b: FLOAT| if largest { b > a } else { b < a };
+    let extreme = numbers
+        .into_iter()
+        .zip(present)
+        .reduce(|a, b| if better(a.0, b.0) { b } else { a });
+    Ok(extreme.map(|(_, v)| v).unwrap_or(Dynamic::UNIT))
+}
+
+// Interpolates linearly between the closest ranks, p is between 0 and 100
+fn percentile(values: &Array, p: FLOAT) -> Result<Dynamic, Box<EvalAltResult>> {
+    if !(0. ..=100.).contains(&p) {
+        return Err(format!("percentile must be between 0 and 100, not {}", p).into());
+    }
+    let sorted = sorted(values)?;
+    if sorted.is_empty() {
+        return Ok(Dynamic::UNIT);
+    }
+    let rank = p / 100. * (sorted.len() - 1) as FLOAT;
+    let (lower, upper) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+    Ok(Dynamic::from_float(lower + (upper - lower) * rank.fract()))
+}
+
+// All functions ignore () in the array, e.g. missing hours in a forecast, and
+// return () if there are no values
+#[export_module]
+pub mod globals {
+    #[rhai_fn(name = "min", return_raw, global)]
+    pub fn min_of(values: Array) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::extreme(values, false)
+    }
+
+    #[rhai_fn(name = "max", return_raw, global)]
+    pub fn max_of(values: Array) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::extreme(values, true)
+    }
+
+    // Integers are summed as integers, the sum of no values is 0
+    #[rhai_fn(return_raw, global)]
+    pub fn sum(values: Array) -> Result<Dynamic, Box<EvalAltResult>> {
+        let ints = values
+            .iter()
+            .filter(|v| !v.is_unit())
+            .map(|v| v.as_int().ok())
+            .collect::<Option<Vec<INT>>>();
+        match ints {
+            Some(ints) => ints
+                .into_iter()
+                .try_fold(0 as INT, INT::checked_add)
+                .map(Dynamic::from_int)
+                .ok_or_else(|| "sum overflows".into()),
+            None => Ok(Dynamic::from_float(super::numbers(&values)?.into_iter().sum())),
+        }
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn avg(values: Array) -> Result<Dynamic, Box<EvalAltResult>> {
+        let numbers = super::numbers(&values)?;
+        match numbers.is_empty() {
+            true => Ok(Dynamic::UNIT),
+            false => Ok(Dynamic::from_float(
+                numbers.iter().sum::<FLOAT>() / numbers.len() as FLOAT,
+            )),
+        }
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn median(values: Array) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::percentile(&values, 50.)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn percentile(values: Array, p: FLOAT) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::percentile(&values, p)
+    }
+
+    #[rhai_fn(name = "percentile", return_raw, global)]
+    pub fn percentile_int(values: Array, p: INT) -> Result<Dynamic, Box<EvalAltResult>> {
+        super::percentile(&values, p as FLOAT)
+    }
+}
@@ -0,0 +1,169 @@
+use std::cell::RefCell;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use rhai::plugin::*;
+use rhai::{Dynamic, EvalAltResult, NativeCallContext};
+
+use super::datetime::TimeDelta;
+use super::json;
+
+thread_local! {
+    // Directory for data kept across runs, e.g. when waking up from suspend
+    static STATE_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+pub fn set_state_dir(dir: PathBuf) {
+    STATE_DIR.with(|state_dir| *state_dir.borrow_mut() = Some(dir));
+}
+
+pub fn state_dir() -> Result<PathBuf, Box<EvalAltResult>> {
+    STATE_DIR
+        .with(|state_dir| state_dir.borrow().clone())
+        .ok_or_else(|| "no state directory configured, see --state-dir".into())
+}
+
+const FILE: &str = "store.json";
+
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Entries are stored as {"value": ..., "expires": SECONDS} with the expiry in
+// seconds since the Unix epoch, or null if the entry does not expire
+fn load(context: &NativeCallContext) -> Result<rhai::Map, Box<EvalAltResult>> {
+    let path = state_dir()?.join(FILE);
+    match fs::read_to_string(&path) {
+        Ok(json) => context.engine().parse_json(json, true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(rhai::Map::new()),
+        Err(err) => Err(format!("failed to read {:?}: {}", path, err).into()),
+    }
+}
+
+fn save(mut entries: rhai::Map) -> Result<(), Box<EvalAltResult>> {
+    let now = unix_time();
+    entries.retain(|_, entry| live(entry, now).is_some());
+
+    let json = json::to_json(&Dynamic::from_map(entries))?;
+    write_file(&state_dir()?.join(FILE), json.as_bytes())
+}
+
+// Replaces the file atomically, so that a crash cannot leave it truncated.
+// State files may contain tokens and cookies, so only the owner can read them.
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<(), Box<EvalAltResult>> {
+    let write = || {
+        if let Some(dir) = path.parent() {
+            DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+
+        // a new file with a unique name, as other processes may write the same path
+        let (tmp, mut file) = loop {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".{}.tmp", rand::random::<u32>()));
+            let tmp = path.with_file_name(name);
+            match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp) {
+                Ok(file) => break (tmp, file),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        };
+
+        let res = file.write_all(contents).and_then(|()| fs::rename(&tmp, path));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res
+    };
+    write().map_err(|err| format!("failed to write {:?}: {}", path, err).into())
+}
+
+// Returns the value of the entry, unless it has expired
+fn live(entry: &Dynamic, now: i64) -> Option<Dynamic> {
+    let entry = entry.read_lock::<rhai::Map>()?;
+    match entry.get("expires").and_then(|e| e.as_int().ok()) {
+        Some(expires) if expires <= now => None,
+        _ => entry.get("value").cloned(),
+    }
+}
+
+pub fn get_value(context: &NativeCallContext, key: &str) -> Result<Option<Dynamic>, Box<EvalAltResult>> {
+    let entries = load(context)?;
+    Ok(entries.get(key).and_then(|e| live(e, unix_time())))
+}
+
+// Stores the value until the expiry in seconds since the Unix epoch, if any
+pub fn set_value(
+    context: &NativeCallContext,
+    key: &str,
+    value: Dynamic,
+    expires: Option<i64>,
+) -> Result<(), Box<EvalAltResult>> {
+    // fail early instead of losing the whole store when saving
+    json::to_json(&value)?;
+
+    let mut entries = load(context)?;
+    let mut entry = rhai::Map::new();
+    entry.insert("value".into(), value);
+    entry.insert(
+        "expires".into(),
+        expires.map(Dynamic::from_int).unwrap_or(Dynamic::UNIT),
+    );
+    entries.insert(key.into(), Dynamic::from_map(entry));
+    debug!("Storing {:?}", key);
+    save(entries)
+}
+
+#[export_module]
+pub mod store {
+    // Returns the stored value, or () if there is none or it has expired
+    #[rhai_fn(return_raw)]
+    pub fn get(context: NativeCallContext, key: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        get_or(context, key, Dynamic::UNIT)
+    }
+
+    #[rhai_fn(return_raw, name = "get")]
+    pub fn get_or(context: NativeCallContext, key: &str, default: Dynamic) -> Result<Dynamic, Box<EvalAltResult>> {
+        Ok(get_value(&context, key)?.unwrap_or(default))
+    }
+
+    #[rhai_fn(return_raw)]
+    pub fn set(context: NativeCallContext, key: &str, value: Dynamic) -> Result<(), Box<EvalAltResult>> {
+        set_value(&context, key, value, None)
+    }
+
+    // Stores the value until the time-to-live has elapsed
+    #[rhai_fn(return_raw, name = "set")]
+    pub fn set_with_ttl(
+        context: NativeCallContext,
+        key: &str,
+        value: Dynamic,
+        ttl: TimeDelta,
+    ) -> Result<(), Box<EvalAltResult>> {
+        const NANOS_PER_SEC: i128 = 1_000_000_000;
+        if ttl.as_nanoseconds() <= 0 {
+            return Err("time-to-live must be positive".into());
+        }
+        let secs = (ttl.as_nanoseconds() + NANOS_PER_SEC - 1) / NANOS_PER_SEC;
+        let expires = i64::try_from(secs)
+            .ok()
+            .and_then(|secs| unix_time().checked_add(secs))
+            .ok_or("time-to-live out of range")?;
+        set_value(&context, key, value, Some(expires))
+    }
+
+    // Removes the entry and returns its value, or () if there was none
+    #[rhai_fn(return_raw)]
+    pub fn remove(context: NativeCallContext, key: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let mut entries = load(&context)?;
+        let value = entries.remove(key).and_then(|e| live(&e, unix_time()));
+        save(entries)?;
+        Ok(value.unwrap_or(Dynamic::UNIT))
+    }
+}
@@ -0,0 +1,199 @@
+use rhai::plugin::*;
+use rhai::{EvalAltResult, INT};
+
+const ELLIPSIS: &str = "…";
+
+fn truncate(s: &str, max: INT, ellipsis: &str) -> String {
+    let max = max.max(0) as usize;
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    // the ellipsis counts towards the maximum, unless it does not fit at all
+    let keep = max.saturating_sub(ellipsis.chars().count());
+    let mut truncated: String = s.chars().take(keep).collect();
+    truncated.truncate(truncated.trim_end().len());
+    truncated.push_str(ellipsis);
+    truncated
+}
+
+fn pad(s: &str, width: INT, fill: &str, left: bool) -> Result<String, Box<EvalAltResult>> {
+    let mut chars = fill.chars();
+    let fill = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => return Err(format!("padding must be a single character, not {:?}", fill).into()),
+    };
+    let padding = (width.max(0) as usize).saturating_sub(s.chars().count());
+    let padding = std::iter::repeat_n(fill, padding);
+    Ok(match left {
+        true => padding.chain(s.chars()).collect(),
+        false => s.chars().chain(padding).collect(),
+    })
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    // only the entities commonly found in feeds and API responses
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "bull" => '•',
+        "middot" => '·',
+        "deg" => '°',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "euro" => '€',
+        "pound" => '£',
+        "times" => '×',
+        "auml" => 'ä',
+        "ouml" => 'ö',
+        "uuml" => 'ü',
+        "Auml" => 'Ä',
+        "Ouml" => 'Ö',
+        "Uuml" => 'Ü',
+        "szlig" => 'ß',
+        "eacute" => 'é',
+        "egrave" => 'è',
+        "agrave" => 'à',
+        _ => return None,
+    })
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| end + 1);
+        match entity.and_then(|end| Some((end, decode_entity(&rest[1..end])?))) {
+            Some((end, c)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            // unknown entities and stray ampersands are kept as is
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn strip_tags(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('<') {
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let closing = rest[1..end].starts_with('/');
+        let tag = rest[1..end].trim_start_matches('/').to_ascii_lowercase();
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        rest = &rest[end + 1..];
+        match name {
+            // their content is not text
+            "script" | "style" if !closing => {
+                let close = format!("</{}", name);
+                let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                rest = &rest[end..];
+            }
+            "br" | "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => stripped.push('\n'),
+            _ => stripped.push(' '),
+        }
+    }
+    // an unterminated tag is dropped
+    if !rest.starts_with('<') {
+        stripped.push_str(rest);
+    }
+    stripped
+}
+
+#[export_module]
+pub mod globals {
+    // Shortens the string to at most max characters, ending with an ellipsis
+    #[rhai_fn(global)]
+    pub fn truncate_chars(s: &str, max: INT) -> String {
+        super::truncate(s, max, super::ELLIPSIS)
+    }
+
+    #[rhai_fn(name = "truncate_chars", global)]
+    pub fn truncate_chars_with(s: &str, max: INT, ellipsis: &str) -> String {
+        super::truncate(s, max, ellipsis)
+    }
+
+    // Pads the string with spaces to at least width characters
+    #[rhai_fn(return_raw, global)]
+    pub fn pad_left(s: &str, width: INT) -> Result<String, Box<EvalAltResult>> {
+        super::pad(s, width, " ", true)
+    }
+
+    #[rhai_fn(name = "pad_left", return_raw, global)]
+    pub fn pad_left_with(s: &str, width: INT, fill: &str) -> Result<String, Box<EvalAltResult>> {
+        super::pad(s, width, fill, true)
+    }
+
+    #[rhai_fn(return_raw, global)]
+    pub fn pad_right(s: &str, width: INT) -> Result<String, Box<EvalAltResult>> {
+        super::pad(s, width, " ", false)
+    }
+
+    #[rhai_fn(name = "pad_right", return_raw, global)]
+    pub fn pad_right_with(s: &str, width: INT, fill: &str) -> Result<String, Box<EvalAltResult>> {
+        super::pad(s, width, fill, false)
+    }
+
+    // Capitalizes the first letter of each word and lowercases the rest
+    #[rhai_fn(global)]
+    pub fn title_case(s: &str) -> String {
+        let mut word_start = true;
+        let mut result = String::with_capacity(s.len());
+        for c in s.chars() {
+            match word_start {
+                true => result.extend(c.to_uppercase()),
+                false => result.extend(c.to_lowercase()),
+            }
+            word_start = !c.is_alphanumeric() && c != '\'';
+        }
+        result
+    }
+
+    // Converts HTML to plain text, with line breaks for block elements and
+    // all other whitespace collapsed
+    #[rhai_fn(global)]
+    pub fn strip_html(html: &str) -> String {
+        // entities are decoded last, so that &lt; does not start a tag
+        let text = super::decode_entities(&super::strip_tags(html));
+        text.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::rc::Rc;
+
+use elementtree::Element;
+use rhai::plugin::*;
+use rhai::{Array, Dynamic, EvalAltResult};
+
+// Element of a parsed XML document. Nodes are shared, so that scripts can
+// pass them around without copying the subtree.
+#[derive(Clone)]
+pub struct Node(Rc<Inner>);
+
+struct Inner {
+    name: String,
+    namespace: Option<String>,
+    attrs: Vec<(String, String)>,
+    // text before the first child, and after the element in its parent
+    text: String,
+    tail: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    pub fn parse(xml: &str) -> Result<Self, Box<EvalAltResult>> {
+        let root = Element::from_reader(xml.as_bytes()).map_err(|e| format!("invalid XML: {}", e))?;
+        Ok(Node::new(&root))
+    }
+
+    fn new(element: &Element) -> Self {
+        Node(Rc::new(Inner {
+            name: element.tag().name().to_string(),
+            namespace: element.tag().ns().map(str::to_string),
+            attrs: element
+                .attrs()
+                .map(|(name, value)| (name.name().to_string(), value.to_string()))
+                .collect(),
+            text: element.text().to_string(),
+            tail: element.tail().to_string(),
+            children: element.children().map(Node::new).collect(),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.0
+            .attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Concatenated text of the element and all its descendants
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.text_content(&mut text);
+        text
+    }
+
+    fn text_content(&self, out: &mut String) {
+        out.push_str(&self.0.text);
+        for child in &self.0.children {
+            child.text_content(out);
+            out.push_str(&child.0.tail);
+        }
+    }
+
+    pub fn find(&self, path: &str) -> Option<Node> {
+        self.select(path).into_iter().next()
+    }
+
+    // Returns the descendants matching the path of element names separated by
+    // slashes, where * matches any element. Namespaces are ignored, as feeds
+    // mix them freely.
+    pub fn select(&self, path: &str) -> Vec<Node> {
+        let mut nodes = vec![self.clone()];
+        for step in path.split('/').filter(|s| !s.is_empty()) {
+            nodes = nodes
+                .iter()
+                .flat_map(|node| node.0.children.iter())
+                .filter(|child| step == "*" || child.0.name == step)
+                .cloned()
+                .collect();
+        }
+        nodes
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.0.name)
+    }
+}
+
+#[export_module]
+pub mod globals {
+    pub type XmlNode = super::Node;
+
+    // Parses an XML document and returns its root element
+    #[rhai_fn(return_raw, global)]
+    pub fn parse_xml(xml: &str) -> Result<XmlNode, Box<EvalAltResult>> {
+        XmlNode::parse(xml)
+    }
+
+    // Local name of the element, without namespace
+    #[rhai_fn(get = "tag", pure)]
+    pub fn tag(node: &mut XmlNode) -> String {
+        node.name().to_string()
+    }
+
+    #[rhai_fn(get = "namespace", pure)]
+    pub fn namespace(node: &mut XmlNode) -> Dynamic {
+        node.0.namespace.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+    }
+
+    #[rhai_fn(get = "text", pure)]
+    pub fn text(node: &mut XmlNode) -> String {
+        node.text()
+    }
+
+    #[rhai_fn(get = "attrs", pure)]
+    pub fn attrs(node: &mut XmlNode) -> rhai::Map {
+        node.0
+            .attrs
+            .iter()
+            .map(|(name, value)| (name.into(), value.clone().into()))
+            .collect()
+    }
+
+    #[rhai_fn(get = "children", pure)]
+    pub fn children(node: &mut XmlNode) -> Array {
+        node.0.children.iter().cloned().map(Dynamic::from).collect()
+    }
+
+    // Returns the value of the attribute, or () if it is not set
+    #[rhai_fn(pure, global)]
+    pub fn attr(node: &mut XmlNode, name: &str) -> Dynamic {
+        node.attr(name)
+            .map(|value| Dynamic::from(value.to_string()))
+            .unwrap_or(Dynamic::UNIT)
+    }
+
+    // Returns the first element matching the path, e.g. "channel/title", or
+    // () if there is none
+    #[rhai_fn(pure, global)]
+    pub fn find(node: &mut XmlNode, path: &str) -> Dynamic {
+        node.find(path).map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+    }
+
+    // Returns all elements matching the path, e.g. "channel/item"
+    #[rhai_fn(pure, global)]
+    pub fn find_all(node: &mut XmlNode, path: &str) -> Array {
+        node.select(path).into_iter().map(Dynamic::from).collect()
+    }
+
+    #[rhai_fn(pure, global)]
+    pub fn to_string(node: &mut XmlNode) -> String {
+        format!("{:?}", node)
+    }
+
+    #[rhai_fn(pure, global)]
+    pub fn to_debug(node: &mut XmlNode) -> String {
+        format!("{:?}", node)
+    }
+}
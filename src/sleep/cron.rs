@@ -0,0 +1,177 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("Expected 5 fields (minute, hour, day of month, month, day of week), found {0}")]
+    FieldCount(usize),
+    #[error("Invalid value {0:?} in {1} field")]
+    InvalidValue(String, &'static str),
+    #[error("Value {0} out of range {2}-{3} in {1} field")]
+    OutOfRange(u32, &'static str, u32, u32),
+}
+
+// A single field of a cron expression, stored as a bitmask of matching values
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Field {
+    mask: u64,
+    // false if the field is `*`, i.e. does not restrict the schedule
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(s: &str, name: &'static str, min: u32, max: u32) -> Result<Self, Error> {
+        let invalid = || Error::InvalidValue(s.to_string(), name);
+        let number = |v: &str| -> Result<u32, Error> {
+            let n = v.parse::<u32>().map_err(|_| invalid())?;
+            match (min..=max).contains(&n) {
+                true => Ok(n),
+                false => Err(Error::OutOfRange(n, name, min, max)),
+            }
+        };
+
+        let mut mask = 0;
+        for item in s.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+                None => (item, 1),
+            };
+            if step == 0 {
+                return Err(invalid());
+            }
+
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (number(start)?, number(end)?),
+                    // `N/S` is short for `N-max/S`
+                    None if item.contains('/') => (number(range)?, max),
+                    None => (number(range)?, number(range)?),
+                },
+            };
+            if start > end {
+                return Err(invalid());
+            }
+
+            for value in (start..=end).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+
+        Ok(Field {
+            mask,
+            restricted: s != "*",
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.mask & (1 << value) != 0
+    }
+}
+
+// A cron expression with the five standard fields. Schedules are evaluated in
+// the local timezone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minute: Field,
+    hour: Field,
+    day_of_month: Field,
+    month: Field,
+    day_of_week: Field,
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(Error::FieldCount(fields.len()));
+        };
+
+        let mut day_of_week = Field::parse(day_of_week, "day of week", 0, 7)?;
+        // both 0 and 7 refer to Sunday
+        if day_of_week.matches(7) {
+            day_of_week.mask |= 1;
+        }
+
+        Ok(Schedule {
+            minute: Field::parse(minute, "minute", 0, 59)?,
+            hour: Field::parse(hour, "hour", 0, 23)?,
+            day_of_month: Field::parse(day_of_month, "day of month", 1, 31)?,
+            month: Field::parse(month, "month", 1, 12)?,
+            day_of_week,
+        })
+    }
+}
+
+impl Schedule {
+    // Upper bound for the search of the next matching time, e.g. to account
+    // for schedules running only on February 29th
+    const MAX_SEARCH_SECS: i64 = 5 * 366 * 24 * 60 * 60;
+
+    fn matches_day(&self, dt: &tz::DateTime) -> bool {
+        if !self.month.matches(dt.month().into()) {
+            return false;
+        }
+
+        // like in cron, restricting both fields matches days matching either of them
+        let day_of_month = self.day_of_month.matches(dt.month_day().into());
+        let day_of_week = self.day_of_week.matches(dt.week_day().into());
+        match (self.day_of_month.restricted, self.day_of_week.restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    // Returns the shortest possible time between two matching times on the
+    // local wall clock
+    pub fn min_interval(&self) -> Duration {
+        let mut minutes = Vec::new();
+        for hour in (0..24).filter(|h| self.hour.matches(*h)) {
+            for minute in (0..60).filter(|m| self.minute.matches(*m)) {
+                minutes.push(hour * 60 + minute);
+            }
+        }
+
+        // the last match of one day is followed by the first one of the next
+        let wrap = minutes
+            .first()
+            .zip(minutes.last())
+            .map(|(first, last)| first + 24 * 60 - last);
+        let min = minutes
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .chain(wrap)
+            .min()
+            .unwrap_or(24 * 60);
+        Duration::from_secs(min as u64 * 60)
+    }
+
+    // Returns the first matching unix time strictly after the given one
+    pub fn next_after(&self, unix_time: i64) -> Option<i64> {
+        let tz = super::local_tz();
+
+        // start at the beginning of the next minute
+        let mut t = unix_time - unix_time.rem_euclid(60) + 60;
+        while t - unix_time < Self::MAX_SEARCH_SECS {
+            let dt = tz::DateTime::from_timespec(t, 0, tz).ok()?;
+            let (hour, minute, second) = (dt.hour() as i64, dt.minute() as i64, dt.second() as i64);
+
+            // skip to the start of the next day, hour, or minute respectively
+            if !self.matches_day(&dt) {
+                t += ((23 - hour) * 60 + (60 - minute)) * 60 - second;
+            } else if !self.hour.matches(hour as u32) {
+                t += (60 - minute) * 60 - second;
+            } else if !self.minute.matches(minute as u32) {
+                t += 60 - second;
+            } else {
+                return Some(t);
+            }
+        }
+
+        None
+    }
+}
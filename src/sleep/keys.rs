@@ -0,0 +1,89 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::evdev::KeyCode;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("Expected key binding in the form KEY=ACTION, KEY:long=ACTION, or KEY+KEY=ACTION")]
+    MalformedBinding,
+    #[error("Key combinations do not support long presses")]
+    LongPressChord,
+    #[error("Unknown key {0:?}")]
+    UnknownKey(String),
+    #[error("Action name may not be empty")]
+    EmptyAction,
+}
+
+// Action performed when a key is pressed while sleeping
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyAction {
+    Exit,
+    Refresh,
+    // Refreshes and passes the action name to the script
+    Named(String),
+}
+
+impl FromStr for KeyAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Err(Error::EmptyAction),
+            "exit" => Ok(KeyAction::Exit),
+            "refresh" => Ok(KeyAction::Refresh),
+            name => Ok(KeyAction::Named(name.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for KeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyAction::Exit => f.write_str("exit"),
+            KeyAction::Refresh => f.write_str("refresh"),
+            KeyAction::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+// Key bound to an action, e.g. KEY_LEFT=previous-page. Long press bindings
+// are triggered if the key is held down for at least a second, e.g.
+// KEY_POWER:long=exit. Bindings of multiple keys are triggered once all of
+// them are held down at the same time, e.g. KEY_HOME+KEY_POWER=exit
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBinding {
+    pub keys: Vec<KeyCode>,
+    pub long_press: bool,
+    pub action: KeyAction,
+}
+
+impl FromStr for KeyBinding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, action) = s.split_once('=').ok_or(Error::MalformedBinding)?;
+        let (keys, long_press) = match key.trim().split_once(':') {
+            Some((keys, "long")) => (keys, true),
+            Some(_) => return Err(Error::MalformedBinding),
+            None => (key.trim(), false),
+        };
+        let keys = keys
+            .split('+')
+            .map(|key| {
+                let key = key.trim();
+                key.parse::<KeyCode>().map_err(|_| Error::UnknownKey(key.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if long_press && keys.len() > 1 {
+            return Err(Error::LongPressChord);
+        }
+        Ok(KeyBinding {
+            keys,
+            long_press,
+            action: action.parse()?,
+        })
+    }
+}
@@ -0,0 +1,820 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+use nix::unistd;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::control::{Command, DbusService, HttpServer, SocketServer};
+use crate::evdev::{
+    self, KeyCode, KeyDevice, KeyDeviceBuilder, KeyEvent, KeyState, SwipeDirection, TouchDevice, TouchDeviceBuilder,
+    TouchEvent,
+};
+use crate::mqtt;
+use crate::scripting::local_tz;
+use crate::systemd;
+use crate::timer::{self, Timer};
+use crate::watch::{self, Watcher};
+
+pub use self::cron::Schedule;
+pub use self::keys::{KeyAction, KeyBinding};
+pub use self::range::{SleepRule, TimeRange};
+pub use self::touch::{Rect, SwipeBinding, TapBinding, TapTarget};
+
+mod cron;
+mod keys;
+mod range;
+mod touch;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("OS error")]
+    OsError(#[from] nix::Error),
+    #[error("Input device error")]
+    EvdevError(#[from] evdev::Error),
+    #[error("Failed to set up timer")]
+    TimerError(#[from] timer::Error),
+    #[error("Failed to suspend via /sys/power/state")]
+    SuspendError(#[from] io::Error),
+    #[error("Failed to watch files")]
+    Watch(#[from] watch::Error),
+    #[error("Failed to listen on UDP {0}")]
+    Listen(SocketAddr, #[source] io::Error),
+    #[error("Failed to receive UDP datagram")]
+    Receive(#[source] io::Error),
+    #[error("Schedule has no upcoming refresh")]
+    ScheduleExhausted,
+}
+
+// Determines when the next refresh is due
+#[derive(Debug, Clone)]
+pub enum Interval {
+    Fixed(Duration),
+    // Multiples of the duration on the local wall clock, e.g. every full minute
+    Aligned(Duration),
+    Schedule(Schedule),
+}
+
+impl Interval {
+    // Returns the time of the next refresh after now. Slots of a schedule may
+    // be arbitrarily close, so those less than `min` away are skipped.
+    pub fn next(&self, min: Duration) -> Result<SystemTime, Error> {
+        let now = SystemTime::now();
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        match self {
+            Interval::Fixed(duration) => Ok(now + *duration),
+            Interval::Aligned(duration) => {
+                let offset = tz::DateTime::now(local_tz())
+                    .map(|dt| dt.local_time_type().ut_offset() as i128 * 1_000_000_000)
+                    .unwrap_or(0);
+                let period = duration.as_nanos().max(1) as i128;
+                let local = since_epoch.as_nanos() as i128 + offset;
+                let next = (local.div_euclid(period) + 1) * period - offset;
+                Ok(UNIX_EPOCH + Duration::from_nanos(next as u64))
+            }
+            Interval::Schedule(schedule) => {
+                let next = schedule
+                    .next_after((since_epoch + min).as_secs() as i64)
+                    .ok_or(Error::ScheduleExhausted)?;
+                Ok(UNIX_EPOCH + Duration::from_secs(next as u64))
+            }
+        }
+    }
+}
+
+// Returns true if any mains or USB power supply is online
+fn external_power_online() -> bool {
+    let Ok(supplies) = fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+
+    supplies.filter_map(|entry| entry.ok()).any(|entry| {
+        let path = entry.path();
+        let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+        let online = fs::read_to_string(path.join("online")).unwrap_or_default();
+        matches!(kind.trim(), "Mains" | "USB") && online.trim() == "1"
+    })
+}
+
+#[derive(Clone, Debug)]
+pub enum WakeupReason {
+    IntervalTick,
+    CarouselTick,
+    KeyPressed(KeyCode, KeyAction),
+    Tapped(KeyAction),
+    Swiped(SwipeDirection, KeyAction),
+    ReloadRequested,
+    TerminationRequested(Signal),
+    FilesChanged,
+    DatagramReceived(SocketAddr),
+    CommandReceived(Command),
+    MessageReceived(mqtt::Message),
+}
+
+// Blocks SIGHUP in the calling thread and returns a file descriptor which
+// receives it instead. With `terminate`, SIGTERM and SIGINT are received as
+// well, so that the dashboard exits cleanly once it sleeps. Must be called
+// before any other threads are spawned
+pub fn reload_signal(terminate: bool) -> Result<SignalFd, Error> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGHUP);
+    if terminate {
+        mask.add(Signal::SIGTERM);
+        mask.add(Signal::SIGINT);
+    }
+    mask.thread_block()?;
+    Ok(SignalFd::with_flags(
+        &mask,
+        SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC,
+    )?)
+}
+
+pub struct Sleeper {
+    timer: Timer,
+    interval: Interval,
+    keys: RefCell<HashMap<RawFd, KeyDevice>>,
+    // device patterns and the keys to look for, used to rescan devices
+    key_patterns: Vec<(String, Vec<KeyCode>)>,
+    bindings: HashMap<KeyCode, KeyAction>,
+    long_press_bindings: HashMap<KeyCode, KeyAction>,
+    chord_bindings: Vec<(Vec<KeyCode>, KeyAction)>,
+    key_state: RefCell<KeyState>,
+    touch: RefCell<HashMap<RawFd, TouchDevice>>,
+    touch_patterns: Vec<String>,
+    // watches the device directories for added or removed devices
+    hotplug: Option<Watcher>,
+    tap_regions: Vec<(Rect, KeyAction)>,
+    swipe_bindings: HashMap<SwipeDirection, KeyAction>,
+    screen_size: (u32, u32),
+    suspend: bool,
+    suspend_grace: Duration,
+    suspend_on_power: bool,
+    quiet_hours: Option<TimeRange>,
+    rules: Vec<SleepRule>,
+    chunk: Option<Duration>,
+    watchdog: Option<Duration>,
+    reload_signal: Option<SignalFd>,
+    watcher: Option<Watcher>,
+    udp: Option<UdpSocket>,
+    http: Option<HttpServer>,
+    dbus: Option<RefCell<DbusService>>,
+    control: Option<SocketServer>,
+    mqtt: Option<RefCell<mqtt::Client>>,
+    jitter: Duration,
+    carousel: Option<Duration>,
+    next_page_at: Cell<Option<SystemTime>>,
+}
+
+impl Sleeper {
+    // Minimum duration a key has to be held down for a long press
+    const LONG_PRESS: Duration = Duration::from_secs(1);
+
+    // Remaining durations below this are slept without another chunked alarm
+    const MAX_RESIDUAL: Duration = Duration::from_secs(2);
+
+    pub fn new(interval: Interval, timer: Timer) -> Self {
+        Sleeper {
+            timer: timer,
+            interval: interval,
+            keys: RefCell::new(HashMap::new()),
+            key_patterns: Vec::new(),
+            bindings: HashMap::new(),
+            long_press_bindings: HashMap::new(),
+            chord_bindings: Vec::new(),
+            key_state: RefCell::new(KeyState::default()),
+            touch: RefCell::new(HashMap::new()),
+            touch_patterns: Vec::new(),
+            hotplug: None,
+            tap_regions: Vec::new(),
+            swipe_bindings: HashMap::new(),
+            screen_size: (0, 0),
+            suspend: false,
+            suspend_grace: Default::default(),
+            suspend_on_power: true,
+            quiet_hours: None,
+            rules: Vec::new(),
+            chunk: None,
+            watchdog: None,
+            reload_signal: None,
+            watcher: None,
+            udp: None,
+            http: None,
+            dbus: None,
+            control: None,
+            mqtt: None,
+            jitter: Duration::ZERO,
+            carousel: None,
+            next_page_at: Cell::new(None),
+        }
+    }
+
+    // Wakes up early if any of the bound keys is pressed on devices matching
+    // the pattern. Devices are picked up again if they are plugged in later
+    pub fn bind_keys(
+        &mut self,
+        pattern: &str,
+        bindings: impl IntoIterator<Item = KeyBinding>,
+    ) -> Result<&mut Self, Error> {
+        let bindings = Vec::from_iter(bindings);
+        let keys = bindings.iter().flat_map(|b| b.keys.iter().copied()).collect::<Vec<_>>();
+        let key_devices = KeyDeviceBuilder::with_keys(keys.iter().copied()).find(pattern)?;
+        self.keys.get_mut().extend(key_devices.map(|d| (d.as_raw_fd(), d)));
+        self.key_patterns.push((pattern.to_string(), keys));
+        self.watch_devices(pattern)?;
+
+        for binding in bindings {
+            match (&binding.keys[..], binding.long_press) {
+                ([key], true) => {
+                    self.long_press_bindings.insert(*key, binding.action);
+                }
+                ([key], false) => {
+                    self.bindings.insert(*key, binding.action);
+                }
+                (_, _) => self.chord_bindings.push((binding.keys, binding.action)),
+            }
+        }
+        Ok(self)
+    }
+
+    // Wakes up early if a touchscreen matching the pattern is tapped within a
+    // tap region or swiped in a bound direction
+    pub fn touch_devices(&mut self, pattern: &str) -> Result<&mut Self, Error> {
+        let touch_devices = TouchDeviceBuilder::find(pattern)?;
+        self.touch.get_mut().extend(touch_devices.map(|d| (d.as_raw_fd(), d)));
+        self.touch_patterns.push(pattern.to_string());
+        self.watch_devices(pattern)?;
+        Ok(self)
+    }
+
+    // Watches the directory of the device pattern for added or removed devices
+    fn watch_devices(&mut self, pattern: &str) -> Result<(), Error> {
+        let Some(dir) = Path::new(pattern).parent().filter(|dir| dir.is_dir()) else {
+            return Ok(());
+        };
+        let hotplug = match &mut self.hotplug {
+            Some(hotplug) => hotplug,
+            None => self.hotplug.insert(Watcher::new()?),
+        };
+        hotplug.dir(dir)?;
+        Ok(())
+    }
+
+    // Opens all devices matching the patterns again, e.g. because devices were
+    // plugged in or re-enumerated after a resume. Keeps the previous devices if
+    // this fails
+    fn rescan_devices(&self) {
+        let rescan = || -> Result<_, evdev::Error> {
+            let mut keys = HashMap::new();
+            for (pattern, filter) in &self.key_patterns {
+                match KeyDeviceBuilder::with_keys(filter.iter().copied()).find(pattern) {
+                    Ok(devices) => keys.extend(devices.map(|d| (d.as_raw_fd(), d))),
+                    Err(evdev::Error::NoInputDevicesFound) => debug!("No input devices matching {:?}", pattern),
+                    Err(err) => return Err(err),
+                }
+            }
+            let mut touch = HashMap::new();
+            for pattern in &self.touch_patterns {
+                match TouchDeviceBuilder::find(pattern) {
+                    Ok(devices) => touch.extend(devices.map(|d| (d.as_raw_fd(), d))),
+                    Err(evdev::Error::NoInputDevicesFound) => debug!("No touch devices matching {:?}", pattern),
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok((keys, touch))
+        };
+
+        debug!("Rescanning input devices");
+        match rescan() {
+            Ok((keys, touch)) => {
+                *self.keys.borrow_mut() = keys;
+                *self.touch.borrow_mut() = touch;
+                // keys held down on the previous devices will never be released
+                *self.key_state.borrow_mut() = KeyState::default();
+            }
+            Err(err) => warn!("Failed to rescan input devices: {}", err),
+        }
+    }
+
+    // Returns the action triggered by the pending events of the device, if any
+    fn read_input(&self, fd: RawFd) -> Result<Option<WakeupReason>, evdev::Error> {
+        if let Some(key) = self.keys.borrow().get(&fd) {
+            while let Some(event) = key.next_key_event()? {
+                if let Some((code, action)) = self.key_action(event) {
+                    return Ok(Some(WakeupReason::KeyPressed(code, action)));
+                }
+            }
+        }
+
+        if let Some(touch) = self.touch.borrow().get(&fd) {
+            while let Some(event) = touch.next_touch_event()? {
+                if let Some(reason) = self.touch_action(event) {
+                    return Ok(Some(reason));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Replaces the tap regions, given in pixels of a screen of the given size.
+    // Regions listed first take precedence if they overlap
+    pub fn tap_regions(
+        &mut self,
+        regions: impl IntoIterator<Item = (Rect, KeyAction)>,
+        screen_size: (u32, u32),
+    ) -> &mut Self {
+        self.tap_regions = regions.into_iter().collect();
+        self.screen_size = screen_size;
+        self
+    }
+
+    pub fn bind_swipes(&mut self, bindings: impl IntoIterator<Item = SwipeBinding>) -> &mut Self {
+        self.swipe_bindings
+            .extend(bindings.into_iter().map(|b| (b.direction, b.action)));
+        self
+    }
+
+    // Returns the action of the tap region containing a tap, or the action
+    // bound to the direction of a swipe, if any
+    fn touch_action(&self, event: TouchEvent) -> Option<WakeupReason> {
+        match event {
+            TouchEvent::Tap(x, y) => {
+                let (width, height) = self.screen_size;
+                let (x, y) = (x * width as f32, y * height as f32);
+                debug!("Screen tapped at {:.0},{:.0}", x, y);
+                self.tap_regions
+                    .iter()
+                    .find(|(rect, _)| rect.contains(x, y))
+                    .map(|(_, action)| WakeupReason::Tapped(action.clone()))
+            }
+            TouchEvent::Swipe(direction) => {
+                debug!("Screen swiped {}", direction);
+                let action = self.swipe_bindings.get(&direction)?;
+                Some(WakeupReason::Swiped(direction, action.clone()))
+            }
+        }
+    }
+
+    // Wakes up early with a reload request if the signal is received
+    pub fn reload_on(&mut self, signal: SignalFd) -> &mut Self {
+        self.reload_signal = Some(signal);
+        self
+    }
+
+    // Wakes up early if any of the watched files changed
+    pub fn watch(&mut self, watcher: Watcher) -> &mut Self {
+        self.watcher = Some(watcher);
+        self
+    }
+
+    // Wakes up early if any datagram is received on this address. Note that
+    // the device cannot receive anything while suspended to RAM
+    pub fn listen_udp(&mut self, addr: SocketAddr) -> Result<&mut Self, Error> {
+        let socket = UdpSocket::bind(addr).map_err(|err| Error::Listen(addr, err))?;
+        socket.set_nonblocking(true).map_err(|err| Error::Listen(addr, err))?;
+        debug!("Listening for wakeup datagrams on UDP {}", addr);
+        self.udp = Some(socket);
+        Ok(self)
+    }
+
+    // Answers HTTP requests while sleeping, waking up early for commands
+    pub fn serve_http(&mut self, server: HttpServer) -> &mut Self {
+        self.http = Some(server);
+        self
+    }
+
+    // Answers D-Bus method calls while sleeping, waking up early for commands
+    pub fn serve_dbus(&mut self, service: DbusService) -> &mut Self {
+        self.dbus = Some(RefCell::new(service));
+        self
+    }
+
+    // Answers commands on a control socket while sleeping, waking up early for commands
+    pub fn serve_control(&mut self, server: SocketServer) -> &mut Self {
+        self.control = Some(server);
+        self
+    }
+
+    // Wakes up early if a message is published on any subscribed topic. The
+    // client connects before sleeping, and again after losing the connection
+    pub fn subscribe(&mut self, client: mqtt::Client) -> &mut Self {
+        self.mqtt = Some(RefCell::new(client));
+        self
+    }
+
+    // Connects the MQTT client and the D-Bus service if they are disconnected.
+    // Failures are only logged, so that the dashboard keeps refreshing
+    fn connect_clients(&self) {
+        if let Some(mqtt) = &self.mqtt {
+            if let Err(err) = mqtt.borrow_mut().connect() {
+                warn!("{}", err);
+            }
+        }
+        if let Some(service) = &self.dbus {
+            if let Err(err) = service.borrow_mut().reconnect() {
+                warn!("Failed to reconnect to D-Bus: {}", err);
+            }
+        }
+    }
+
+    // Reads all pending datagrams and returns the sender of the last one
+    fn receive_datagrams(socket: &UdpSocket) -> Result<Option<SocketAddr>, io::Error> {
+        let mut buf = [0u8; 512];
+        let mut sender = None;
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((_, addr)) => sender = Some(addr),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(sender),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn suspend(&mut self, yes: bool) -> &mut Self {
+        self.suspend = yes;
+        self
+    }
+
+    pub fn suspend_grace(&mut self, period: Duration) -> &mut Self {
+        self.suspend_grace = period;
+        self
+    }
+
+    // Whether to suspend while the device is connected to AC or USB power
+    pub fn suspend_on_power(&mut self, yes: bool) -> &mut Self {
+        self.suspend_on_power = yes;
+        self
+    }
+
+    pub fn quiet_hours(&mut self, quiet_hours: TimeRange) -> &mut Self {
+        self.quiet_hours = Some(quiet_hours);
+        self
+    }
+
+    // Splits long sleeps into alarms of at most this duration, resynchronizing
+    // with the system clock after each of them to compensate for RTC drift
+    pub fn chunk(&mut self, max_duration: Duration) -> &mut Self {
+        self.chunk = Some(max_duration);
+        self
+    }
+
+    // Sends systemd watchdog keepalives with this interval while sleeping
+    pub fn watchdog(&mut self, interval: Duration) -> &mut Self {
+        self.watchdog = Some(interval);
+        self
+    }
+
+    // Delays each refresh by a random duration of up to this maximum
+    pub fn jitter(&mut self, max_jitter: Duration) -> &mut Self {
+        self.jitter = max_jitter;
+        self
+    }
+
+    // Wakes up to show the next page with this interval
+    pub fn carousel(&mut self, interval: Duration) -> &mut Self {
+        self.carousel = Some(interval);
+        self.restart_carousel();
+        self
+    }
+
+    // Shows the current page for a full interval, e.g. after switching pages
+    pub fn restart_carousel(&self) {
+        self.next_page_at
+            .set(self.carousel.map(|interval| SystemTime::now() + interval));
+    }
+
+    // Returns when the next page is due, which is deferred until after quiet hours
+    fn next_page(&self) -> Option<SystemTime> {
+        let next_page = self.next_page_at.get()?;
+        Some(match &self.quiet_hours {
+            Some(quiet_hours) => quiet_hours.defer(next_page),
+            None => next_page,
+        })
+    }
+
+    pub fn rules(&mut self, rules: impl IntoIterator<Item = SleepRule>) -> &mut Self {
+        self.rules.extend(rules);
+        self
+    }
+
+    // Returns the first rule whose time range contains the current time
+    pub fn active_rule(&self) -> Option<&SleepRule> {
+        let now = SystemTime::now();
+        self.rules.iter().find(|rule| rule.range.contains(now))
+    }
+
+    // Returns the action triggered by the key event, if any. Keys with a long
+    // press binding trigger their actions only once released, key combinations
+    // once the last of their keys is pressed
+    fn key_action(&self, event: KeyEvent) -> Option<(KeyCode, KeyAction)> {
+        let mut key_state = self.key_state.borrow_mut();
+        key_state.update(event);
+        if let KeyEvent::Pressed(code) = event {
+            let chord = self
+                .chord_bindings
+                .iter()
+                .find(|(keys, _)| keys.contains(&code) && key_state.all_held(keys));
+            if let Some((_, action)) = chord {
+                return Some((code, action.clone()));
+            }
+        }
+
+        let action = match event {
+            KeyEvent::Pressed(code) if self.long_press_bindings.contains_key(&code) => None,
+            KeyEvent::Pressed(code) => self.bindings.get(&code),
+            KeyEvent::Released(code, held) if held >= Self::LONG_PRESS => self.long_press_bindings.get(&code),
+            KeyEvent::Released(code, _) if self.long_press_bindings.contains_key(&code) => self.bindings.get(&code),
+            KeyEvent::Released(..) => None,
+        };
+        action.map(|action| (event.code(), action.clone()))
+    }
+
+    // Returns the interval for the current time, taking rules into account
+    fn interval(&self) -> Interval {
+        match (self.active_rule(), &self.interval) {
+            (Some(rule), Interval::Aligned(_)) => Interval::Aligned(rule.duration),
+            (Some(rule), _) => Interval::Fixed(rule.duration),
+            (None, interval) => interval.clone(),
+        }
+    }
+
+    fn set_suspend_timer(&self) -> Result<(bool, Option<TimerFd>), Error> {
+        if !self.suspend {
+            return Ok((false, None));
+        } else if self.suspend_grace.is_zero() {
+            return Ok((true, None));
+        }
+
+        debug!("Waiting {:?} before suspending to memory", self.suspend_grace);
+        let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK)?;
+        let expiration = Expiration::OneShot(TimeSpec::from_duration(self.suspend_grace));
+        timer.set(expiration, TimerSetTimeFlags::empty())?;
+
+        Ok((false, Some(timer)))
+    }
+
+    // Returns false if suspending was skipped
+    fn suspend_to_memory(&self) -> Result<bool, Error> {
+        if !self.suspend_on_power && external_power_online() {
+            debug!("Not suspending to memory while connected to external power");
+            return Ok(false);
+        }
+
+        debug!("Suspending to memory");
+
+        OpenOptions::new()
+            .write(true)
+            .open("/sys/power/state")?
+            .write_all(b"mem")?;
+
+        Ok(true)
+    }
+
+    // File descriptors to wait for while sleeping
+    fn poll_fds(&self, wakeup_fd: RawFd, suspend_timer: Option<&TimerFd>) -> Vec<PollFd> {
+        let mut fds = vec![wakeup_fd];
+        fds.extend(self.keys.borrow().keys());
+        fds.extend(self.touch.borrow().keys());
+        fds.extend(self.reload_signal.as_ref().map(|s| s.as_raw_fd()));
+        fds.extend(self.watcher.as_ref().map(|w| w.as_raw_fd()));
+        fds.extend(self.hotplug.as_ref().map(|w| w.as_raw_fd()));
+        fds.extend(self.udp.as_ref().map(|s| s.as_raw_fd()));
+        fds.extend(self.http.as_ref().map(|s| s.as_raw_fd()));
+        fds.extend(self.dbus.as_ref().and_then(|s| s.borrow().fd()));
+        fds.extend(self.control.as_ref().map(|s| s.as_raw_fd()));
+        fds.extend(self.mqtt.as_ref().and_then(|m| m.borrow().fd()));
+        fds.extend(suspend_timer.map(|t| t.as_raw_fd()));
+        fds.into_iter().map(|fd| PollFd::new(fd, PollFlags::POLLIN)).collect()
+    }
+
+    // Returns the time of the next alarm for the given deadline
+    fn alarm_time(&self, deadline: SystemTime) -> SystemTime {
+        match self.chunk {
+            Some(chunk) => deadline.min(SystemTime::now() + chunk),
+            None => deadline,
+        }
+    }
+
+    // Shortest duration accepted from scripts and schedules, which bypass the
+    // checks of the command line options. Suspending requires the same minimum
+    // as --sleep.
+    fn min_refresh(&self) -> Duration {
+        match self.suspend {
+            true => Duration::from_secs(30).max(self.suspend_grace + Duration::from_secs(1)),
+            false => Duration::from_secs(1),
+        }
+    }
+
+    // Returns when the next refresh is due, or after the given duration instead
+    fn deadline(&self, next_refresh: Option<Duration>) -> Result<SystemTime, Error> {
+        let mut deadline = match next_refresh {
+            Some(duration) => {
+                let min = self.min_refresh();
+                if duration < min {
+                    debug!("Extending next refresh in {:?} to {:?}", duration, min);
+                }
+                SystemTime::now() + duration.max(min)
+            }
+            None => self.interval().next(self.min_refresh())?,
+        };
+        if !self.jitter.is_zero() {
+            deadline += rand::thread_rng().gen_range(Duration::ZERO..=self.jitter);
+        }
+        Ok(match &self.quiet_hours {
+            Some(quiet_hours) => quiet_hours.defer(deadline),
+            None => deadline,
+        })
+    }
+
+    // Sets a wakeup alarm for the next refresh without waiting for it. The
+    // alarm remains set after returning, e.g. for an external suspend script
+    pub fn program_wakeup(&self, next_refresh: Option<Duration>) -> Result<SystemTime, Error> {
+        let deadline = self.deadline(next_refresh)?;
+        debug!(
+            "Setting wakeup alarm in {:?}",
+            deadline.duration_since(SystemTime::now()).unwrap_or_default()
+        );
+        self.timer.set_at(deadline)?.persist();
+        Ok(deadline)
+    }
+
+    // Waits until the next refresh is due, or for the given duration instead
+    pub fn wait(&self, next_refresh: Option<Duration>) -> Result<WakeupReason, Error> {
+        let deadline = self.deadline(next_refresh)?;
+        let (deadline, page_due) = match self.next_page() {
+            Some(next_page) if next_page < deadline => (next_page, true),
+            _ => (deadline, false),
+        };
+        let duration = deadline.duration_since(SystemTime::now()).unwrap_or_default();
+        debug!("Sleeping for {:?}", duration);
+        let mut wakeup_timer = self.timer.set_at(self.alarm_time(deadline))?;
+        let wakeup_fd = wakeup_timer.as_raw_fd();
+        let (mut suspend_now, suspend_timer) = self.set_suspend_timer()?;
+        self.connect_clients();
+        let mut pollfd = self.poll_fds(wakeup_fd, suspend_timer.as_ref());
+
+        // wake up regularly to keep the MQTT connection alive
+        let ping = self.mqtt.as_ref().map(|_| mqtt::Client::PING_INTERVAL);
+        let timeout = match self.watchdog.into_iter().chain(ping).min() {
+            Some(interval) => interval.as_millis().clamp(1, i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        let has_devices = !self.key_patterns.is_empty() || !self.touch_patterns.is_empty();
+        let mut rescan = false;
+        let mut reconnect = false;
+        loop {
+            if suspend_now {
+                // devices may have been re-enumerated while suspended
+                rescan |= self.suspend_to_memory()? && has_devices;
+                suspend_now = false;
+            }
+            if rescan {
+                self.rescan_devices();
+            }
+            if let Some(mqtt) = &self.mqtt {
+                if let Err(err) = mqtt.borrow_mut().keep_alive() {
+                    warn!("Lost connection to MQTT broker: {}", err);
+                    reconnect = true;
+                }
+            }
+            if reconnect {
+                self.connect_clients();
+            }
+            if rescan || reconnect {
+                pollfd = self.poll_fds(wakeup_fd, suspend_timer.as_ref());
+                (rescan, reconnect) = (false, false);
+            }
+
+            if poll(&mut pollfd, timeout)? == 0 {
+                systemd::notify("WATCHDOG=1");
+                continue;
+            }
+
+            for event in &pollfd {
+                let fd = event.as_raw_fd();
+                if !event.any().unwrap_or(false) {
+                    continue;
+                }
+
+                match self.read_input(fd) {
+                    Ok(Some(reason)) => return Ok(reason),
+                    Ok(None) => {}
+                    Err(err) if err.is_disconnected() => {
+                        debug!("Input device disconnected");
+                        rescan = true;
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+
+                if let Some(hotplug) = self.hotplug.as_ref().filter(|w| w.as_raw_fd() == fd) {
+                    rescan |= hotplug.changed()?;
+                }
+
+                if self.reload_signal.as_ref().is_some_and(|s| s.as_raw_fd() == fd) {
+                    // consume the pending signal, SignalFd::read_signal needs a mutable reference
+                    let mut siginfo = [0u8; mem::size_of::<libc::signalfd_siginfo>()];
+                    unistd::read(fd, &mut siginfo)?;
+                    // the signal number is the first field of the siginfo
+                    let signo = u32::from_ne_bytes([siginfo[0], siginfo[1], siginfo[2], siginfo[3]]);
+                    return Ok(match Signal::try_from(signo as i32) {
+                        Ok(signal @ (Signal::SIGTERM | Signal::SIGINT)) => WakeupReason::TerminationRequested(signal),
+                        _ => WakeupReason::ReloadRequested,
+                    });
+                }
+
+                if let Some(watcher) = self.watcher.as_ref().filter(|w| w.as_raw_fd() == fd) {
+                    if watcher.changed()? {
+                        return Ok(WakeupReason::FilesChanged);
+                    }
+                }
+
+                if let Some(socket) = self.udp.as_ref().filter(|s| s.as_raw_fd() == fd) {
+                    if let Some(sender) = Self::receive_datagrams(socket).map_err(Error::Receive)? {
+                        return Ok(WakeupReason::DatagramReceived(sender));
+                    }
+                }
+
+                if let Some(server) = self.http.as_ref().filter(|s| s.as_raw_fd() == fd) {
+                    if let Some(command) = server.handle() {
+                        return Ok(WakeupReason::CommandReceived(command));
+                    }
+                }
+
+                if let Some(service) = self.dbus.as_ref().filter(|s| s.borrow().fd() == Some(fd)) {
+                    match service.borrow_mut().handle() {
+                        Ok(Some(command)) => return Ok(WakeupReason::CommandReceived(command)),
+                        Ok(None) => {}
+                        Err(err) => {
+                            warn!("Lost connection to D-Bus: {}", err);
+                            reconnect = true;
+                        }
+                    }
+                }
+
+                if let Some(server) = self.control.as_ref().filter(|s| s.as_raw_fd() == fd) {
+                    if let Some(command) = server.handle() {
+                        return Ok(WakeupReason::CommandReceived(command));
+                    }
+                }
+
+                if let Some(mqtt) = self.mqtt.as_ref().filter(|m| m.borrow().fd() == Some(fd)) {
+                    match mqtt.borrow_mut().receive() {
+                        Ok(Some(message)) => return Ok(WakeupReason::MessageReceived(message)),
+                        Ok(None) => {}
+                        Err(err) => {
+                            warn!("Lost connection to MQTT broker: {}", err);
+                            reconnect = true;
+                        }
+                    }
+                }
+
+                if fd == wakeup_fd {
+                    wakeup_timer.wait()?;
+
+                    let remaining = deadline.duration_since(SystemTime::now()).unwrap_or_default();
+                    if self.chunk.is_some() && remaining > Self::MAX_RESIDUAL {
+                        debug!("Sleeping for remaining {:?}", remaining);
+                        // the previous alarm must be disabled before setting a new one
+                        drop(wakeup_timer);
+                        wakeup_timer = self.timer.set_at(self.alarm_time(deadline))?;
+                        suspend_now = self.suspend;
+                        continue;
+                    }
+
+                    // the RTC has a resolution of seconds and may thus fire
+                    // slightly early, so wait for the remaining time
+                    if let Ok(remaining) = deadline.duration_since(SystemTime::now()) {
+                        thread::sleep(remaining);
+                    }
+                    if page_due {
+                        self.restart_carousel();
+                        return Ok(WakeupReason::CarouselTick);
+                    }
+                    return Ok(WakeupReason::IntervalTick);
+                }
+
+                if let Some(suspend_timer) = &suspend_timer {
+                    if fd == suspend_timer.as_raw_fd() {
+                        suspend_timer.wait()?;
+                        suspend_now = true;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("Expected time range in the form HH:MM-HH:MM")]
+    MalformedRange,
+    #[error("Invalid time of day {0:?}")]
+    InvalidTime(String),
+    #[error("Expected rule in the form HH:MM-HH:MM=DURATION")]
+    MalformedRule,
+    #[error("Sleep rule duration must be positive")]
+    ZeroDuration,
+}
+
+// Daily time window in the local timezone. The window may span midnight,
+// e.g. from 23:00 to 06:00.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeRange {
+    // minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+fn parse_time(s: &str) -> Result<u32, Error> {
+    let invalid = || Error::InvalidTime(s.to_string());
+    let (hour, minute) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hour = hour.parse::<u32>().map_err(|_| invalid())?;
+    let minute = minute.parse::<u32>().map_err(|_| invalid())?;
+    match hour < 24 && minute < 60 {
+        true => Ok(hour * 60 + minute),
+        false => Err(invalid()),
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').ok_or(Error::MalformedRange)?;
+        Ok(TimeRange {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl TimeRange {
+    fn contains_minute(&self, minute_of_day: u32) -> bool {
+        match self.start <= self.end {
+            true => self.start <= minute_of_day && minute_of_day < self.end,
+            false => self.start <= minute_of_day || minute_of_day < self.end,
+        }
+    }
+
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let unix_time = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        match tz::DateTime::from_timespec(unix_time, 0, super::local_tz()) {
+            Ok(dt) => self.contains_minute(dt.hour() as u32 * 60 + dt.minute() as u32),
+            Err(_) => false,
+        }
+    }
+
+    // Moves the time to the end of the range if it falls within it
+    pub fn defer(&self, time: SystemTime) -> SystemTime {
+        let tz = super::local_tz();
+        let unix_time = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let Ok(dt) = tz::DateTime::from_timespec(unix_time, 0, tz) else {
+            return time;
+        };
+
+        let minute_of_day = dt.hour() as u32 * 60 + dt.minute() as u32;
+        if !self.contains_minute(minute_of_day) {
+            return time;
+        }
+
+        // the window ends on the next day if we are before midnight
+        let day = match minute_of_day >= self.end {
+            true => tz::DateTime::from_timespec(unix_time + 24 * 60 * 60, 0, tz).unwrap_or(dt),
+            false => dt,
+        };
+        let end = tz::DateTime::find(
+            day.year(),
+            day.month(),
+            day.month_day(),
+            (self.end / 60) as u8,
+            (self.end % 60) as u8,
+            0,
+            0,
+            tz,
+        )
+        .ok()
+        .and_then(|found| found.earliest());
+
+        match end {
+            Some(end) => UNIX_EPOCH + Duration::from_secs(end.unix_time().max(0) as u64),
+            None => time,
+        }
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+// Refresh interval used instead of the default one during a time range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SleepRule {
+    pub range: TimeRange,
+    pub duration: Duration,
+}
+
+impl FromStr for SleepRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, duration) = s.split_once('=').ok_or(Error::MalformedRule)?;
+        let duration = humantime::parse_duration(duration.trim()).map_err(|_| Error::MalformedRule)?;
+        if duration.is_zero() {
+            return Err(Error::ZeroDuration);
+        }
+        Ok(SleepRule {
+            range: range.parse()?,
+            duration,
+        })
+    }
+}
+
+impl fmt::Display for SleepRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.range, humantime::format_duration(self.duration))
+    }
+}
@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use super::keys::{self, KeyAction};
+use crate::evdev::SwipeDirection;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("Expected tap binding in the form X,Y,WIDTH,HEIGHT=ACTION or #ELEMENT=ACTION")]
+    MalformedBinding,
+    #[error("Expected tap region in the form X,Y,WIDTH,HEIGHT")]
+    MalformedRegion,
+    #[error("Element id may not be empty")]
+    EmptyElement,
+    #[error("Expected swipe binding in the form DIRECTION=ACTION")]
+    MalformedSwipe,
+    #[error("{0}")]
+    UnknownDirection(String),
+    #[error(transparent)]
+    Action(#[from] keys::Error),
+}
+
+// Rectangle in screen pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+impl FromStr for Rect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::MalformedRegion)?;
+        match values[..] {
+            [x, y, width, height] if width > 0. && height > 0. => Ok(Rect { x, y, width, height }),
+            _ => Err(Error::MalformedRegion),
+        }
+    }
+}
+
+// Area of the screen which triggers an action when tapped
+#[derive(Debug, Clone, PartialEq)]
+pub enum TapTarget {
+    Rect(Rect),
+    // Bounding box of the element with this id in the rendered document
+    Element(String),
+}
+
+// Tap target bound to an action, e.g. 0,0,300,800=previous-page or
+// #refresh-button=refresh
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapBinding {
+    pub target: TapTarget,
+    pub action: KeyAction,
+}
+
+impl FromStr for TapBinding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, action) = s.split_once('=').ok_or(Error::MalformedBinding)?;
+        let target = match target.trim().strip_prefix('#') {
+            Some("") => return Err(Error::EmptyElement),
+            Some(id) => TapTarget::Element(id.to_string()),
+            None => TapTarget::Rect(target.parse()?),
+        };
+        Ok(TapBinding {
+            target,
+            action: action.parse()?,
+        })
+    }
+}
+
+// Swipe direction bound to an action, e.g. left=next-page
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwipeBinding {
+    pub direction: SwipeDirection,
+    pub action: KeyAction,
+}
+
+impl FromStr for SwipeBinding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (direction, action) = s.split_once('=').ok_or(Error::MalformedSwipe)?;
+        Ok(SwipeBinding {
+            direction: direction.trim().parse().map_err(Error::UnknownDirection)?,
+            action: action.parse()?,
+        })
+    }
+}
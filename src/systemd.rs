@@ -0,0 +1,47 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+// Sends a state update such as READY=1 to the service manager. Does nothing
+// if we are not running as a systemd service with Type=notify
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    debug!("Notifying systemd: {}", state);
+    if let Err(err) = send(&path, state) {
+        warn!("Failed to notify systemd: {}", err);
+    }
+}
+
+fn send(path: &OsStr, state: &str) -> Result<(), io::Error> {
+    // socket paths starting with @ refer to the abstract namespace
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+// Returns the interval in which WATCHDOG=1 keepalives have to be sent, if the
+// service has a watchdog configured via WatchdogSec
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    // send keepalives at twice the rate of the timeout, as recommended by systemd
+    Some(Duration::from_micros(usec / 2))
+}
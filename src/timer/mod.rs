@@ -1,8 +1,12 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::mem;
 use std::os::fd::{AsRawFd, RawFd};
-use std::path::Path;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-use log::warn;
+use log::{debug, warn};
 use nix::sys::time::TimeSpec;
 use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
 use thiserror::Error;
@@ -18,6 +22,34 @@ pub enum Error {
     #[error("Timer error")]
     Fd(#[from] nix::Error),
 }
+// RTC device used to wake up from suspend
+#[derive(Debug, Clone, PartialEq)]
+pub enum RtcDevice {
+    // Use the first device which supports wakeup alarms
+    Auto,
+    Path(PathBuf),
+}
+
+impl FromStr for RtcDevice {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(RtcDevice::Auto),
+            path => Ok(RtcDevice::Path(PathBuf::from(path))),
+        }
+    }
+}
+
+impl fmt::Display for RtcDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtcDevice::Auto => f.write_str("auto"),
+            RtcDevice::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
 enum TimerImpl {
     Rtc(RtcClock),
     Fd(TimerFd),
@@ -37,12 +69,33 @@ impl Timer {
         })
     }
 
-    pub fn realtime_alarm(path: impl AsRef<Path>) -> Result<Self, Error> {
-        // Linux 3.11+ actually supports CLOCK_BOOTTIME_ALARM on TimerFd.
-        // Unfortunately, the Kindle 4 ships with Linux 2.6.31, so rely on
-        // manually programming an RTC device
+    pub fn realtime_alarm(device: &RtcDevice) -> Result<Self, Error> {
+        // Linux 3.11+ supports CLOCK_BOOTTIME_ALARM on TimerFd, which wakes
+        // the system from suspend. Unfortunately, the Kindle 4 ships with
+        // Linux 2.6.31, so fall back to manually programming an RTC device
+        let flags = TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC;
+        match TimerFd::new(ClockId::CLOCK_BOOTTIME_ALARM, flags) {
+            Ok(timerfd) => {
+                debug!("Using CLOCK_BOOTTIME_ALARM for wakeup alarms");
+                return Ok(Timer {
+                    timer: TimerImpl::Fd(timerfd),
+                });
+            }
+            Err(err) => debug!("CLOCK_BOOTTIME_ALARM not available, using RTC: {}", err),
+        }
+
+        Self::rtc(device)
+    }
+
+    // Uses the RTC device even if alarm timers are available. Unlike alarm
+    // timers, RTC alarms remain set after the process has exited
+    pub fn rtc(device: &RtcDevice) -> Result<Self, Error> {
+        let rtc_clock = match device {
+            RtcDevice::Auto => RtcClock::discover()?,
+            RtcDevice::Path(path) => RtcClock::new(path)?,
+        };
         Ok(Timer {
-            timer: TimerImpl::Rtc(RtcClock::new(path)?),
+            timer: TimerImpl::Rtc(rtc_clock),
         })
     }
 
@@ -65,6 +118,26 @@ impl Timer {
             }
         }
     }
+
+    // Sets an alarm for the given point in wall clock time. Times in the past
+    // fire immediately.
+    pub fn set_at(&self, time: SystemTime) -> Result<Alarm<'_>, Error> {
+        match &self.timer {
+            // both the monotonic and the boottime clock are unrelated to the
+            // wall clock, so the time has to be converted into a duration
+            TimerImpl::Fd(_) => {
+                let duration = time.duration_since(SystemTime::now()).unwrap_or_default();
+                // a zero duration would disarm the timer instead of firing immediately
+                self.set(duration.max(Duration::from_millis(1)))
+            }
+            TimerImpl::Rtc(rtc_clock) => {
+                let rtc_alarm = rtc_clock.set_alarm_at(time)?;
+                Ok(Alarm {
+                    alarm: AlarmImpl::Rtc(rtc_alarm),
+                })
+            }
+        }
+    }
 }
 
 enum AlarmImpl<'a> {
@@ -84,6 +157,11 @@ impl Alarm<'_> {
         }
     }
 
+    // Keeps the alarm set after it has been dropped
+    pub fn persist(self) {
+        mem::forget(self);
+    }
+
     pub fn wait(&self) -> Result<(), Error> {
         match &self.alarm {
             AlarmImpl::Fd(timerfd) => Ok(timerfd.wait()?),
@@ -4,7 +4,7 @@ use std::mem::{self, MaybeUninit};
 use std::os::fd::{AsRawFd, RawFd};
 use std::os::unix::prelude::OpenOptionsExt;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use log::debug;
 use nix::errno::Errno;
@@ -156,6 +156,29 @@ impl RtcClock {
         Ok(RtcClock { dev, tz })
     }
 
+    // Opens the first RTC device which supports wakeup alarms
+    pub fn discover() -> Result<Self, Error> {
+        // e.g. /dev/rtc0, /dev/rtc1, but not the /dev/rtc symlink
+        let mut devices = fs::read_dir("/dev")
+            .map_err(Error::RtcDeviceError)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let index = path.file_name()?.to_str()?.strip_prefix("rtc")?.parse::<u32>().ok()?;
+                Some((index, path))
+            })
+            .collect::<Vec<_>>();
+        devices.sort();
+
+        for (_, path) in devices {
+            match RtcClock::new(&path) {
+                Ok(rtc) => return Ok(rtc),
+                Err(err) => debug!("Skipping RTC {:?}: {}", path, err),
+            }
+        }
+
+        Err(Error::WakeupNotSupported)
+    }
+
     fn rtc_time(&self) -> Result<DateTime, Error> {
         let fd = self.dev.as_raw_fd();
         let rtc_time = unsafe {
@@ -178,10 +201,8 @@ impl RtcClock {
         .ok_or(Error::InvalidRTCTime)
     }
 
-    fn rtc_time_add_duration(&self, duration: Duration) -> Result<sys::rtc_time, Error> {
-        let duration: i64 = duration.as_secs().try_into().map_err(TzError::from)?;
-        let rtc_unixtime = self.rtc_time()?.unix_time() + duration;
-        let rtc_datetime = DateTime::from_timespec(rtc_unixtime, 0, self.tz).map_err(TzError::from)?;
+    fn rtc_time_at(&self, unix_time: i64) -> Result<sys::rtc_time, Error> {
+        let rtc_datetime = DateTime::from_timespec(unix_time, 0, self.tz).map_err(TzError::from)?;
 
         Ok(sys::rtc_time {
             tm_sec: rtc_datetime.second() as std::ffi::c_int,
@@ -197,7 +218,30 @@ impl RtcClock {
     }
 
     pub fn set_alarm<'a>(&'a self, duration: Duration) -> Result<RtcAlarm<'a>, Error> {
-        let wakeup_time = self.rtc_time_add_duration(duration)?;
+        // round up to avoid waking up before the duration has elapsed
+        let secs = duration.as_secs() + (duration.subsec_nanos() > 0) as u64;
+        let duration: i64 = secs.try_into().map_err(TzError::from)?;
+        // alarms at the current second might never fire
+        let wakeup_time = self.rtc_time_at(self.rtc_time()?.unix_time() + duration.max(1))?;
+        RtcAlarm::enable(&self.dev, wakeup_time)
+    }
+
+    // Sets the alarm to the given system time. The RTC is often not kept in
+    // sync with the system clock, so the time is shifted by their offset
+    pub fn set_alarm_at<'a>(&'a self, time: SystemTime) -> Result<RtcAlarm<'a>, Error> {
+        let rtc_unixtime = self.rtc_time()?.unix_time();
+        let system_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let target_time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        // round up to avoid waking up before the time has been reached
+        let secs = |d: Duration| -> Result<i64, Error> {
+            let secs = d.as_secs() + (d.subsec_nanos() > 0) as u64;
+            Ok(secs.try_into().map_err(TzError::from)?)
+        };
+        let offset = rtc_unixtime - system_time.as_secs() as i64;
+        // alarms in the past are rejected or never fire, so they are moved to
+        // the next second instead
+        let wakeup_unixtime = (secs(target_time)? + offset).max(rtc_unixtime + 1);
+        let wakeup_time = self.rtc_time_at(wakeup_unixtime)?;
         RtcAlarm::enable(&self.dev, wakeup_time)
     }
 }
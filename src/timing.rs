@@ -0,0 +1,76 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Durations of the individual stages of a single render iteration
+#[derive(Debug, Default)]
+pub struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Timings::default()
+    }
+
+    pub fn measure<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(stage, start.elapsed());
+        result
+    }
+
+    pub fn record(&mut self, stage: &'static str, duration: Duration) {
+        match self.stages.iter_mut().find(|(s, _)| *s == stage) {
+            Some((_, total)) => *total += duration,
+            None => self.stages.push((stage, duration)),
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, d)| *d).sum()
+    }
+
+    // Formats the timings as a single line JSON object with durations in milliseconds
+    pub fn to_json(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut json = format!("{{\"timestamp\":{}", timestamp);
+        for (stage, duration) in &self.stages {
+            json.push_str(&format!(",\"{}_ms\":{:.3}", stage, duration.as_secs_f64() * 1000.));
+        }
+        json.push_str(&format!(",\"total_ms\":{:.3}}}", self.total().as_secs_f64() * 1000.));
+        json
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stage, duration) in &self.stages {
+            write!(f, "{}={:?} ", stage, duration)?;
+        }
+        write!(f, "total={:?}", self.total())
+    }
+}
+
+// Appends the timings of each iteration as JSON lines to a file
+#[derive(Debug)]
+pub struct TimingLog {
+    file: File,
+}
+
+impl TimingLog {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TimingLog { file })
+    }
+
+    pub fn write(&mut self, timings: &Timings) -> Result<(), io::Error> {
+        writeln!(self.file, "{}", timings.to_json())
+    }
+}
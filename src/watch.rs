@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to set up inotify")]
+    Init(#[source] nix::Error),
+    #[error("Failed to watch {0:?}")]
+    Watch(PathBuf, #[source] nix::Error),
+    #[error("Failed to read inotify events")]
+    Read(#[source] nix::Error),
+}
+
+// Watches files and directories for modifications. Files are watched via their
+// parent directory, as editors often replace files instead of modifying them
+pub struct Watcher {
+    inotify: Inotify,
+    // watched directories, and the file names to filter for (none for any file)
+    dirs: HashMap<WatchDescriptor, (PathBuf, Option<HashSet<OsString>>)>,
+    ignored: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    // Delay after the first change to coalesce multiple writes into one refresh
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+
+    pub fn new() -> Result<Self, Error> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(Error::Init)?;
+        Ok(Watcher {
+            inotify,
+            dirs: HashMap::new(),
+            ignored: HashSet::new(),
+        })
+    }
+
+    fn add_watch(&mut self, dir: &Path) -> Result<WatchDescriptor, Error> {
+        let flags = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE;
+        self.inotify
+            .add_watch(dir, flags)
+            .map_err(|err| Error::Watch(dir.to_path_buf(), err))
+    }
+
+    pub fn file(&mut self, path: &Path) -> Result<&mut Self, Error> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(self);
+        };
+
+        debug!("Watching {:?} for changes", path);
+        let wd = self.add_watch(dir)?;
+        let entry = self
+            .dirs
+            .entry(wd)
+            .or_insert_with(|| (dir.to_path_buf(), Some(HashSet::new())));
+        if let Some(names) = &mut entry.1 {
+            names.insert(name.to_os_string());
+        }
+        Ok(self)
+    }
+
+    pub fn dir(&mut self, path: &Path) -> Result<&mut Self, Error> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        debug!("Watching {:?} for changes", path);
+        let wd = self.add_watch(&path)?;
+        self.dirs.insert(wd, (path, None));
+        Ok(self)
+    }
+
+    // Ignores changes of this file, e.g. because it is written by ourselves
+    pub fn ignore(&mut self, path: &Path) -> &mut Self {
+        let path = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => dir.canonicalize().map(|dir| dir.join(name)),
+            _ => path.canonicalize(),
+        };
+        self.ignored.extend(path.ok());
+        self
+    }
+
+    // Reads all pending events and returns true if a watched file has changed
+    pub fn changed(&self) -> Result<bool, Error> {
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => break,
+                Err(err) => return Err(Error::Read(err)),
+            };
+
+            for event in events {
+                let (Some((dir, names)), Some(name)) = (self.dirs.get(&event.wd), &event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if names.as_ref().is_some_and(|names| !names.contains(name)) || self.ignored.contains(&path) {
+                    continue;
+                }
+                debug!("Detected change of {:?}", path);
+                changed = true;
+            }
+
+            if changed {
+                // wait for any writes following the first one
+                thread::sleep(Self::DEBOUNCE);
+            }
+        }
+
+        Ok(changed)
+    }
+
+    // Blocks until a watched file has changed
+    pub fn wait(&self) -> Result<(), Error> {
+        let mut pollfd = [PollFd::new(self.as_raw_fd(), PollFlags::POLLIN)];
+        loop {
+            poll(&mut pollfd, -1).map_err(Error::Read)?;
+            if self.changed()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl AsRawFd for Watcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}